@@ -0,0 +1,537 @@
+//! Cache of rendered practicals, so that regenerating a record only renders
+//! the entries that changed.
+//!
+//! Each practical is stored as the `document.xml` body its blocks render to,
+//! with its pictures, in a folder named after a hash of everything the
+//! rendering depends on: the entry itself, the sections of the config that
+//! lay it out, the files it refers to and the version of create-docx.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::entry::Entry;
+use crate::error::Error;
+use crate::utilities::{Block, DocumentConfig};
+
+/// Rendered practicals stored in a folder, usually `.create-docx-cache` next
+/// to `format.toml`.
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    /// The practicals this build took from the cache or stored, in order.
+    uses: Vec<Use>,
+}
+
+/// A practical this build took from the cache or stored.
+#[derive(Debug)]
+struct Use {
+    key: String,
+    index: usize,
+    outcome: Outcome,
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Reused,
+    Rebuilt,
+    /// Rendered again but could not be stored, and why.
+    Failed(String),
+}
+
+/// What a build took from the cache.
+#[derive(Debug, Default, Clone)]
+pub struct CacheReport {
+    /// Indexes of the practicals that were reused, in order.
+    pub reused: Vec<usize>,
+    /// Indexes of the practicals that were rendered again, in order.
+    pub rebuilt: Vec<usize>,
+    /// Indexes of the practicals that could not be cached, and why.
+    pub errors: Vec<(usize, String)>,
+}
+
+/// A practical rendered by an earlier build.
+#[derive(Debug, Clone)]
+pub struct CachedPractical {
+    /// The `document.xml` body of the practical.
+    xml: String,
+    /// Ids and PNG data of the pictures the body refers to.
+    images: Vec<(String, Vec<u8>)>,
+    /// Descriptions of the pictures, in order.
+    pub(crate) descriptions: Vec<String>,
+}
+
+/// Contents of `practical.json` in a cached practical's folder.
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    images: Vec<String>,
+    descriptions: Vec<String>,
+}
+
+impl Cache {
+    /// Open the cache in `dir`, creating the folder if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|error| Error::Cache {
+            path: dir.clone(),
+            error,
+        })?;
+        Ok(Cache {
+            dir,
+            uses: Vec::new(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn report(&self) -> CacheReport {
+        let mut report = CacheReport::default();
+        for used in &self.uses {
+            match &used.outcome {
+                Outcome::Reused => report.reused.push(used.index),
+                Outcome::Rebuilt => report.rebuilt.push(used.index),
+                Outcome::Failed(error) => {
+                    report.rebuilt.push(used.index);
+                    report.errors.push((used.index, error.clone()));
+                }
+            }
+        }
+        // Entries are rendered as they arrive, which may be out of order
+        report.reused.sort_unstable();
+        report.rebuilt.sort_unstable();
+        report.errors.sort_by_key(|(index, _)| *index);
+        report
+    }
+
+    /// Delete every cached practical.
+    pub fn clear(&self) -> Result<usize, Error> {
+        self.remove_where(|_| true)
+    }
+
+    /// Delete the cached practicals this build did not use.
+    pub fn prune(&self) -> Result<usize, Error> {
+        self.remove_where(|key| !self.uses.iter().any(|used| used.key == key))
+    }
+
+    fn remove_where(&self, remove: impl Fn(&str) -> bool) -> Result<usize, Error> {
+        let error = |error| Error::Cache {
+            path: self.dir.clone(),
+            error,
+        };
+        let mut removed = 0;
+        for folder in std::fs::read_dir(&self.dir).map_err(error)? {
+            let path = folder.map_err(error)?.path();
+            let Some(key) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            // Leave alone anything the cache did not write
+            let cached = key.len() == 32 && key.bytes().all(|byte| byte.is_ascii_hexdigit());
+            if cached && path.is_dir() && remove(key) {
+                std::fs::remove_dir_all(&path).map_err(error)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Practical `index`, stored under `key` by an earlier build.
+    pub fn get(&mut self, key: &str, index: usize) -> Option<CachedPractical> {
+        let practical = read(&self.dir.join(key))?;
+        self.record(key, index, Outcome::Reused);
+        Some(practical)
+    }
+
+    /// Store the blocks practical `index` rendered to under `key`.
+    pub fn put(&mut self, key: &str, index: usize, blocks: &[Block]) {
+        let folder = self.dir.join(key);
+        let outcome = match write(&folder, blocks) {
+            Ok(()) => Outcome::Rebuilt,
+            Err(error) => {
+                // Don't leave a half-written practical behind
+                let _ = std::fs::remove_dir_all(&folder);
+                Outcome::Failed(error.to_string())
+            }
+        };
+        self.record(key, index, outcome);
+    }
+
+    /// Leave the practical under `key` out of the report and the
+    /// practicals this build used, when a practical that arrived later
+    /// replaced it.
+    pub(crate) fn forget(&mut self, key: &str) {
+        self.uses.retain(|used| used.key != key);
+    }
+
+    fn record(&mut self, key: &str, index: usize, outcome: Outcome) {
+        self.uses.push(Use {
+            key: key.to_string(),
+            index,
+            outcome,
+        });
+    }
+}
+
+/// Hash of everything the blocks of `entry` depend on when laid out in
+/// `text_width` twips.
+pub fn key(config: &DocumentConfig, entry: &Entry, text_width: usize) -> String {
+    versioned_key(env!("CARGO_PKG_VERSION"), config, entry, text_width)
+}
+
+/// [`key`] as create-docx `version` computes it.
+fn versioned_key(
+    version: &str,
+    config: &DocumentConfig,
+    entry: &Entry,
+    text_width: usize,
+) -> String {
+    let mut hash = Fnv::new();
+    hash.write(version.as_bytes());
+    hash.write(&text_width.to_le_bytes());
+    for section in [
+        serde_json::to_string(&config.header),
+        serde_json::to_string(&config.question),
+        serde_json::to_string(&config.solution),
+        serde_json::to_string(&config.output),
+        serde_json::to_string(&config.footer),
+        serde_json::to_string(&config.images),
+        serde_json::to_string(&config.headings),
+        serde_json::to_string(&config.units),
+        serde_json::to_string(&config.numbering),
+        // The size of code listings comes from these
+        serde_json::to_string(&config.defaults),
+        serde_json::to_string(&config.styles),
+        serde_json::to_string(entry),
+    ] {
+        hash.write(section.unwrap_or_default().as_bytes());
+    }
+
+    // Files read while rendering
+    let runs = entry.runs();
+    let expected = runs
+        .iter()
+        .filter_map(|run| run.expected.as_ref()?.file.as_deref());
+    let images = entry
+        .question_images
+        .iter()
+        .chain(runs.iter().flat_map(|run| &run.images))
+        .map(|image| image.path.as_path());
+    let screenshot = &config.output.screenshot;
+    let fonts = [&screenshot.font, &screenshot.bold_font]
+        .into_iter()
+        .flatten()
+        .map(PathBuf::as_path);
+    for path in expected.chain(images).chain(fonts) {
+        hash.write(path.to_string_lossy().as_bytes());
+        match std::fs::read(path) {
+            Ok(contents) => hash.write(&contents),
+            Err(error) => hash.write(error.to_string().as_bytes()),
+        }
+    }
+
+    format!("{:032x}", hash.finish())
+}
+
+fn read(folder: &Path) -> Option<CachedPractical> {
+    let metadata: Metadata =
+        serde_json::from_slice(&std::fs::read(folder.join("practical.json")).ok()?).ok()?;
+    let xml = std::fs::read_to_string(folder.join("document.xml")).ok()?;
+    let images = metadata
+        .images
+        .into_iter()
+        .map(|id| {
+            let png = std::fs::read(folder.join(format!("{}.png", id))).ok()?;
+            Some((id, png))
+        })
+        .collect::<Option<_>>()?;
+    Some(CachedPractical {
+        xml,
+        images,
+        descriptions: metadata.descriptions,
+    })
+}
+
+fn write(folder: &Path, blocks: &[Block]) -> Result<(), Error> {
+    let mut doc = docx_rs::Docx::new();
+    let mut descriptions = Vec::new();
+    for block in blocks {
+        if let Block::Picture { description, .. } = block {
+            descriptions.push(description.clone());
+        }
+        doc = block.clone().add_to(doc);
+    }
+
+    let xml = doc.build();
+    let document = String::from_utf8_lossy(&xml.document);
+    let body = document
+        .find("<w:body>")
+        .map(|start| start + "<w:body>".len())
+        .zip(document.rfind("<w:sectPr"))
+        .map_or("", |(start, end)| &document[start..end]);
+
+    std::fs::create_dir_all(folder)?;
+    std::fs::write(folder.join("document.xml"), body)?;
+    let mut images = Vec::new();
+    for (id, png) in xml.media {
+        std::fs::write(folder.join(format!("{}.png", id)), png)?;
+        images.push(id);
+    }
+    let metadata = Metadata {
+        images,
+        descriptions,
+    };
+    std::fs::write(
+        folder.join("practical.json"),
+        serde_json::to_string(&metadata).unwrap_or_default(),
+    )?;
+    Ok(())
+}
+
+/// Text of the paragraph standing in for the `position`th cached practical
+/// of a document until its XML is put in place.
+fn marker(position: usize) -> String {
+    format!("\u{27e6}create-docx cached practical {}\u{27e7}", position)
+}
+
+impl CachedPractical {
+    /// The paragraph standing in for the practical: its marker, followed by
+    /// its pictures so that docx-rs packs them.
+    pub(crate) fn placeholder(&self, position: usize) -> docx_rs::Paragraph {
+        let mut paragraph =
+            docx_rs::Paragraph::new().add_run(docx_rs::Run::new().add_text(marker(position)));
+        for (_, png) in &self.images {
+            let mut pic = docx_rs::Pic::new(blank_png());
+            pic.image = png.clone();
+            paragraph = paragraph.add_run(docx_rs::Run::new().add_image(pic));
+        }
+        paragraph
+    }
+}
+
+/// Replace the placeholders of `practicals` in `document` with their XML,
+/// pointing their pictures at the ids docx-rs gave the placeholders'.
+pub(crate) fn splice(document: &[u8], practicals: &[CachedPractical]) -> Vec<u8> {
+    const EMBED: &str = "r:embed=\"";
+    let mut document = String::from_utf8_lossy(document).into_owned();
+    for (position, practical) in practicals.iter().enumerate() {
+        let Some(found) = document.find(&marker(position)) else {
+            continue;
+        };
+        let (Some(start), Some(end)) = (
+            document[..found].rfind("<w:p "),
+            document[found..]
+                .find("</w:p>")
+                .map(|end| found + end + "</w:p>".len()),
+        ) else {
+            continue;
+        };
+
+        let ids: Vec<&str> = document[start..end]
+            .split(EMBED)
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .collect();
+        let mut pieces = practical.xml.split(EMBED);
+        let mut xml = pieces.next().unwrap_or_default().to_string();
+        for piece in pieces {
+            let (old, rest) = piece.split_once('"').unwrap_or((piece, ""));
+            let new = practical
+                .images
+                .iter()
+                .zip(&ids)
+                .find(|((id, _), _)| id == old)
+                .map_or(old, |(_, new)| new);
+            xml.push_str(EMBED);
+            xml.push_str(new);
+            xml.push('"');
+            xml.push_str(rest);
+        }
+        document.replace_range(start..end, &xml);
+    }
+    document.into_bytes()
+}
+
+/// A transparent 1×1 PNG, for docx-rs to decode in place of the cached
+/// pictures, which are already PNG.
+fn blank_png() -> &'static [u8] {
+    static BLANK: OnceLock<Vec<u8>> = OnceLock::new();
+    BLANK.get_or_init(|| {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 1, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&[0; 4]))
+            .expect("a 1×1 PNG always encodes");
+        png
+    })
+}
+
+/// 128-bit FNV-1a, which unlike the standard library's hasher gives the
+/// same hash in every build.
+struct Fnv(u128);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0x6c62272e07bb014262b821756295c58d)
+    }
+
+    /// Hash the length and then the bytes, so that "ab" + "c" and
+    /// "a" + "bc" differ.
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes(&(bytes.len() as u64).to_le_bytes());
+        self.bytes(bytes);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u128;
+            self.0 = self.0.wrapping_mul(0x0000000001000000000000000000013b);
+        }
+    }
+
+    fn finish(&self) -> u128 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::utilities::DocumentBuilder;
+
+    /// A picture `width` pixels wide. docx-rs stores identical pictures
+    /// once, so each picture of a test gets its own width.
+    fn picture(width: u32, description: &str) -> Block {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&vec![0; 4 * width as usize]))
+            .unwrap();
+        let pic = docx_rs::Pic::new(&png);
+        Block::Picture {
+            paragraph: Box::new(
+                docx_rs::Paragraph::new().add_run(docx_rs::Run::new().add_image(pic)),
+            ),
+            description: description.to_string(),
+        }
+    }
+
+    fn text(text: &str) -> Block {
+        docx_rs::Paragraph::new()
+            .add_run(docx_rs::Run::new().add_text(text))
+            .into()
+    }
+
+    /// The ids pictures in `xml` refer to.
+    fn embeds(xml: &str) -> Vec<&str> {
+        xml.split("r:embed=\"")
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .collect()
+    }
+
+    #[test]
+    fn cached_practical_is_spliced_with_its_pictures() {
+        let dir =
+            std::env::temp_dir().join(format!("create-docx-cache-test-{}", std::process::id()));
+        let mut cache = Cache::open(&dir).unwrap();
+        cache.put(
+            "key",
+            0,
+            &[
+                text("cached text"),
+                picture(1, "first"),
+                picture(2, "second"),
+            ],
+        );
+        let cached = cache.get("key", 0).unwrap();
+        assert_eq!(cached.descriptions, ["first", "second"]);
+        assert_eq!(cache.report().reused, [0]);
+        assert_eq!(cache.report().rebuilt, [0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // A picture rendered in this build comes first, so docx-rs numbers
+        // the cached ones differently from the build that stored them
+        let doc = picture(3, "fresh")
+            .add_to(docx_rs::Docx::new())
+            .add_paragraph(cached.placeholder(0));
+        let xml = doc.build();
+        let placeholder = String::from_utf8(xml.document.clone()).unwrap();
+        assert!(placeholder.contains(&marker(0)));
+
+        let document = String::from_utf8(splice(&xml.document, &[cached])).unwrap();
+        assert!(!document.contains(&marker(0)));
+        assert!(document.contains(">cached text<"));
+        let rels = String::from_utf8(xml.document_rels).unwrap();
+        let ids = embeds(&document);
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids.iter().collect::<BTreeSet<_>>().len(), 3);
+        for id in ids {
+            assert!(rels.contains(&format!("Id=\"{}\"", id)), "no image {}", id);
+        }
+    }
+
+    /// A builder that added `entries` to a document, using the cache in
+    /// `dir`.
+    fn build<'a>(dir: &Path, config: &'a DocumentConfig, entries: &[Entry]) -> DocumentBuilder<'a> {
+        let mut builder = DocumentBuilder::new(config).with_cache(Cache::open(dir).unwrap());
+        for entry in entries {
+            builder.add_entry(entry);
+        }
+        builder
+    }
+
+    #[test]
+    fn unchanged_practicals_are_reused_and_the_rest_pruned() {
+        let dir =
+            std::env::temp_dir().join(format!("create-docx-reuse-test-{}", std::process::id()));
+        let entries = [
+            Entry::new(0, "Sum an array", ".py", "print(1)"),
+            Entry::new(1, "Reverse an array", ".py", "print(2)"),
+        ];
+        let mut config = DocumentConfig::default();
+        let report = build(&dir, &config, &entries).cache().unwrap().report();
+        assert_eq!(report.rebuilt, [0, 1]);
+        let report = build(&dir, &config, &entries).cache().unwrap().report();
+        assert_eq!(report.reused, [0, 1]);
+        assert!(report.rebuilt.is_empty());
+
+        // Any section laying out the practicals renders them again
+        config.headings.enabled = false;
+        let report = build(&dir, &config, &entries).cache().unwrap().report();
+        assert!(report.reused.is_empty());
+        assert_eq!(report.rebuilt, [0, 1]);
+
+        // So does another version of create-docx
+        let key = |version| versioned_key(version, &config, &entries[0], 9000);
+        assert_eq!(
+            key(env!("CARGO_PKG_VERSION")),
+            super::key(&config, &entries[0], 9000)
+        );
+        assert_ne!(key("0.0.0"), key(env!("CARGO_PKG_VERSION")));
+
+        // Pruning keeps only what the build used, and nothing the cache
+        // did not write
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        let builder = build(&dir, &config, &entries);
+        assert_eq!(builder.cache().unwrap().report().reused, [0, 1]);
+        assert_eq!(builder.cache().unwrap().prune().unwrap(), 2);
+        let mut left: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|folder| folder.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        let mut kept: Vec<_> = builder.keys().into_values().collect();
+        kept.push("notes.txt".to_string());
+        kept.sort();
+        assert_eq!(left, kept);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+//! Comparison of a program's output with the output the instructor expects.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
+/// How outputs are normalized before they are compared.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Normalization {
+    /// Ignore spaces and tabs at the end of lines.
+    #[serde(default = "default_true")]
+    pub trim_trailing_whitespace: bool,
+    /// Treat any run of spaces and tabs as a single space and ignore leading
+    /// whitespace.
+    #[serde(default)]
+    pub collapse_whitespace: bool,
+    /// Compare letters case-insensitively.
+    #[serde(default)]
+    pub ignore_case: bool,
+    /// Ignore empty lines.
+    #[serde(default)]
+    pub ignore_blank_lines: bool,
+}
+
+/// One line of a diff between the expected and the actual output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    /// Present in both outputs.
+    Same(String),
+    /// Only in the expected output.
+    Expected(String),
+    /// Only in the actual output.
+    Actual(String),
+}
+
+/// Result of comparing the output of one run.
+#[derive(Debug, Clone)]
+pub struct CheckOutcome {
+    pub passed: bool,
+    /// Line-level diff of the normalized outputs, empty when they match.
+    pub diff: Vec<DiffLine>,
+    /// Why the comparison could not be made, e.g. a missing expected file.
+    pub error: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization {
+            trim_trailing_whitespace: default_true(),
+            collapse_whitespace: false,
+            ignore_case: false,
+            ignore_blank_lines: false,
+        }
+    }
+}
+
+impl Normalization {
+    /// The lines of `text` as they are compared. Trailing empty lines are
+    /// always ignored.
+    pub fn lines(&self, text: &str) -> Vec<String> {
+        let mut lines: Vec<String> = text
+            .lines()
+            .map(|line| {
+                let mut line = line.to_string();
+                if self.collapse_whitespace {
+                    line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+                } else if self.trim_trailing_whitespace {
+                    line.truncate(line.trim_end().len());
+                }
+                if self.ignore_case {
+                    line = line.to_lowercase();
+                }
+                line
+            })
+            .filter(|line| !self.ignore_blank_lines || !line.trim().is_empty())
+            .collect();
+
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+}
+
+/// Compare `actual` with `expected` after normalizing both.
+///
+/// ```
+/// use helpers::check::{compare, DiffLine, Normalization};
+///
+/// let outcome = compare("Sum = 5\n", "sum = 5  \n", &Normalization {
+///     ignore_case: true,
+///     ..Default::default()
+/// });
+/// assert!(outcome.passed);
+///
+/// let outcome = compare("1\n2\n", "1\n3\n", &Normalization::default());
+/// assert_eq!(outcome.diff[1], DiffLine::Expected("2".to_string()));
+/// assert_eq!(outcome.diff[2], DiffLine::Actual("3".to_string()));
+/// ```
+pub fn compare(expected: &str, actual: &str, normalization: &Normalization) -> CheckOutcome {
+    let expected = normalization.lines(expected);
+    let actual = normalization.lines(actual);
+    if expected == actual {
+        return CheckOutcome {
+            passed: true,
+            diff: Vec::new(),
+            error: None,
+        };
+    }
+
+    let expected: Vec<&str> = expected.iter().map(String::as_str).collect();
+    let actual: Vec<&str> = actual.iter().map(String::as_str).collect();
+    let diff = TextDiff::from_slices(&expected, &actual)
+        .iter_all_changes()
+        .map(|change| {
+            let line = change.value().to_string();
+            match change.tag() {
+                ChangeTag::Equal => DiffLine::Same(line),
+                ChangeTag::Delete => DiffLine::Expected(line),
+                ChangeTag::Insert => DiffLine::Actual(line),
+            }
+        })
+        .collect();
+
+    CheckOutcome {
+        passed: false,
+        diff,
+        error: None,
+    }
+}
+
+impl CheckOutcome {
+    /// A failed check that could not be compared.
+    pub fn error(error: impl Into<String>) -> Self {
+        CheckOutcome {
+            passed: false,
+            diff: Vec::new(),
+            error: Some(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passes(expected: &str, actual: &str, normalization: &Normalization) -> bool {
+        compare(expected, actual, normalization).passed
+    }
+
+    #[test]
+    fn trailing_whitespace_and_newlines_are_ignored_by_default() {
+        let default = Normalization::default();
+        assert!(passes("a\nb", "a  \nb\t\n\n\n", &default));
+        assert!(passes("a\n\n", "a", &default));
+        // Blank lines between others still count
+        assert!(!passes("a\nb", "a\n\nb", &default));
+
+        let exact = Normalization {
+            trim_trailing_whitespace: false,
+            ..Default::default()
+        };
+        assert!(!passes("a\nb", "a  \nb", &exact));
+        assert!(passes("a\nb", "a\nb\n\n", &exact));
+    }
+
+    #[test]
+    fn whitespace_collapses_into_single_spaces() {
+        let collapse = Normalization {
+            collapse_whitespace: true,
+            ..Default::default()
+        };
+        assert!(passes("Sum = 5", "  Sum\t=   5 ", &collapse));
+        assert!(!passes("Sum = 5", "Sum=5", &collapse));
+        assert!(!passes("Sum = 5", "  Sum = 5", &Normalization::default()));
+    }
+
+    #[test]
+    fn case_and_blank_lines_are_ignored_when_asked() {
+        let default = Normalization::default();
+        assert!(!passes("Sum = 5", "SUM = 5", &default));
+        let ignore_case = Normalization {
+            ignore_case: true,
+            ..Default::default()
+        };
+        assert!(passes("Sum = 5", "SUM = 5", &ignore_case));
+
+        let ignore_blank_lines = Normalization {
+            ignore_blank_lines: true,
+            ..Default::default()
+        };
+        assert!(passes("a\nb", "\na\n  \n\nb", &ignore_blank_lines));
+    }
+
+    #[test]
+    fn failed_comparison_diffs_the_normalized_lines() {
+        let outcome = compare(
+            "Enter n:\n1\n2\n3\n",
+            "Enter n:  \n1\n3\n4\n",
+            &Normalization::default(),
+        );
+        assert!(!outcome.passed);
+        assert!(outcome.error.is_none());
+        assert_eq!(
+            outcome.diff,
+            [
+                DiffLine::Same("Enter n:".to_string()),
+                DiffLine::Same("1".to_string()),
+                DiffLine::Expected("2".to_string()),
+                DiffLine::Same("3".to_string()),
+                DiffLine::Actual("4".to_string()),
+            ]
+        );
+
+        let outcome = compare("a", "a", &Normalization::default());
+        assert!(outcome.passed && outcome.diff.is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "create-docx",
+    version,
+    about = "Turn pfcreator entries into a .docx practical record",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub build: BuildArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Build the record (the default when no subcommand is given)
+    Build(BuildArgs),
+    /// Rebuild the record whenever format.toml, the entries, questions.txt
+    /// or a source file changes, until interrupted
+    Watch(WatchArgs),
+    /// Write a format.toml reproducing the layout of a sample record, such
+    /// as one handed out in Word
+    InferConfig(InferArgs),
+    /// Print the JSON Schema of format.toml
+    Schema,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Word document
+    #[default]
+    Docx,
+    /// JSON dump of the document model, useful for debugging a format.toml
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct BuildArgs {
+    /// Folder holding format.toml and output.json; relative paths resolve
+    /// against the working directory
+    pub folder: Option<PathBuf>,
+
+    /// Path of the format config [default: <FOLDER>/format.toml]
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Path of the entries file, a JSON array or one JSON entry per line;
+    /// `-` reads from stdin [default: <FOLDER>/output.json]
+    #[arg(short, long, value_name = "FILE")]
+    pub input: Option<PathBuf>,
+
+    /// Path of the generated document; may use placeholders such as
+    /// `{roll_no}_{course}.docx` [default: <FOLDER>/labfile.docx]
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<String>,
+
+    /// Format of the generated document
+    #[arg(short, long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+
+    /// Set a placeholder value for the output name, overriding [variables]
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, String)>,
+
+    /// Keep output.json and the output_rtf folder instead of deleting them
+    /// after the document is written
+    #[arg(long)]
+    pub keep_intermediate: bool,
+
+    /// Render every entry again, without reading or writing the cache
+    #[arg(long, conflicts_with_all = ["cache_dir", "clear_cache", "prune_cache"])]
+    pub no_cache: bool,
+
+    /// Folder of the cache of rendered entries
+    /// [default: <FOLDER>/.create-docx-cache]
+    #[arg(long, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Empty the cache before building
+    #[arg(long)]
+    pub clear_cache: bool,
+
+    /// Delete cached entries this build did not use, e.g. of removed or
+    /// edited practicals
+    #[arg(long)]
+    pub prune_cache: bool,
+
+    /// Update the existing document instead of overwriting it: only the
+    /// practicals that changed are rewritten and new ones added, and
+    /// everything else, such as a teacher's annotations, is kept
+    #[arg(long)]
+    pub update: bool,
+
+    /// Exit with an error when any output differs from its expected output
+    /// or a screenshot cannot be drawn; the document is still written
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Only print errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print details about every entry
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    /// Command that runs the programs again and rewrites the entries when
+    /// questions.txt or a source file changes, e.g. `pfcreator cpp .`; runs
+    /// in <FOLDER>
+    #[arg(long, value_name = "COMMAND")]
+    pub regenerate: Option<String>,
+
+    /// How long to wait for changes to settle before rebuilding
+    #[arg(long, value_name = "MS", default_value_t = 300)]
+    pub debounce: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct InferArgs {
+    /// The sample record, a .docx file
+    #[arg(value_name = "SAMPLE")]
+    pub sample: PathBuf,
+
+    /// Where to write the config; printed when not given
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+}
+
+fn parse_var(raw: &str) -> Result<(String, String), String> {
+    raw.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", raw))
+}
+
+impl BuildArgs {
+    /// The input folder, resolved against the working directory.
+    pub fn folder(&self) -> Result<PathBuf, String> {
+        let folder = self
+            .folder
+            .as_deref()
+            .ok_or("missing <FOLDER> argument, see --help")?;
+        absolute(folder)
+    }
+
+    pub fn config_path(&self, folder: &Path) -> Result<PathBuf, String> {
+        match &self.config {
+            Some(path) => absolute(path),
+            None => Ok(folder.join("format.toml")),
+        }
+    }
+
+    pub fn cache_dir(&self, folder: &Path) -> Result<PathBuf, String> {
+        match &self.cache_dir {
+            Some(path) => absolute(path),
+            None => Ok(folder.join(".create-docx-cache")),
+        }
+    }
+
+    pub fn reads_stdin(&self) -> bool {
+        self.input.as_deref() == Some(Path::new("-"))
+    }
+
+    pub fn input_path(&self, folder: &Path) -> Result<PathBuf, String> {
+        match &self.input {
+            Some(path) => absolute(path),
+            None => Ok(folder.join("output.json")),
+        }
+    }
+
+    /// Where to write the document. Placeholders in `--output` are filled
+    /// from `variables`, `--var` and `{folder}` (the folder name).
+    pub fn output_path(
+        &self,
+        folder: &Path,
+        variables: &BTreeMap<String, String>,
+    ) -> Result<PathBuf, String> {
+        let Some(template) = &self.output else {
+            let name = match self.format {
+                OutputFormat::Docx => "labfile.docx",
+                OutputFormat::Json => "labfile.json",
+            };
+            return Ok(folder.join(name));
+        };
+
+        let mut values = variables.clone();
+        if let Some(name) = folder.file_name() {
+            values.insert("folder".to_string(), name.to_string_lossy().into_owned());
+        }
+        values.extend(self.vars.iter().cloned());
+
+        let mut name = template.clone();
+        for (key, value) in &values {
+            name = name.replace(&format!("{{{}}}", key), value);
+        }
+        if let Some(start) = name.find('{') {
+            let end = name[start..]
+                .find('}')
+                .map_or(name.len(), |end| start + end + 1);
+            return Err(format!(
+                "no value for placeholder {} in --output, set it in [variables] or with --var",
+                &name[start..end]
+            ));
+        }
+
+        absolute(Path::new(&name))
+    }
+}
+
+fn absolute(path: &Path) -> Result<PathBuf, String> {
+    std::path::absolute(path).map_err(|e| format!("Failed to resolve {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(args: &[&str]) -> BuildArgs {
+        let cli = Cli::try_parse_from(["create-docx"].iter().chain(args)).unwrap();
+        match cli.command {
+            Some(Command::Build(build)) => build,
+            None => cli.build,
+            Some(command) => panic!("not a build: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn output_placeholders_are_filled_from_variables_vars_and_the_folder() {
+        let folder = Path::new("/records/cs101");
+        let variables = BTreeMap::from([
+            ("roll_no".to_string(), "41".to_string()),
+            ("course".to_string(), "C".to_string()),
+        ]);
+        let args = build(&[
+            "cs101",
+            "--output",
+            "out/{roll_no}_{course}_{folder}.docx",
+            "--var",
+            "course=DSA",
+        ]);
+        let path = args.output_path(folder, &variables).unwrap();
+        assert!(path.is_absolute());
+        assert!(path.ends_with("out/41_DSA_cs101.docx"));
+
+        let args = build(&["cs101", "-o", "{roll_no}_{name}.docx"]);
+        let error = args.output_path(folder, &variables).unwrap_err();
+        assert!(error.contains("{name}"), "{}", error);
+
+        let args = build(&["cs101"]);
+        assert_eq!(
+            args.output_path(folder, &variables).unwrap(),
+            folder.join("labfile.docx")
+        );
+        let args = build(&["cs101", "--format", "json"]);
+        assert_eq!(
+            args.output_path(folder, &variables).unwrap(),
+            folder.join("labfile.json")
+        );
+    }
+
+    #[test]
+    fn vars_split_at_the_first_equals_sign() {
+        let args = build(&["cs101", "--var", " title =a=b", "--var", "empty="]);
+        assert_eq!(
+            args.vars,
+            [
+                ("title".to_string(), "a=b".to_string()),
+                ("empty".to_string(), String::new()),
+            ]
+        );
+
+        let error = Cli::try_parse_from(["create-docx", "cs101", "--var", "title"]).unwrap_err();
+        assert!(
+            error.to_string().contains("expected KEY=VALUE"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn a_dash_reads_the_entries_from_stdin() {
+        assert!(build(&["cs101", "--input", "-"]).reads_stdin());
+        assert!(build(&["build", "cs101", "-i", "-"]).reads_stdin());
+        assert!(!build(&["cs101", "--input", "entries.json"]).reads_stdin());
+        assert!(!build(&["cs101"]).reads_stdin());
+    }
+}
@@ -0,0 +1,1092 @@
+//! The interchange format between pfcreator (or any other producer) and
+//! create-docx. A file holds a JSON array of entries, one per practical:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "index": 0,
+//!   "question": "Print the sum of two numbers",
+//!   "extension": ".cpp",
+//!   "code": {
+//!     "source": "int main() { ... }",
+//!     "highlighted": { "type": "rtf", "data": "{\\rtf1 ...}" }
+//!   },
+//!   "files": [],
+//!   "output": { "type": "ansi", "data": "Sum = 5\n" },
+//!   "runs": [],
+//!   "stderr": "",
+//!   "exit_status": 0,
+//!   "duration_ms": 12
+//! }
+//! ```
+//!
+//! The same entries may also be sent as newline delimited JSON, one entry
+//! object per line, see [`read_entries`].
+//!
+//! Entries without a `version` field are read as the legacy format pfcreator
+//! wrote before versioning (see [`ZigOutput`]).
+//!
+//! The question text may end with directives that are removed from the
+//! printed question, see [`Entry::take_directives`].
+
+use rtf_parser::RtfDocument;
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use crate::check::{self, CheckOutcome, Normalization};
+use crate::error::EntryError;
+use crate::numbering::NumberingFormat;
+
+/// Newest entry format version this build understands.
+pub const ENTRY_VERSION: u32 = 1;
+
+/// How the text in a [`Content`] is encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    /// Plain text, printed as is.
+    #[default]
+    Text,
+    /// Terminal output that may contain ANSI escape sequences.
+    Ansi,
+    /// An RTF document.
+    Rtf,
+}
+
+/// A block of text tagged with its encoding.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Content {
+    #[serde(rename = "type", default)]
+    pub content_type: ContentType,
+    pub data: String,
+}
+
+/// Source code of a practical.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Code {
+    /// The source exactly as written.
+    pub source: String,
+    /// Optional syntax highlighted rendering of `source`, e.g. pygmentize RTF.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlighted: Option<Content>,
+}
+
+/// One source file of a solution split across several files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SourceFile {
+    /// File name shown in the caption, e.g. "stack.h".
+    pub filename: String,
+    /// Language of the file, e.g. "cpp". Defaults to the file extension.
+    #[serde(default)]
+    pub language: String,
+    /// The source exactly as written.
+    pub content: String,
+    /// Optional syntax highlighted rendering of `content`, e.g. pygmentize RTF.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlighted: Option<Content>,
+}
+
+/// One execution of the program on a given input.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProgramRun {
+    /// Short description of the case, e.g. "edge case".
+    #[serde(default)]
+    pub label: String,
+    /// What was fed to the program on stdin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    /// What the program printed on stdout. Either this or `transcript` is
+    /// required.
+    #[serde(default)]
+    pub output: Content,
+    /// The terminal session in order: prompts printed by the program
+    /// interleaved with what the user typed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transcript: Vec<TranscriptChunk>,
+    /// What the program printed on stderr, when captured separately.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    /// Exit status of the program, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<i32>,
+    /// Number of the signal that terminated the program, e.g. 11 for SIGSEGV.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// Wall-clock time the program ran for, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// What the program should have printed for this input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<ExpectedOutput>,
+    /// Image files the program wrote, e.g. plots, printed below its output.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageFile>,
+}
+
+/// An image file embedded in the record.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageFile {
+    /// Path of the PNG, JPEG, GIF, BMP or TIFF file, relative to the record
+    /// folder.
+    pub path: PathBuf,
+    /// Caption printed below the image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    /// Description read by screen readers instead of the image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt: Option<String>,
+}
+
+/// Output a program is expected to print, given inline or as a file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExpectedOutput {
+    /// The expected output itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// File holding the expected output, relative to the record folder.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// Which side of the terminal a [`TranscriptChunk`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    /// Typed by the user.
+    Input,
+    /// Printed by the program, may contain ANSI escape sequences.
+    Output,
+}
+
+/// A piece of an interactive session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptChunk {
+    pub stream: Stream,
+    pub text: String,
+}
+
+/// One practical: its question, code and captured output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Entry format version, currently [`ENTRY_VERSION`].
+    pub version: u32,
+    /// Position of the practical in the record, starting at 0.
+    pub index: usize,
+    pub question: String,
+    /// Extension of the source file including the dot, e.g. ".cpp".
+    pub extension: String,
+    /// Code of a single-file solution. Either this or `files` is required.
+    #[serde(default)]
+    pub code: Code,
+    /// Source files of a multi-file solution, in the order they were listed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<SourceFile>,
+    /// What the program printed on stdout, for a single run. Either this or
+    /// `runs` is required.
+    #[serde(default)]
+    pub output: Content,
+    /// Interleaved input and output of an interactive single run, used
+    /// instead of `output`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transcript: Vec<TranscriptChunk>,
+    /// Several runs of the program on distinct inputs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<ProgramRun>,
+    /// What the program printed on stderr, when captured separately.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    /// Exit status of the program, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<i32>,
+    /// Number of the signal that terminated the program, e.g. 11 for SIGSEGV.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    /// Wall-clock time the program ran for, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// What the program should have printed, for a single run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<ExpectedOutput>,
+    /// Images shown below the question, e.g. a diagram.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub question_images: Vec<ImageFile>,
+    /// Image files a single-run program wrote, printed below its output.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageFile>,
+    /// Unit of the syllabus the practical belongs to. The practicals after
+    /// it that have none belong to the same unit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<Unit>,
+    /// How the number of the practical is printed, from the `[numbering]`
+    /// of the record it is added to.
+    #[serde(skip)]
+    pub(crate) numbering: NumberingFormat,
+}
+
+/// A unit, or chapter, of the syllabus that groups practicals.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unit {
+    /// Number of the unit, starting at 1. When left out, it follows on from
+    /// the unit before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub number: Option<usize>,
+    /// Title of the unit, e.g. "Arrays".
+    pub title: String,
+    /// Position of the practical in the unit, starting at 0. When left out,
+    /// it is counted from the practicals before it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+/// The unversioned format pfcreator wrote before [`Entry`] existed.
+/// `output_rtf` holds either RTF or raw terminal output.
+#[derive(Deserialize)]
+pub struct ZigOutput {
+    question: String,
+    index: usize,
+    extension: String,
+    code: String,
+    #[serde(default)]
+    code_rtf: Option<String>,
+    output_rtf: String,
+}
+
+impl From<ZigOutput> for Entry {
+    fn from(legacy: ZigOutput) -> Self {
+        Entry {
+            version: ENTRY_VERSION,
+            index: legacy.index,
+            question: legacy.question,
+            extension: legacy.extension,
+            code: Code {
+                source: legacy.code,
+                highlighted: legacy.code_rtf.map(|data| Content {
+                    content_type: ContentType::Rtf,
+                    data,
+                }),
+            },
+            files: Vec::new(),
+            output: Content {
+                content_type: if legacy.output_rtf.trim_start().starts_with("{\\rtf") {
+                    ContentType::Rtf
+                } else {
+                    ContentType::Ansi
+                },
+                data: legacy.output_rtf,
+            },
+            transcript: Vec::new(),
+            runs: Vec::new(),
+            stderr: None,
+            exit_status: None,
+            signal: None,
+            duration_ms: None,
+            expected: None,
+            question_images: Vec::new(),
+            images: Vec::new(),
+            unit: None,
+            numbering: NumberingFormat::default(),
+        }
+    }
+}
+
+impl Entry {
+    /// An entry with the given source code and no output yet.
+    pub fn new(
+        index: usize,
+        question: impl Into<String>,
+        extension: impl Into<String>,
+        source: impl Into<String>,
+    ) -> Self {
+        Entry {
+            version: ENTRY_VERSION,
+            index,
+            question: question.into(),
+            extension: extension.into(),
+            code: Code {
+                source: source.into(),
+                highlighted: None,
+            },
+            files: Vec::new(),
+            output: Content::default(),
+            transcript: Vec::new(),
+            runs: Vec::new(),
+            stderr: None,
+            exit_status: None,
+            signal: None,
+            duration_ms: None,
+            expected: None,
+            question_images: Vec::new(),
+            images: Vec::new(),
+            unit: None,
+            numbering: NumberingFormat::default(),
+        }
+    }
+
+    /// Attach a syntax highlighted rendering of the source, e.g. RTF.
+    pub fn with_highlighted_code(mut self, highlighted: Content) -> Self {
+        self.code.highlighted = Some(highlighted);
+        self
+    }
+
+    /// Add a file to a multi-file solution.
+    pub fn with_file(mut self, file: SourceFile) -> Self {
+        self.files.push(file);
+        self
+    }
+
+    pub fn with_output(mut self, output: Content) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Add a run of the program on a distinct input.
+    pub fn with_run(mut self, run: ProgramRun) -> Self {
+        self.runs.push(run);
+        self
+    }
+
+    pub fn with_stderr(mut self, stderr: impl Into<String>) -> Self {
+        self.stderr = Some(stderr.into());
+        self
+    }
+
+    pub fn with_exit_status(mut self, exit_status: i32) -> Self {
+        self.exit_status = Some(exit_status);
+        self
+    }
+
+    pub fn with_signal(mut self, signal: i32) -> Self {
+        self.signal = Some(signal);
+        self
+    }
+
+    pub fn with_duration_ms(mut self, duration_ms: u64) -> Self {
+        self.duration_ms = Some(duration_ms);
+        self
+    }
+
+    /// Set the output a single-run entry is expected to print.
+    pub fn with_expected(mut self, expected: ExpectedOutput) -> Self {
+        self.expected = Some(expected);
+        self
+    }
+
+    /// Show an image below the question.
+    pub fn with_question_image(mut self, image: ImageFile) -> Self {
+        self.question_images.push(image);
+        self
+    }
+
+    /// Start unit `unit` with this practical.
+    pub fn with_unit(mut self, unit: Unit) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+
+    /// Add an image file a single-run program wrote.
+    pub fn with_image(mut self, image: ImageFile) -> Self {
+        self.images.push(image);
+        self
+    }
+
+    /// Remove directive lines from the question and apply them. Supported
+    /// directives are `@expected-file: <path>` and `@expected: <text>`; when
+    /// nothing follows `@expected:` on its line, the rest of the question is
+    /// the expected output. `@image: <path>` shows an image below the
+    /// question and `@output-image: <path>` one the program wrote below its
+    /// output; either may end with `| <caption>`. `@unit: <title>` starts a
+    /// unit of the syllabus with the practical. Directives never override
+    /// fields that are set.
+    ///
+    /// ```
+    /// let mut entry = helpers::Entry::new(
+    ///     0,
+    ///     "Print 5\n@expected-file: q1.txt\n@image: circuit.png | The circuit",
+    ///     ".py",
+    ///     "",
+    /// );
+    /// entry.take_directives();
+    /// assert_eq!(entry.question, "Print 5");
+    /// assert_eq!(entry.expected.unwrap().file.unwrap().to_str(), Some("q1.txt"));
+    /// assert_eq!(entry.question_images[0].caption.as_deref(), Some("The circuit"));
+    /// ```
+    pub fn take_directives(&mut self) {
+        let mut question = Vec::new();
+        let mut expected = None;
+        let mut question_images = Vec::new();
+        let mut images = Vec::new();
+        let mut unit = None;
+        let mut found = false;
+
+        let mut lines = self.question.lines();
+        while let Some(line) = lines.next() {
+            let directive = line.trim();
+            if let Some(path) = directive.strip_prefix("@expected-file:") {
+                expected = Some(ExpectedOutput::file(path.trim()));
+            } else if let Some(text) = directive.strip_prefix("@expected:") {
+                let text = if text.trim().is_empty() {
+                    lines.by_ref().collect::<Vec<_>>().join("\n")
+                } else {
+                    text.trim().to_string()
+                };
+                expected = Some(ExpectedOutput::text(text));
+            } else if let Some(image) = directive.strip_prefix("@image:") {
+                question_images.push(ImageFile::from_directive(image));
+            } else if let Some(image) = directive.strip_prefix("@output-image:") {
+                images.push(ImageFile::from_directive(image));
+            } else if let Some(title) = directive.strip_prefix("@unit:") {
+                unit = Some(Unit::new(title.trim()));
+            } else {
+                question.push(line);
+                continue;
+            }
+            found = true;
+        }
+
+        if !found {
+            return;
+        }
+        self.question = question.join("\n").trim_end().to_string();
+        if self.expected.is_none() && self.runs.is_empty() {
+            self.expected = expected;
+        }
+        if self.question_images.is_empty() {
+            self.question_images = question_images;
+        }
+        if self.images.is_empty() && self.runs.is_empty() {
+            self.images = images;
+        }
+        if self.unit.is_none() {
+            self.unit = unit;
+        }
+    }
+
+    /// Resolve relative expected output and image files against `folder`.
+    pub fn resolve_files(&mut self, folder: &Path) {
+        let expected = self
+            .expected
+            .iter_mut()
+            .chain(self.runs.iter_mut().filter_map(|run| run.expected.as_mut()));
+        for expected in expected {
+            if let Some(file) = &mut expected.file {
+                if file.is_relative() {
+                    *file = folder.join(&*file);
+                }
+            }
+        }
+
+        let images = self
+            .question_images
+            .iter_mut()
+            .chain(self.images.iter_mut())
+            .chain(self.runs.iter_mut().flat_map(|run| run.images.iter_mut()));
+        for image in images {
+            if image.path.is_relative() {
+                image.path = folder.join(&image.path);
+            }
+        }
+    }
+
+    /// The files of the solution. A single-file entry is returned as one
+    /// file named after the extension, e.g. "main.cpp".
+    pub fn source_files(&self) -> Vec<SourceFile> {
+        if !self.files.is_empty() {
+            return self.files.clone();
+        }
+
+        vec![SourceFile {
+            filename: format!("main{}", self.extension),
+            language: self.extension.trim_start_matches('.').to_string(),
+            content: self.code.source.clone(),
+            highlighted: self.code.highlighted.clone(),
+        }]
+    }
+
+    /// The runs of the program. A single-run entry is returned as one run
+    /// built from `output` and `exit_status`.
+    pub fn runs(&self) -> Vec<ProgramRun> {
+        if !self.runs.is_empty() {
+            return self.runs.clone();
+        }
+
+        vec![ProgramRun {
+            label: String::new(),
+            stdin: None,
+            output: self.output.clone(),
+            transcript: self.transcript.clone(),
+            stderr: self.stderr.clone(),
+            exit_status: self.exit_status,
+            signal: self.signal,
+            duration_ms: self.duration_ms,
+            expected: self.expected.clone(),
+            images: self.images.clone(),
+        }]
+    }
+}
+
+impl ProgramRun {
+    pub fn new(label: impl Into<String>, output: Content) -> Self {
+        ProgramRun {
+            label: label.into(),
+            output,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_stdin(mut self, stdin: impl Into<String>) -> Self {
+        self.stdin = Some(stdin.into());
+        self
+    }
+
+    pub fn with_exit_status(mut self, exit_status: i32) -> Self {
+        self.exit_status = Some(exit_status);
+        self
+    }
+
+    pub fn with_stderr(mut self, stderr: impl Into<String>) -> Self {
+        self.stderr = Some(stderr.into());
+        self
+    }
+
+    pub fn with_signal(mut self, signal: i32) -> Self {
+        self.signal = Some(signal);
+        self
+    }
+
+    pub fn with_duration_ms(mut self, duration_ms: u64) -> Self {
+        self.duration_ms = Some(duration_ms);
+        self
+    }
+
+    pub fn with_expected(mut self, expected: ExpectedOutput) -> Self {
+        self.expected = Some(expected);
+        self
+    }
+
+    /// Add an image file the program wrote on this input.
+    pub fn with_image(mut self, image: ImageFile) -> Self {
+        self.images.push(image);
+        self
+    }
+
+    /// Compare the output with the expected output, if there is one.
+    pub fn check(&self, normalization: &Normalization) -> Option<CheckOutcome> {
+        let expected = self.expected.as_ref()?;
+        Some(match expected.load() {
+            Ok(text) => check::compare(&text, &self.plain_text(), normalization),
+            Err(error) => CheckOutcome::error(error),
+        })
+    }
+
+    /// Whether the program exited with a non-zero status or was killed.
+    pub fn failed(&self) -> bool {
+        self.signal.is_some() || self.exit_status.is_some_and(|status| status != 0)
+    }
+
+    /// How the program ended, e.g. "exited with code 1" or
+    /// "killed by SIGSEGV". Empty when nothing is known.
+    pub fn status(&self) -> String {
+        match (self.signal, self.exit_status) {
+            (Some(signal), _) => format!("killed by {}", signal_name(signal)),
+            (None, Some(code)) => format!("exited with code {}", code),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// The run time formatted for people, e.g. "35 ms" or "1.20 s".
+    pub fn duration(&self) -> String {
+        match self.duration_ms {
+            Some(ms) if ms < 1000 => format!("{} ms", ms),
+            Some(ms) => format!("{:.2} s", ms as f64 / 1000.0),
+            None => String::new(),
+        }
+    }
+
+    /// Add a piece of an interactive session.
+    pub fn with_chunk(mut self, stream: Stream, text: impl Into<String>) -> Self {
+        self.transcript.push(TranscriptChunk {
+            stream,
+            text: text.into(),
+        });
+        self
+    }
+
+    /// Everything the terminal showed, without markup.
+    pub fn plain_text(&self) -> String {
+        if self.transcript.is_empty() {
+            return self.output.plain_text();
+        }
+
+        self.transcript
+            .iter()
+            .map(|chunk| match chunk.stream {
+                Stream::Input => chunk.text.clone(),
+                Stream::Output => remove_ansi_codes(&chunk.text),
+            })
+            .collect()
+    }
+}
+
+/// Conventional name of a POSIX signal number.
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        24 => "SIGXCPU",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
+
+impl SourceFile {
+    pub fn new(filename: impl Into<String>, content: impl Into<String>) -> Self {
+        let filename = filename.into();
+        SourceFile {
+            language: language_of(&filename),
+            filename,
+            content: content.into(),
+            highlighted: None,
+        }
+    }
+
+    pub fn with_highlighted(mut self, highlighted: Content) -> Self {
+        self.highlighted = Some(highlighted);
+        self
+    }
+
+    /// The language, falling back to the extension of the file name.
+    pub fn language(&self) -> String {
+        if self.language.is_empty() {
+            language_of(&self.filename)
+        } else {
+            self.language.clone()
+        }
+    }
+}
+
+impl ExpectedOutput {
+    pub fn text(text: impl Into<String>) -> Self {
+        ExpectedOutput {
+            text: Some(text.into()),
+            file: None,
+        }
+    }
+
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        ExpectedOutput {
+            text: None,
+            file: Some(path.into()),
+        }
+    }
+
+    /// The expected output, read from `file` unless given inline.
+    pub fn load(&self) -> Result<String, String> {
+        match (&self.text, &self.file) {
+            (Some(text), _) => Ok(text.clone()),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read expected output {:?}: {}", path, e)),
+            (None, None) => Err("expected output has neither `text` nor `file`".to_string()),
+        }
+    }
+}
+
+impl ImageFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ImageFile {
+            path: path.into(),
+            caption: None,
+            alt: None,
+        }
+    }
+
+    pub fn with_caption(mut self, caption: impl Into<String>) -> Self {
+        self.caption = Some(caption.into());
+        self
+    }
+
+    pub fn with_alt(mut self, alt: impl Into<String>) -> Self {
+        self.alt = Some(alt.into());
+        self
+    }
+
+    /// Parse the `<path> | <caption>` argument of an image directive.
+    fn from_directive(argument: &str) -> Self {
+        match argument.split_once('|') {
+            Some((path, caption)) => ImageFile::new(path.trim()).with_caption(caption.trim()),
+            None => ImageFile::new(argument.trim()),
+        }
+    }
+
+    /// File name of the image, e.g. "plot.png".
+    pub fn filename(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+impl Unit {
+    /// A unit numbered after the one before it.
+    pub fn new(title: impl Into<String>) -> Self {
+        Unit {
+            number: None,
+            title: title.into(),
+            position: None,
+        }
+    }
+
+    pub fn with_number(mut self, number: usize) -> Self {
+        self.number = Some(number);
+        self
+    }
+
+    /// The unit practical `entry` belongs to, given `previous`, the unit of
+    /// the practical before it, once numbered.
+    pub(crate) fn follow(entry: Option<Unit>, previous: Option<Unit>) -> Option<Unit> {
+        let next = |unit: Unit| Unit {
+            position: Some(unit.position.map_or(0, |position| position + 1)),
+            ..unit
+        };
+        match (entry, previous) {
+            (None, previous) => previous.map(next),
+            // The same unit, given again
+            (Some(unit), Some(previous))
+                if unit.title == previous.title
+                    && unit
+                        .number
+                        .is_none_or(|number| previous.number == Some(number)) =>
+            {
+                let mut followed = next(previous);
+                followed.position = unit.position.or(followed.position);
+                Some(followed)
+            }
+            (Some(unit), previous) => {
+                let after = previous.and_then(|previous| previous.number).unwrap_or(0);
+                Some(Unit {
+                    number: Some(unit.number.unwrap_or(after + 1)),
+                    position: Some(unit.position.unwrap_or(0)),
+                    title: unit.title,
+                })
+            }
+        }
+    }
+}
+
+fn language_of(filename: &str) -> String {
+    std::path::Path::new(filename)
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl Content {
+    pub fn text(data: impl Into<String>) -> Self {
+        Content {
+            content_type: ContentType::Text,
+            data: data.into(),
+        }
+    }
+
+    pub fn ansi(data: impl Into<String>) -> Self {
+        Content {
+            content_type: ContentType::Ansi,
+            data: data.into(),
+        }
+    }
+
+    pub fn rtf(data: impl Into<String>) -> Self {
+        Content {
+            content_type: ContentType::Rtf,
+            data: data.into(),
+        }
+    }
+
+    /// The text with any ANSI or RTF markup removed.
+    pub fn plain_text(&self) -> String {
+        match self.content_type {
+            ContentType::Text => self.data.clone(),
+            ContentType::Ansi => remove_ansi_codes(&self.data),
+            ContentType::Rtf => match RtfDocument::try_from(self.data.as_str()) {
+                Ok(doc) => doc.get_text(),
+                Err(_) => self.data.clone(),
+            },
+        }
+    }
+}
+
+pub fn remove_ansi_codes(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch == '\u{001b}' && chars.peek() == Some(&'[') {
+            chars.next(); // consume '['
+            for next_ch in chars.by_ref() {
+                if next_ch == 'm' {
+                    break;
+                }
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+
+    result
+}
+
+/// Parse a JSON array of entries, accepting both versioned and legacy
+/// entries. Errors name the offending entry.
+///
+/// ```
+/// let entries = helpers::entry::parse_entries(
+///     r#"[{ "version": 1, "index": 0, "question": "Say hi", "extension": ".py",
+///           "code": { "source": "print('hi')" },
+///           "output": { "type": "text", "data": "hi" } }]"#,
+/// )
+/// .unwrap();
+/// assert_eq!(entries[0].output.data, "hi");
+/// ```
+pub fn parse_entries(json: &str) -> Result<Vec<Entry>, EntryError> {
+    let values: Vec<serde_json::Value> =
+        serde_json::from_str(json).map_err(EntryError::NotAnArray)?;
+
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(position, value)| {
+            parse_entry(value).map_err(|error| EntryError::AtEntry {
+                position: position + 1,
+                error: Box::new(error),
+            })
+        })
+        .collect()
+}
+
+/// Parse a single entry object.
+pub fn parse_entry(value: serde_json::Value) -> Result<Entry, EntryError> {
+    let Some(object) = value.as_object() else {
+        return Err(EntryError::NotAnObject);
+    };
+
+    let mut entry = match object.get("version") {
+        None => serde_json::from_value::<ZigOutput>(value)
+            .map(Entry::from)
+            .map_err(EntryError::InvalidLegacy),
+        Some(version) => match version.as_u64() {
+            Some(version) if version >= 1 && version <= ENTRY_VERSION as u64 => {
+                let has_code = object.contains_key("code");
+                let has_output = object.contains_key("output");
+                let entry: Entry = serde_json::from_value(value).map_err(EntryError::Invalid)?;
+                if !has_code && entry.files.is_empty() {
+                    return Err(EntryError::MissingCode);
+                }
+                if !has_output && entry.runs.is_empty() && entry.transcript.is_empty() {
+                    return Err(EntryError::MissingOutput);
+                }
+                Ok(entry)
+            }
+            _ => Err(EntryError::UnsupportedVersion(version.clone())),
+        },
+    }?;
+    entry.take_directives();
+    Ok(entry)
+}
+
+/// Read entries from `reader` and hand each one to `on_entry` as soon as it
+/// is parsed. The input is either a JSON array of entries or newline
+/// delimited JSON with one entry per line. In the latter case a line that
+/// fails to parse is passed to `on_error` and skipped; a malformed array is
+/// an error for the whole input.
+pub fn read_entries<R: BufRead>(
+    mut reader: R,
+    mut on_entry: impl FnMut(Entry),
+    mut on_error: impl FnMut(EntryError),
+) -> Result<(), EntryError> {
+    let mut line_number = 1;
+
+    // Skip leading whitespace to find out which of the two formats this is
+    let is_array = loop {
+        let buffer = reader.fill_buf()?;
+        let Some(&first) = buffer.first() else {
+            return Ok(());
+        };
+        if !first.is_ascii_whitespace() {
+            break first == b'[';
+        }
+        if first == b'\n' {
+            line_number += 1;
+        }
+        reader.consume(1);
+    };
+
+    if is_array {
+        let mut json = String::new();
+        reader.read_to_string(&mut json)?;
+        parse_entries(&json)?.into_iter().for_each(on_entry);
+        return Ok(());
+    }
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        if !line.trim().is_empty() {
+            let parsed = serde_json::from_str(&line)
+                .map_err(EntryError::Json)
+                .and_then(parse_entry);
+            match parsed {
+                Ok(entry) => on_entry(entry),
+                Err(error) => on_error(EntryError::AtLine {
+                    line: line_number,
+                    error: Box::new(error),
+                }),
+            }
+        }
+        line_number += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY: &str = r#"{ "index": 2, "question": "Add", "extension": ".c",
+        "code": "int main() {}", "code_rtf": "{\\rtf1 code}", "output_rtf": "\u001b[1m5\u001b[0m" }"#;
+
+    fn entry(index: usize) -> String {
+        format!(
+            r#"{{ "version": 1, "index": {}, "question": "Q{}", "extension": ".py",
+                "code": {{ "source": "" }}, "output": {{ "data": "" }} }}"#,
+            index, index
+        )
+        .replace('\n', "")
+    }
+
+    /// The entries read from `input` and the errors reported, by line.
+    fn read(input: &str) -> (Vec<usize>, Vec<String>) {
+        let mut indexes = Vec::new();
+        let mut errors = Vec::new();
+        read_entries(
+            input.as_bytes(),
+            |entry| indexes.push(entry.index),
+            |error| errors.push(error.to_string()),
+        )
+        .unwrap();
+        (indexes, errors)
+    }
+
+    #[test]
+    fn legacy_entries_are_converted() {
+        let entry = parse_entry(serde_json::from_str(LEGACY).unwrap()).unwrap();
+        assert_eq!(entry.version, ENTRY_VERSION);
+        assert_eq!(entry.index, 2);
+        assert_eq!(entry.code.source, "int main() {}");
+        let highlighted = entry.code.highlighted.unwrap();
+        assert_eq!(highlighted.content_type, ContentType::Rtf);
+        assert_eq!(highlighted.data, "{\\rtf1 code}");
+        assert_eq!(entry.output.content_type, ContentType::Ansi);
+
+        let rtf = LEGACY.replace(r#""\u001b[1m5\u001b[0m""#, r#"" {\\rtf1 5}""#);
+        let entry = parse_entry(serde_json::from_str(&rtf).unwrap()).unwrap();
+        assert_eq!(entry.output.content_type, ContentType::Rtf);
+    }
+
+    #[test]
+    fn legacy_entries_must_be_complete() {
+        let error = parse_entry(serde_json::json!({ "index": 0, "question": "Add" })).unwrap_err();
+        assert!(matches!(error, EntryError::InvalidLegacy(_)));
+        let error = parse_entry(serde_json::json!({ "version": 7 })).unwrap_err();
+        assert!(matches!(error, EntryError::UnsupportedVersion(_)));
+    }
+
+    #[test]
+    fn directives_are_taken_from_the_question() {
+        let mut entry = Entry::new(
+            0,
+            "Reverse a list\n  @unit: Lists \n@output-image: plot.png\n@expected:\n[3, 2, 1]\n[]",
+            ".py",
+            "",
+        );
+        entry.take_directives();
+        assert_eq!(entry.question, "Reverse a list");
+        assert_eq!(entry.unit.unwrap().title, "Lists");
+        assert_eq!(entry.images[0].path, PathBuf::from("plot.png"));
+        // `@expected:` alone takes the rest of the question
+        assert_eq!(
+            entry.expected.unwrap().text.as_deref(),
+            Some("[3, 2, 1]\n[]")
+        );
+    }
+
+    #[test]
+    fn directives_do_not_override_fields() {
+        let mut entry = Entry::new(0, "Print 5\n@expected: 6\n@unit: Basics", ".py", "")
+            .with_unit(Unit::new("Numbers"));
+        entry.expected = Some(ExpectedOutput::text("5"));
+        entry.take_directives();
+        assert_eq!(entry.question, "Print 5");
+        assert_eq!(entry.expected.unwrap().text.as_deref(), Some("5"));
+        assert_eq!(entry.unit.unwrap().title, "Numbers");
+    }
+
+    #[test]
+    fn questions_without_directives_are_left_alone() {
+        let mut entry = Entry::new(0, "Send an e-mail to a@b.c  \n", ".py", "");
+        entry.take_directives();
+        assert_eq!(entry.question, "Send an e-mail to a@b.c  \n");
+    }
+
+    #[test]
+    fn ndjson_errors_name_their_line_and_are_skipped() {
+        let input = format!(
+            "\n{}\n\n{{ not json\n{}\n[1]\n{}\n",
+            entry(0),
+            entry(1),
+            LEGACY.replace('\n', "")
+        );
+        let (indexes, errors) = read(&input);
+        assert_eq!(indexes, [0, 1, 2]);
+        assert_eq!(errors.len(), 2);
+        assert!(
+            errors[0].starts_with("line 4: invalid JSON"),
+            "{}",
+            errors[0]
+        );
+        assert!(
+            errors[1].starts_with("line 6: expected a JSON object"),
+            "{}",
+            errors[1]
+        );
+    }
+
+    #[test]
+    fn arrays_fail_as_a_whole() {
+        let (indexes, errors) = read(&format!("  [{}, {}]", entry(0), entry(1)));
+        assert_eq!(indexes, [0, 1]);
+        assert!(errors.is_empty());
+
+        let error = read_entries(
+            format!("[{}, 5]", entry(0)).as_bytes(),
+            |_| panic!("no entry is read from a broken array"),
+            |_| {},
+        )
+        .unwrap_err();
+        assert!(matches!(error, EntryError::AtEntry { position: 2, .. }));
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use crate::entry::ENTRY_VERSION;
+
+/// Errors returned by the document generator.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read {path:?}: {error}")]
+    Read {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("failed to parse format.toml: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error("invalid [output.screenshot] in format.toml: {0}")]
+    Screenshot(String),
+    #[error("invalid [styles] in format.toml: {0}")]
+    Style(String),
+    #[error("invalid container in format.toml: {0}")]
+    Container(String),
+    #[error("invalid [headings] in format.toml: {0}")]
+    Headings(String),
+    #[error("failed to parse entries: {0}")]
+    Entries(#[from] EntryError),
+    #[error("failed to write docx document: {0}")]
+    Docx(#[from] docx_rs::DocxError),
+    #[error("failed to use the cache in {path:?}: {error}")]
+    Cache {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("cannot update the existing document: {0}")]
+    Update(String),
+    #[error("cannot use the reference document {path:?}: {message}")]
+    Reference { path: PathBuf, message: String },
+    #[error("cannot read the sample document: {0}")]
+    Infer(String),
+    #[error("failed to write document: {0}")]
+    Write(#[from] std::io::Error),
+    #[error("refusing to generate the record because fail_on_error is set: {}", .0.join("; "))]
+    ProgramsFailed(Vec<String>),
+    #[error("output differs from the expected output: {}", .0.join("; "))]
+    ChecksFailed(Vec<String>),
+    #[error("screenshots could not be drawn: {}", .0.join("; "))]
+    ScreenshotsFailed(Vec<String>),
+}
+
+/// Errors found while reading entries.
+#[derive(Debug, thiserror::Error)]
+pub enum EntryError {
+    #[error("failed to read entries: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("expected a JSON array of entries: {0}")]
+    NotAnArray(serde_json::Error),
+    #[error("invalid JSON: {0}")]
+    Json(serde_json::Error),
+    #[error("expected a JSON object")]
+    NotAnObject,
+    #[error("{0}")]
+    Invalid(serde_json::Error),
+    #[error("missing field `code` or `files`")]
+    MissingCode,
+    #[error("missing field `output`, `transcript` or `runs`")]
+    MissingOutput,
+    #[error("{0} (legacy entry without a `version` field)")]
+    InvalidLegacy(serde_json::Error),
+    #[error("unsupported version {0}, this create-docx reads versions 1 to {ENTRY_VERSION}")]
+    UnsupportedVersion(serde_json::Value),
+    #[error("entry {position}: {error}")]
+    AtEntry {
+        position: usize,
+        error: Box<EntryError>,
+    },
+    #[error("line {line}: {error}")]
+    AtLine { line: usize, error: Box<EntryError> },
+}
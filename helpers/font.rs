@@ -0,0 +1,680 @@
+//! Just enough TrueType to draw monospace text into a bitmap: glyph lookup,
+//! outlines and an anti-aliased rasterizer.
+
+use std::fmt;
+use std::path::Path;
+
+/// A TrueType font loaded into memory.
+pub struct Font {
+    data: Vec<u8>,
+    units_per_em: f32,
+    ascender: f32,
+    descender: f32,
+    line_gap: f32,
+    long_loca: bool,
+    glyph_count: u16,
+    h_metric_count: u16,
+    loca: usize,
+    glyf: usize,
+    hmtx: usize,
+    cmap: Option<(usize, u16)>,
+}
+
+/// Coverage of a rasterized glyph, one value from 0 to 1 per pixel.
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub coverage: Vec<f32>,
+}
+
+#[derive(Clone, Copy)]
+struct Point {
+    x: f32,
+    y: f32,
+    on_curve: bool,
+}
+
+/// Monospace fonts tried when none is configured.
+const SYSTEM_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+    "/usr/share/fonts/TTF/DejaVuSansMono.ttf",
+    "/usr/share/fonts/dejavu-sans-mono-fonts/DejaVuSansMono.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationMono-Regular.ttf",
+    "/usr/share/fonts/liberation-mono/LiberationMono-Regular.ttf",
+    "/Library/Fonts/Courier New.ttf",
+    "/System/Library/Fonts/Supplemental/Courier New.ttf",
+    "C:\\Windows\\Fonts\\consola.ttf",
+    "C:\\Windows\\Fonts\\cour.ttf",
+];
+
+impl fmt::Debug for Font {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Font")
+            .field("glyphs", &self.glyph_count)
+            .field("units_per_em", &self.units_per_em)
+            .finish()
+    }
+}
+
+impl Font {
+    /// Load a `.ttf` file.
+    pub fn load(path: &Path) -> Result<Font, String> {
+        let data =
+            std::fs::read(path).map_err(|e| format!("failed to read font {:?}: {}", path, e))?;
+        Font::parse(data).map_err(|e| format!("failed to load font {:?}: {}", path, e))
+    }
+
+    /// Load the first monospace font found in the usual system locations.
+    pub fn system_monospace() -> Result<Font, String> {
+        SYSTEM_FONTS
+            .iter()
+            .map(Path::new)
+            .find(|path| path.exists())
+            .ok_or_else(|| {
+                "no monospace TrueType font found, set `font` to the path of a .ttf file"
+                    .to_string()
+            })
+            .and_then(Font::load)
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<Font, String> {
+        let table = |tag: &[u8; 4]| -> Option<usize> {
+            let count = read_u16(&data, 4)? as usize;
+            (0..count)
+                .map(|i| 12 + 16 * i)
+                .find(|&record| data.get(record..record + 4) == Some(tag))
+                .and_then(|record| read_u32(&data, record + 8))
+                .map(|offset| offset as usize)
+        };
+        let required = |tag: &[u8; 4]| {
+            table(tag).ok_or_else(|| {
+                format!(
+                    "missing `{}` table, only TrueType outlines are supported",
+                    String::from_utf8_lossy(tag)
+                )
+            })
+        };
+
+        let head = required(b"head")?;
+        let hhea = required(b"hhea")?;
+        let maxp = required(b"maxp")?;
+        let loca = required(b"loca")?;
+        let glyf = required(b"glyf")?;
+        let hmtx = required(b"hmtx")?;
+        let cmap = required(b"cmap")?;
+        let malformed = || "malformed font".to_string();
+
+        let mut font = Font {
+            units_per_em: read_u16(&data, head + 18).ok_or_else(malformed)? as f32,
+            long_loca: read_i16(&data, head + 50).ok_or_else(malformed)? == 1,
+            ascender: read_i16(&data, hhea + 4).ok_or_else(malformed)? as f32,
+            descender: read_i16(&data, hhea + 6).ok_or_else(malformed)? as f32,
+            line_gap: read_i16(&data, hhea + 8).ok_or_else(malformed)? as f32,
+            h_metric_count: read_u16(&data, hhea + 34).ok_or_else(malformed)?,
+            glyph_count: read_u16(&data, maxp + 4).ok_or_else(malformed)?,
+            loca,
+            glyf,
+            hmtx,
+            cmap: None,
+            data,
+        };
+        font.cmap = font.find_cmap(cmap);
+        if font.cmap.is_none() {
+            return Err("no Unicode character map".to_string());
+        }
+        Ok(font)
+    }
+
+    /// Offset and format of the best Unicode subtable of `cmap`.
+    fn find_cmap(&self, cmap: usize) -> Option<(usize, u16)> {
+        let count = read_u16(&self.data, cmap + 2)? as usize;
+        let mut best = None;
+        for i in 0..count {
+            let record = cmap + 4 + 8 * i;
+            let platform = read_u16(&self.data, record)?;
+            let encoding = read_u16(&self.data, record + 2)?;
+            let offset = cmap + read_u32(&self.data, record + 4)? as usize;
+            let format = read_u16(&self.data, offset)?;
+            let unicode = platform == 0 || (platform == 3 && (encoding == 1 || encoding == 10));
+            if unicode && (format == 12 || (format == 4 && best.is_none())) {
+                best = Some((offset, format));
+            }
+        }
+        best
+    }
+
+    pub fn glyph_index(&self, ch: char) -> u16 {
+        let code = ch as u32;
+        let Some((offset, format)) = self.cmap else {
+            return 0;
+        };
+        let data = &self.data;
+        let found = match format {
+            4 => (|| {
+                let segments = read_u16(data, offset + 6)? as usize / 2;
+                let ends = offset + 14;
+                let starts = ends + 2 * segments + 2;
+                let deltas = starts + 2 * segments;
+                let ranges = deltas + 2 * segments;
+                for i in 0..segments {
+                    let end = read_u16(data, ends + 2 * i)? as u32;
+                    if code > end {
+                        continue;
+                    }
+                    let start = read_u16(data, starts + 2 * i)? as u32;
+                    if code < start {
+                        return None;
+                    }
+                    let delta = read_u16(data, deltas + 2 * i)?;
+                    let range = read_u16(data, ranges + 2 * i)? as usize;
+                    if range == 0 {
+                        return Some((code as u16).wrapping_add(delta));
+                    }
+                    let address = ranges + 2 * i + range + 2 * (code - start) as usize;
+                    let glyph = read_u16(data, address)?;
+                    return Some(if glyph == 0 {
+                        0
+                    } else {
+                        glyph.wrapping_add(delta)
+                    });
+                }
+                None
+            })(),
+            12 => (|| {
+                let groups = read_u32(data, offset + 12)? as usize;
+                for i in 0..groups {
+                    let group = offset + 16 + 12 * i;
+                    let start = read_u32(data, group)?;
+                    let end = read_u32(data, group + 4)?;
+                    if (start..=end).contains(&code) {
+                        let glyph = read_u32(data, group + 8)? + code - start;
+                        return u16::try_from(glyph).ok();
+                    }
+                }
+                None
+            })(),
+            _ => None,
+        };
+        found.filter(|&glyph| glyph < self.glyph_count).unwrap_or(0)
+    }
+
+    /// Horizontal advance of a glyph in pixels at `size` pixels per em.
+    pub fn advance(&self, glyph: u16, size: f32) -> f32 {
+        let index = glyph.min(self.h_metric_count.saturating_sub(1)) as usize;
+        let units = read_u16(&self.data, self.hmtx + 4 * index).unwrap_or(0) as f32;
+        units * size / self.units_per_em
+    }
+
+    /// Distance from the top of a line to the baseline, in pixels.
+    pub fn ascent(&self, size: f32) -> f32 {
+        self.ascender * size / self.units_per_em
+    }
+
+    /// Height of a line, in pixels.
+    pub fn line_height(&self, size: f32) -> f32 {
+        (self.ascender - self.descender + self.line_gap) * size / self.units_per_em
+    }
+
+    /// Rasterize a glyph into a `width` × `height` cell whose baseline is
+    /// `baseline` pixels from the top.
+    pub fn rasterize(
+        &self,
+        glyph: u16,
+        size: f32,
+        width: usize,
+        height: usize,
+        baseline: f32,
+    ) -> Bitmap {
+        let scale = size / self.units_per_em;
+        let mut raster = Raster::new(width, height);
+        for contour in self.outline(glyph, 0) {
+            let points: Vec<Point> = contour
+                .into_iter()
+                .map(|p| Point {
+                    x: p.x * scale,
+                    y: baseline - p.y * scale,
+                    on_curve: p.on_curve,
+                })
+                .collect();
+            raster.draw_contour(&points);
+        }
+        raster.finish()
+    }
+
+    fn glyph_range(&self, glyph: u16) -> Option<(usize, usize)> {
+        let glyph = glyph as usize;
+        let (start, end) = if self.long_loca {
+            (
+                read_u32(&self.data, self.loca + 4 * glyph)? as usize,
+                read_u32(&self.data, self.loca + 4 * glyph + 4)? as usize,
+            )
+        } else {
+            (
+                read_u16(&self.data, self.loca + 2 * glyph)? as usize * 2,
+                read_u16(&self.data, self.loca + 2 * glyph + 2)? as usize * 2,
+            )
+        };
+        (end > start).then_some((self.glyf + start, self.glyf + end))
+    }
+
+    /// Contours of a glyph in font units.
+    fn outline(&self, glyph: u16, depth: usize) -> Vec<Vec<Point>> {
+        let Some((start, _)) = self.glyph_range(glyph) else {
+            return Vec::new();
+        };
+        let Some(contours) = read_i16(&self.data, start) else {
+            return Vec::new();
+        };
+        if contours >= 0 {
+            self.simple_outline(start, contours as usize)
+                .unwrap_or_default()
+        } else if depth < 4 {
+            self.composite_outline(start, depth).unwrap_or_default()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn simple_outline(&self, start: usize, contours: usize) -> Option<Vec<Vec<Point>>> {
+        let data = &self.data;
+        let mut ends = Vec::with_capacity(contours);
+        for i in 0..contours {
+            ends.push(read_u16(data, start + 10 + 2 * i)? as usize);
+        }
+        let count = ends.last().map_or(0, |last| last + 1);
+        let instructions = read_u16(data, start + 10 + 2 * contours)? as usize;
+        let mut offset = start + 12 + 2 * contours + instructions;
+
+        let mut flags = Vec::with_capacity(count);
+        while flags.len() < count {
+            let flag = *data.get(offset)?;
+            offset += 1;
+            flags.push(flag);
+            if flag & 8 != 0 {
+                let repeat = *data.get(offset)?;
+                offset += 1;
+                for _ in 0..repeat {
+                    flags.push(flag);
+                }
+            }
+        }
+        flags.truncate(count);
+
+        let mut read_coordinates = |short: u8, same: u8| -> Option<Vec<f32>> {
+            let mut value = 0i32;
+            let mut values = Vec::with_capacity(count);
+            for &flag in &flags {
+                if flag & short != 0 {
+                    let delta = *data.get(offset)? as i32;
+                    offset += 1;
+                    value += if flag & same != 0 { delta } else { -delta };
+                } else if flag & same == 0 {
+                    value += read_i16(data, offset)? as i32;
+                    offset += 2;
+                }
+                values.push(value as f32);
+            }
+            Some(values)
+        };
+        let xs = read_coordinates(2, 16)?;
+        let ys = read_coordinates(4, 32)?;
+
+        let mut outline = Vec::with_capacity(contours);
+        let mut first = 0;
+        for end in ends {
+            let contour = (first..=end.min(count.saturating_sub(1)))
+                .map(|i| Point {
+                    x: xs[i],
+                    y: ys[i],
+                    on_curve: flags[i] & 1 != 0,
+                })
+                .collect();
+            outline.push(contour);
+            first = end + 1;
+        }
+        Some(outline)
+    }
+
+    fn composite_outline(&self, start: usize, depth: usize) -> Option<Vec<Vec<Point>>> {
+        let data = &self.data;
+        let mut offset = start + 10;
+        let mut outline = Vec::new();
+        loop {
+            let flags = read_u16(data, offset)?;
+            let glyph = read_u16(data, offset + 2)?;
+            offset += 4;
+            let (dx, dy) = if flags & 1 != 0 {
+                let args = (read_i16(data, offset)?, read_i16(data, offset + 2)?);
+                offset += 4;
+                (args.0 as f32, args.1 as f32)
+            } else {
+                let args = (*data.get(offset)? as i8, *data.get(offset + 1)? as i8);
+                offset += 2;
+                (args.0 as f32, args.1 as f32)
+            };
+            // Components positioned by matching points are placed unmoved
+            let (dx, dy) = if flags & 2 != 0 { (dx, dy) } else { (0.0, 0.0) };
+
+            let f2dot14 = |offset: usize| read_i16(data, offset).map(|v| v as f32 / 16384.0);
+            let (a, b, c, d) = if flags & 8 != 0 {
+                let s = f2dot14(offset)?;
+                offset += 2;
+                (s, 0.0, 0.0, s)
+            } else if flags & 0x40 != 0 {
+                let (sx, sy) = (f2dot14(offset)?, f2dot14(offset + 2)?);
+                offset += 4;
+                (sx, 0.0, 0.0, sy)
+            } else if flags & 0x80 != 0 {
+                let matrix = (
+                    f2dot14(offset)?,
+                    f2dot14(offset + 2)?,
+                    f2dot14(offset + 4)?,
+                    f2dot14(offset + 6)?,
+                );
+                offset += 8;
+                matrix
+            } else {
+                (1.0, 0.0, 0.0, 1.0)
+            };
+
+            for contour in self.outline(glyph, depth + 1) {
+                outline.push(
+                    contour
+                        .into_iter()
+                        .map(|p| Point {
+                            x: a * p.x + c * p.y + dx,
+                            y: b * p.x + d * p.y + dy,
+                            on_curve: p.on_curve,
+                        })
+                        .collect(),
+                );
+            }
+
+            if flags & 0x20 == 0 {
+                return Some(outline);
+            }
+        }
+    }
+}
+
+/// Signed-area accumulation rasterizer: every edge adds the area it covers
+/// to the cells it crosses, and a running sum along each row turns that into
+/// coverage with the non-zero winding rule.
+struct Raster {
+    width: usize,
+    height: usize,
+    stride: usize,
+    area: Vec<f32>,
+}
+
+impl Raster {
+    fn new(width: usize, height: usize) -> Self {
+        let stride = width + 2;
+        Raster {
+            width,
+            height,
+            stride,
+            area: vec![0.0; stride * height],
+        }
+    }
+
+    fn draw_contour(&mut self, points: &[Point]) {
+        if points.len() < 2 {
+            return;
+        }
+
+        // Insert the on-curve points implied between two off-curve points
+        let mut expanded = Vec::with_capacity(points.len() * 2);
+        for (i, &p) in points.iter().enumerate() {
+            let q = points[(i + 1) % points.len()];
+            expanded.push(p);
+            if !p.on_curve && !q.on_curve {
+                expanded.push(Point {
+                    x: (p.x + q.x) / 2.0,
+                    y: (p.y + q.y) / 2.0,
+                    on_curve: true,
+                });
+            }
+        }
+        let Some(start) = expanded.iter().position(|p| p.on_curve) else {
+            return;
+        };
+
+        let count = expanded.len();
+        let mut current = expanded[start];
+        let mut i = 1;
+        while i <= count {
+            let p = expanded[(start + i) % count];
+            if p.on_curve {
+                self.draw_line(current, p);
+                current = p;
+                i += 1;
+            } else {
+                let end = expanded[(start + i + 1) % count];
+                self.draw_quad(current, p, end);
+                current = end;
+                i += 2;
+            }
+        }
+    }
+
+    fn draw_quad(&mut self, p0: Point, p1: Point, p2: Point) {
+        const SEGMENTS: usize = 8;
+        let mut previous = p0;
+        for step in 1..=SEGMENTS {
+            let t = step as f32 / SEGMENTS as f32;
+            let u = 1.0 - t;
+            let next = Point {
+                x: u * u * p0.x + 2.0 * u * t * p1.x + t * t * p2.x,
+                y: u * u * p0.y + 2.0 * u * t * p1.y + t * t * p2.y,
+                on_curve: true,
+            };
+            self.draw_line(previous, next);
+            previous = next;
+        }
+    }
+
+    fn draw_line(&mut self, p0: Point, p1: Point) {
+        if (p0.y - p1.y).abs() < f32::EPSILON {
+            return;
+        }
+        // Area left of the cell is accumulated into column 0 anyway, so
+        // clamping x keeps the coverage inside the cell exact
+        let clamp = |x: f32| x.clamp(0.0, self.width as f32);
+        let (direction, top, bottom) = if p0.y < p1.y {
+            (1.0, p0, p1)
+        } else {
+            (-1.0, p1, p0)
+        };
+        let dxdy = (bottom.x - top.x) / (bottom.y - top.y);
+
+        let first_row = top.y.max(0.0).floor() as usize;
+        let last_row = (bottom.y.ceil().max(0.0) as usize).min(self.height);
+        for row in first_row..last_row {
+            let y0 = (row as f32).max(top.y);
+            let y1 = ((row + 1) as f32).min(bottom.y);
+            if y1 <= y0 {
+                continue;
+            }
+            let xa = clamp(top.x + (y0 - top.y) * dxdy);
+            let xb = clamp(top.x + (y1 - top.y) * dxdy);
+            let d = (y1 - y0) * direction;
+            let (x0, x1) = if xa < xb { (xa, xb) } else { (xb, xa) };
+            let line = &mut self.area[row * self.stride..(row + 1) * self.stride];
+
+            let x0floor = x0.floor();
+            let x0i = x0floor as usize;
+            let x1ceil = x1.ceil();
+            let x1i = x1ceil as usize;
+            if x1i <= x0i + 1 {
+                // The edge stays within one column
+                let middle = 0.5 * (xa + xb) - x0floor;
+                line[x0i] += d - d * middle;
+                line[x0i + 1] += d * middle;
+            } else {
+                let s = (x1 - x0).recip();
+                let x0f = x0 - x0floor;
+                let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+                let x1f = x1 - x1ceil + 1.0;
+                let am = 0.5 * s * x1f * x1f;
+                line[x0i] += d * a0;
+                if x1i == x0i + 2 {
+                    line[x0i + 1] += d * (1.0 - a0 - am);
+                } else {
+                    let a1 = s * (1.5 - x0f);
+                    line[x0i + 1] += d * (a1 - a0);
+                    for cell in &mut line[x0i + 2..x1i - 1] {
+                        *cell += d * s;
+                    }
+                    let a2 = a1 + (x1i - x0i - 3) as f32 * s;
+                    line[x1i - 1] += d * (1.0 - a2 - am);
+                }
+                line[x1i] += d * am;
+            }
+        }
+    }
+
+    fn finish(self) -> Bitmap {
+        let mut coverage = Vec::with_capacity(self.width * self.height);
+        for row in self.area.chunks(self.stride) {
+            let mut sum = 0.0;
+            for &area in &row[..self.width] {
+                sum += area;
+                coverage.push(sum.abs().min(1.0));
+            }
+        }
+        Bitmap {
+            width: self.width,
+            height: self.height,
+            coverage,
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    read_u16(data, offset).map(|value| value as i16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A TrueType font of 1000 units per em whose only glyph, for "A", is
+    /// a square from 100 to 500 across and 0 to 700 up, 600 units wide.
+    pub(crate) fn square_font() -> Vec<u8> {
+        let u16s = |values: &[u16]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect()
+        };
+        let mut head = vec![0; 54];
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        let mut hhea = vec![0; 36];
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&2u16.to_be_bytes());
+        let maxp = u16s(&[1, 0, 2]);
+        // Contours, bounds, the end of the one contour, no instructions,
+        // four points on the curve and their x and y moves
+        let glyf = u16s(&[
+            1,
+            100,
+            0,
+            500,
+            700,
+            3,
+            0,
+            0x0101,
+            0x0101,
+            100,
+            0,
+            400,
+            0,
+            0,
+            700,
+            0,
+            (-700i16) as u16,
+        ]);
+        let loca = u16s(&[0, 0, glyf.len() as u16 / 2]);
+        let hmtx = u16s(&[600, 0, 600, 0]);
+        let delta = 1u16.wrapping_sub(u16::from(b'A'));
+        let mut cmap = u16s(&[0, 1, 3, 1, 0, 12]);
+        cmap.extend(u16s(&[
+            4, 32, 0, 4, 4, 1, 0, // format 4, two segments
+            0x41, 0xffff, 0, // ends
+            0x41, 0xffff, // starts
+            delta, 1, // deltas
+            0, 0, // no ranges
+        ]));
+
+        let tables: [(&[u8; 4], Vec<u8>); 7] = [
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"loca", loca),
+            (b"maxp", maxp),
+        ];
+        let mut font = u16s(&[1, 0, tables.len() as u16, 0, 0, 0]);
+        let mut offset = 12 + 16 * tables.len();
+        let mut data: Vec<u8> = Vec::new();
+        for (tag, table) in &tables {
+            font.extend_from_slice(*tag);
+            font.extend(0u32.to_be_bytes());
+            font.extend((offset as u32).to_be_bytes());
+            font.extend((table.len() as u32).to_be_bytes());
+            offset += table.len();
+            data.extend(table);
+        }
+        font.extend(data);
+        font
+    }
+
+    #[test]
+    fn glyph_is_found_measured_and_rasterized() {
+        let font = Font::parse(square_font()).unwrap();
+        let glyph = font.glyph_index('A');
+        assert_eq!(glyph, 1);
+        assert_eq!(font.glyph_index('B'), 0);
+        assert_eq!(font.advance(glyph, 20.0), 12.0);
+        assert_eq!(font.ascent(20.0), 16.0);
+        assert_eq!(font.line_height(20.0), 20.0);
+
+        // At 20 pixels per em the square covers x 2 to 10 and the 14
+        // pixels above the baseline
+        let bitmap = font.rasterize(glyph, 20.0, 12, 24, 18.0);
+        assert_eq!((bitmap.width, bitmap.height), (12, 24));
+        let coverage = |x: usize, y: usize| bitmap.coverage[y * bitmap.width + x];
+        assert!(coverage(6, 10) > 0.99);
+        assert!(coverage(2, 4) > 0.99 && coverage(9, 17) > 0.99);
+        for (x, y) in [(1, 10), (10, 10), (6, 3), (6, 18)] {
+            assert!(coverage(x, y) < 0.01, "pixel {}, {} is covered", x, y);
+        }
+        assert_eq!(
+            font.rasterize(0, 20.0, 12, 24, 18.0).coverage,
+            vec![0.0; 12 * 24]
+        );
+    }
+
+    #[test]
+    fn fonts_without_outlines_are_rejected() {
+        let mut font = square_font();
+        // Rename the glyf table
+        let record = font.windows(4).position(|tag| tag == b"glyf").unwrap();
+        font[record..record + 4].copy_from_slice(b"CFF ");
+        let error = Font::parse(font).unwrap_err();
+        assert!(error.contains("`glyf`"), "{}", error);
+    }
+}
@@ -0,0 +1,955 @@
+//! Inferring a `format.toml` from a sample record, e.g. one a department
+//! hands out in Word.
+//!
+//! The sample is split into practicals at page breaks, or else at a
+//! repeating numbered heading. Within each practical, paragraphs are given
+//! the roles of the config (header, question, code and output with their
+//! titles, footer) and the formatting of each role is the one most of its
+//! paragraphs share. Whatever does not map cleanly is reported in
+//! [`InferredConfig::notes`].
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::utilities::{DocumentConfig, Paragraph, TextDefaults};
+
+/// A config inferred from a sample record.
+#[derive(Debug)]
+pub struct InferredConfig {
+    pub config: DocumentConfig,
+    /// What could not be mapped to the config, or was only guessed.
+    pub notes: Vec<String>,
+}
+
+/// Words that make a short paragraph the title of the code.
+const SOLUTION_TITLES: [&str; 6] = [
+    "code",
+    "program",
+    "source",
+    "solution",
+    "implementation",
+    "listing",
+];
+
+/// Words that make a short paragraph the title of the output.
+const OUTPUT_TITLES: [&str; 4] = ["output", "result", "screenshot", "execution"];
+
+/// Parts of the names of monospace fonts, which mark code.
+const MONOSPACE: [&str; 10] = [
+    "courier",
+    "consolas",
+    "mono",
+    "menlo",
+    "monaco",
+    "code",
+    "console",
+    "fira",
+    "inconsolata",
+    "hack",
+];
+
+/// Formatting of a paragraph, taken from its longest run.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Format {
+    size: usize,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    font: String,
+    color: String,
+    align: String,
+    style: String,
+}
+
+/// A non-empty paragraph of the sample.
+#[derive(Debug, Clone)]
+struct Line {
+    text: String,
+    format: Format,
+    /// Text colors of the runs, more than one for highlighted code.
+    colors: usize,
+    pictures: usize,
+    /// Fill of the table cell holding the paragraph, `Some("")` for a cell
+    /// without one.
+    cell: Option<String>,
+}
+
+impl Line {
+    fn is_code(&self) -> bool {
+        let font = self.format.font.to_lowercase();
+        MONOSPACE.iter().any(|name| font.contains(name))
+    }
+
+    /// Whether the paragraph is a title of a few words including one of
+    /// `words`, such as "Program:" or "Source code".
+    fn is_title(&self, words: &[&str]) -> bool {
+        let text = self
+            .text
+            .trim()
+            .trim_end_matches([':', '-', '.'])
+            .to_lowercase();
+        text.split_whitespace().count() <= 3
+            && !self.is_code()
+            && text
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| words.iter().any(|title| word.starts_with(title)))
+    }
+}
+
+/// The paragraphs of one practical, by role.
+#[derive(Debug, Default)]
+struct Practical {
+    header: Option<Line>,
+    question: Vec<Line>,
+    solution_title: Option<Line>,
+    solution: Vec<Line>,
+    output_title: Option<Line>,
+    output: Vec<Line>,
+    footer: Option<Line>,
+}
+
+/// Infer the config of the record in `docx`, a `.docx` file.
+pub fn infer_config(docx: &[u8]) -> Result<InferredConfig, Error> {
+    let docx = docx_rs::read_docx(docx).map_err(|e| Error::Infer(e.to_string()))?;
+    // docx-rs keeps most property values private but serializes them, so
+    // the document is read in its serialized form
+    let styles = Styles::new(serde_json::to_value(&docx.styles).unwrap_or_default());
+    let document = serde_json::to_value(&docx.document).unwrap_or_default();
+
+    let mut notes = Vec::new();
+    let mut pages = vec![Vec::new()];
+    collect(&document["children"], &styles, None, &mut pages, &mut notes);
+    pages.retain(|page| !page.is_empty());
+    if pages.len() < 2 {
+        pages = split_at_headings(pages.concat());
+    }
+
+    let mut practicals = Vec::new();
+    for (number, page) in pages.iter().enumerate() {
+        let looks_like_practical = page
+            .iter()
+            .any(|line| line.is_code() || line.is_title(&SOLUTION_TITLES));
+        if looks_like_practical {
+            practicals.push(page.clone());
+        } else {
+            notes.push(format!(
+                "skipped part {} (\"{}\"), which has no code",
+                number + 1,
+                preview(&page[0].text)
+            ));
+        }
+    }
+    if practicals.is_empty() {
+        return Err(Error::Infer(
+            "found no practical, i.e. a part of the sample with code in a monospace font or a title such as \"Program:\"".to_string(),
+        ));
+    }
+
+    let header = header_template(&practicals);
+    let mut practicals: Vec<Practical> = practicals
+        .into_iter()
+        .map(|lines| assign_roles(lines, header.is_some()))
+        .collect();
+    find_footers(&mut practicals);
+
+    let config = build_config(&practicals, header, &mut notes);
+    Ok(InferredConfig { config, notes })
+}
+
+impl InferredConfig {
+    /// The config as the contents of a `format.toml`, leaving out values
+    /// that are the defaults and listing the notes in a comment on top.
+    pub fn to_toml(&self, source: &str) -> String {
+        let mut toml = format!(
+            "# Inferred by `create-docx infer-config` from {}.\n",
+            source
+        );
+        if !self.notes.is_empty() {
+            toml.push_str("# Check these before building a record:\n");
+            for note in &self.notes {
+                toml.push_str(&format!("#   - {}\n", note));
+            }
+        }
+        toml.push('\n');
+
+        let defaults: DocumentConfig = toml::from_str(
+            "header.text = ''\nquestion.text = ''\nsolution.text = ''\nsolution.title.text = ''\noutput.text = ''\noutput.title.text = ''\nfooter.text = ''",
+        )
+        .expect("the minimal config parses");
+        let (Ok(mut config), Ok(defaults)) = (
+            toml::Value::try_from(&self.config),
+            toml::Value::try_from(&defaults),
+        ) else {
+            return toml;
+        };
+        prune(&mut config, &defaults);
+        toml.push_str(&toml::to_string_pretty(&config).unwrap_or_default());
+        toml
+    }
+}
+
+/// Remove the values of `value` equal to those in `defaults`.
+fn prune(value: &mut toml::Value, defaults: &toml::Value) {
+    let (Some(table), Some(defaults)) = (value.as_table_mut(), defaults.as_table()) else {
+        return;
+    };
+    table.retain(|key, value| {
+        let Some(default) = defaults.get(key) else {
+            return true;
+        };
+        if value.is_table() {
+            prune(value, default);
+            return value.as_table().is_some_and(|table| !table.is_empty());
+        }
+        value != default
+    });
+}
+
+/// Paragraph and character styles of the sample, for the formatting a
+/// paragraph inherits.
+struct Styles {
+    styles: HashMap<String, Value>,
+    defaults: Value,
+}
+
+impl Styles {
+    fn new(styles: Value) -> Self {
+        let by_id = styles["styles"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|style| Some((style["styleId"].as_str()?.to_string(), style.clone())))
+            .collect();
+        Styles {
+            styles: by_id,
+            defaults: styles["docDefaults"].clone(),
+        }
+    }
+
+    /// `key` of the run properties of style `id` or the styles it is based
+    /// on.
+    fn run(&self, id: Option<&str>, key: &str) -> Option<Value> {
+        self.chain(id)
+            .find_map(|style| non_null(&style["runProperty"][key]))
+    }
+
+    /// `key` of the run properties of the document defaults.
+    fn default_run(&self, key: &str) -> Option<Value> {
+        non_null(&self.defaults["runPropertyDefault"]["runProperty"][key])
+    }
+
+    /// `key` of the paragraph properties of style `id` or the styles it is
+    /// based on.
+    fn paragraph(&self, id: Option<&str>, key: &str) -> Option<Value> {
+        self.chain(id)
+            .find_map(|style| non_null(&style["paragraphProperty"][key]))
+            .or_else(|| {
+                non_null(&self.defaults["paragraphPropertyDefault"]["paragraphProperty"][key])
+            })
+    }
+
+    fn chain<'a>(&'a self, id: Option<&'a str>) -> impl Iterator<Item = &'a Value> {
+        let mut next = id;
+        // Guard against styles based on each other
+        (0..16).map_while(move |_| {
+            let style = self.styles.get(next?)?;
+            next = style["basedOn"]
+                .as_str()
+                .or_else(|| style["basedOn"]["val"].as_str());
+            Some(style)
+        })
+    }
+}
+
+fn non_null(value: &Value) -> Option<Value> {
+    (!value.is_null()).then(|| value.clone())
+}
+
+/// Add the non-empty paragraphs in `children` to `pages`, starting a new
+/// page at every page break.
+fn collect(
+    children: &Value,
+    styles: &Styles,
+    cell: Option<&str>,
+    pages: &mut Vec<Vec<Line>>,
+    notes: &mut Vec<String>,
+) {
+    for child in children.as_array().into_iter().flatten() {
+        let data = &child["data"];
+        match child["type"].as_str() {
+            Some("paragraph") => add_paragraph(data, styles, cell, pages, notes),
+            Some("table") => {
+                for row in data["rows"].as_array().into_iter().flatten() {
+                    for cell in row["data"]["cells"].as_array().into_iter().flatten() {
+                        let fill = cell["data"]["property"]["shading"]["fill"]
+                            .as_str()
+                            .filter(|fill| *fill != "auto")
+                            .map(|fill| format!("#{}", fill.to_lowercase()))
+                            .unwrap_or_default();
+                        collect(&cell["data"]["children"], styles, Some(&fill), pages, notes);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn add_paragraph(
+    data: &Value,
+    styles: &Styles,
+    cell: Option<&str>,
+    pages: &mut Vec<Vec<Line>>,
+    notes: &mut Vec<String>,
+) {
+    let property = &data["property"];
+    let style = property["style"].as_str();
+    if property["pageBreakBefore"].as_bool() == Some(true) {
+        new_page(pages);
+    }
+
+    let mut text = String::new();
+    let mut pictures = 0;
+    let mut break_after = false;
+    // Characters of text per format, to find the format of most of the text
+    let mut formats: Vec<(Format, usize)> = Vec::new();
+    let mut colors = Vec::new();
+    for run in runs(&data["children"]) {
+        let format = run_format(&run["data"]["runProperty"], style, property, styles, notes);
+        let mut length = 0;
+        for child in run["data"]["children"].as_array().into_iter().flatten() {
+            match child["type"].as_str() {
+                Some("text") => {
+                    let part = child["data"]["text"].as_str().unwrap_or_default();
+                    length += part.trim().chars().count();
+                    text.push_str(part);
+                }
+                Some("tab") => text.push('\t'),
+                Some("drawing") => pictures += 1,
+                Some("break") if child["data"]["breakType"] == "page" => {
+                    if text.trim().is_empty() && pictures == 0 {
+                        new_page(pages);
+                    } else {
+                        break_after = true;
+                    }
+                }
+                Some("break") => text.push('\n'),
+                _ => {}
+            }
+        }
+        if length > 0 {
+            if !colors.contains(&format.color) {
+                colors.push(format.color.clone());
+            }
+            match formats.iter_mut().find(|(known, _)| *known == format) {
+                Some((_, total)) => *total += length,
+                None => formats.push((format, length)),
+            }
+        }
+    }
+
+    if !text.trim().is_empty() || pictures > 0 {
+        let format = formats
+            .into_iter()
+            .max_by_key(|(_, length)| *length)
+            .map(|(format, _)| format)
+            .unwrap_or_else(|| run_format(&Value::Null, style, property, styles, notes));
+        let lines = pages.last_mut().expect("there is always a page");
+        lines.push(Line {
+            text: text.trim_end().to_string(),
+            format,
+            colors: colors.len(),
+            pictures,
+            cell: cell.map(str::to_string),
+        });
+    }
+    if break_after || !property["sectionProperty"].is_null() {
+        new_page(pages);
+    }
+}
+
+fn new_page(pages: &mut Vec<Vec<Line>>) {
+    if pages.last().is_some_and(|page| !page.is_empty()) {
+        pages.push(Vec::new());
+    }
+}
+
+/// The runs of a paragraph, including those in hyperlinks and insertions.
+fn runs(children: &Value) -> Vec<&Value> {
+    let mut runs = Vec::new();
+    for child in children.as_array().into_iter().flatten() {
+        if child["type"] == "run" {
+            runs.push(child);
+        } else {
+            runs.extend(self::runs(&child["data"]["children"]));
+        }
+    }
+    runs
+}
+
+fn run_format(
+    run: &Value,
+    style: Option<&str>,
+    paragraph: &Value,
+    styles: &Styles,
+    notes: &mut Vec<String>,
+) -> Format {
+    let character_style = run["style"].as_str();
+    let value = |key: &str| {
+        non_null(&run[key])
+            .or_else(|| styles.run(character_style, key))
+            .or_else(|| styles.run(style, key))
+            .or_else(|| styles.default_run(key))
+    };
+
+    let size = value("sz")
+        .and_then(|size| size.as_u64())
+        // Word's size when nothing sets one
+        .map_or(10, |half_points| (half_points as usize).div_ceil(2));
+    let font = value("fonts")
+        .and_then(|fonts| {
+            ["ascii", "hiAnsi", "eastAsia"]
+                .iter()
+                .find_map(|key| fonts[*key].as_str().map(str::to_string))
+        })
+        .unwrap_or_else(|| {
+            let note = "some text uses the theme font, which was taken to be Calibri".to_string();
+            if !notes.contains(&note) {
+                notes.push(note);
+            }
+            "Calibri".to_string()
+        });
+    let color = value("color")
+        .and_then(|color| color.as_str().map(str::to_lowercase))
+        .filter(|color| color != "auto")
+        .map_or_else(|| "#000000".to_string(), |color| format!("#{}", color));
+    let align = non_null(&paragraph["alignment"])
+        .or_else(|| styles.paragraph(style, "alignment"))
+        .and_then(|align| align.as_str().map(str::to_string));
+    let align = match align.as_deref() {
+        Some("center") => "center",
+        Some("right" | "end") => "right",
+        Some("both" | "distribute") => "justify",
+        _ => "left",
+    };
+
+    Format {
+        size,
+        bold: value("bold").and_then(|bold| bold.as_bool()) == Some(true),
+        italic: value("italic").and_then(|italic| italic.as_bool()) == Some(true),
+        underline: value("underline")
+            .and_then(|underline| underline.as_str().map(|kind| kind != "none"))
+            .unwrap_or(false),
+        font,
+        color,
+        align: align.to_string(),
+        style: style.unwrap_or("Normal").to_string(),
+    }
+}
+
+/// `text` with every run of digits replaced by `#`.
+fn template(text: &str) -> String {
+    let mut template = String::new();
+    for c in text.trim().chars() {
+        if !c.is_ascii_digit() {
+            template.push(c);
+        } else if !template.ends_with('#') {
+            template.push('#');
+        }
+    }
+    template
+}
+
+/// Split a sample without page breaks before every paragraph of the most
+/// common numbered heading, e.g. "Practical 3".
+fn split_at_headings(lines: Vec<Line>) -> Vec<Vec<Line>> {
+    // Templates by count, in the order they first appear
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for line in &lines {
+        let numbered = line.text.chars().any(|c| c.is_ascii_digit());
+        if !numbered || line.is_code() || line.text.chars().count() > 40 {
+            continue;
+        }
+        let template = template(&line.text);
+        match counts.iter_mut().find(|(known, _)| *known == template) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((template, 1)),
+        }
+    }
+    let most = counts.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let heading = counts
+        .into_iter()
+        .find(|(_, count)| *count >= 2 && *count == most)
+        .map(|(template, _)| template);
+    let Some(heading) = heading else {
+        return vec![lines];
+    };
+
+    let mut pages = vec![Vec::new()];
+    for line in lines {
+        if template(&line.text) == heading {
+            new_page(&mut pages);
+        }
+        pages.last_mut().expect("there is always a page").push(line);
+    }
+    pages
+}
+
+/// The text of the heading opening every practical, with its number
+/// replaced by `{n}`, if the first paragraphs of most practicals share one.
+fn header_template(practicals: &[Vec<Line>]) -> Option<String> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for lines in practicals {
+        let first = &lines[0];
+        if first.is_code() || first.is_title(&SOLUTION_TITLES) || first.is_title(&OUTPUT_TITLES) {
+            continue;
+        }
+        *counts.entry(template(&first.text)).or_default() += 1;
+    }
+    let (template, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+    // A single practical can only be told to have a heading by its number
+    let repeated = if practicals.len() == 1 {
+        template.contains('#')
+    } else {
+        count * 2 > practicals.len()
+    };
+    repeated.then(|| template.replacen('#', "{n}", 1))
+}
+
+fn assign_roles(lines: Vec<Line>, has_header: bool) -> Practical {
+    #[derive(PartialEq, PartialOrd)]
+    enum Part {
+        Question,
+        Solution,
+        Output,
+    }
+
+    let mut practical = Practical::default();
+    let mut part = Part::Question;
+    let mut lines = lines.into_iter();
+    if has_header {
+        practical.header = lines.next();
+    }
+    for line in lines {
+        if part < Part::Solution && line.is_title(&SOLUTION_TITLES) {
+            practical.solution_title = Some(line);
+            part = Part::Solution;
+        } else if part < Part::Output && line.is_title(&OUTPUT_TITLES) {
+            practical.output_title = Some(line);
+            part = Part::Output;
+        } else if part == Part::Question && line.is_code() && !practical.question.is_empty() {
+            // Code without a title above it
+            practical.solution.push(line);
+            part = Part::Solution;
+        } else {
+            match part {
+                Part::Question => practical.question.push(line),
+                Part::Solution => practical.solution.push(line),
+                Part::Output => practical.output.push(line),
+            }
+        }
+    }
+    practical
+}
+
+/// Take the last paragraph of the practicals out of their output when most
+/// practicals end with the same text, e.g. "Teacher's signature: ______".
+fn find_footers(practicals: &mut [Practical]) {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for practical in practicals.iter() {
+        if let Some(last) = practical
+            .output
+            .last()
+            .filter(|_| practical.output.len() > 1)
+        {
+            *counts.entry(template(&last.text)).or_default() += 1;
+        }
+    }
+    let footer = counts
+        .into_iter()
+        .filter(|(_, count)| *count >= 2 && *count * 2 > practicals.len())
+        .max_by_key(|(_, count)| *count)
+        .map(|(template, _)| template);
+    let Some(footer) = footer else {
+        return;
+    };
+    for practical in practicals {
+        if practical.output.len() > 1
+            && practical
+                .output
+                .last()
+                .is_some_and(|last| template(&last.text) == footer)
+        {
+            practical.footer = practical.output.pop();
+        }
+    }
+}
+
+fn build_config(
+    practicals: &[Practical],
+    header: Option<String>,
+    notes: &mut Vec<String>,
+) -> DocumentConfig {
+    let mut config = DocumentConfig::default();
+    let count = practicals.len();
+    notes.insert(
+        0,
+        format!(
+            "found {} practical{}",
+            count,
+            if count == 1 { "" } else { "s" }
+        ),
+    );
+
+    let lines = |role: fn(&Practical) -> Vec<&Line>| -> Vec<&Line> {
+        practicals.iter().flat_map(role).collect()
+    };
+
+    let headers = lines(|p| p.header.iter().collect());
+    config.header = match header {
+        Some(text) => Some(paragraph(text, "[header]", &headers, notes)),
+        None => {
+            notes.push(
+                "found no heading repeated in every practical, so there is no [header]".to_string(),
+            );
+            None
+        }
+    };
+
+    let questions = lines(|p| p.question.iter().collect());
+    let firsts: Vec<&Line> = practicals
+        .iter()
+        .filter_map(|p| p.question.first())
+        .collect();
+    if questions.is_empty() {
+        notes.push("found no question text, kept the default [question]".to_string());
+    } else {
+        let text = question_text(&firsts);
+        config.question = paragraph(text, "[question]", &questions, notes);
+        if questions.iter().any(|line| line.pictures > 0) {
+            notes.push(
+                "questions hold pictures; list them with @image: in questions.txt".to_string(),
+            );
+        }
+    }
+
+    // `[headings]` prints the header as Heading 1 and the question below
+    // it as Heading 2, so those are left to it
+    let mut level = 1;
+    if let Some(header) = &mut config.header {
+        if header.style == "Heading1" {
+            header.style = Paragraph::default().style;
+        }
+        level = 2;
+    }
+    if config.question.style == format!("Heading{}", level) {
+        config.question.style = Paragraph::default().style;
+    }
+
+    let solution_titles = lines(|p| p.solution_title.iter().collect());
+    match most_common_text(&solution_titles) {
+        Some(text) => {
+            config.solution.title = paragraph(text, "[solution.title]", &solution_titles, notes)
+        }
+        None => notes.push("found no title above the code, kept \"Code:\"".to_string()),
+    }
+    let code = lines(|p| p.solution.iter().collect());
+    if code.is_empty() {
+        notes.push("found no code, kept the default [solution]".to_string());
+    } else {
+        config.solution.content = paragraph("{solution}", "[solution]", &code, notes);
+        if !code.iter().any(|line| line.is_code()) {
+            notes.push(format!(
+                "the code is not in a monospace font; it uses {}",
+                config
+                    .solution
+                    .content
+                    .font
+                    .clone()
+                    .unwrap_or_else(|| TextDefaults::default().font)
+            ));
+        }
+        if code.iter().filter(|line| line.colors > 1).count() * 2 > code.len() {
+            notes.push(
+                "the code is highlighted; create-docx highlights code from pygmentize's output, so only the plain color is kept".to_string(),
+            );
+        }
+        container(&mut config.solution.container, "[solution]", &code, notes);
+    }
+
+    let output_titles = lines(|p| p.output_title.iter().collect());
+    match most_common_text(&output_titles) {
+        Some(text) => {
+            config.output.title = paragraph(text, "[output.title]", &output_titles, notes)
+        }
+        None => notes.push("found no title above the output, kept \"Output:\"".to_string()),
+    }
+    let output = lines(|p| p.output.iter().collect());
+    let text_output: Vec<&Line> = output
+        .iter()
+        .copied()
+        .filter(|line| !line.text.trim().is_empty())
+        .collect();
+    if !text_output.is_empty() {
+        config.output.content = paragraph("{output}", "[output]", &text_output, notes);
+        container(
+            &mut config.output.container,
+            "[output]",
+            &text_output,
+            notes,
+        );
+    }
+    if output.iter().any(|line| line.pictures > 0) {
+        notes.push(
+            "the output is shown as pictures; [output.screenshot] renders it the same way"
+                .to_string(),
+        );
+    }
+    if output.is_empty() {
+        notes.push("found no output, kept the default [output]".to_string());
+    }
+
+    let footers = lines(|p| p.footer.iter().collect());
+    if let Some(text) = most_common_text(&footers) {
+        config.footer = Some(paragraph(text, "[footer]", &footers, notes));
+    }
+    config
+}
+
+/// Text of `[question]`: the label most questions start with, such as
+/// "Q) " or "Aim: ", followed by `{question}`.
+fn question_text(firsts: &[&Line]) -> String {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for line in firsts {
+        let template = template(&line.text);
+        // Up to the first ':', ')' or '.' of a short label, with the space
+        // after it
+        let end = template
+            .char_indices()
+            .take(16)
+            .find(|(_, c)| matches!(c, ':' | ')' | '.'))
+            .map(|(i, c)| i + c.len_utf8());
+        if let Some(end) = end.filter(|end| !template[*end..].trim().is_empty()) {
+            let space = if template[end..].starts_with(' ') {
+                " "
+            } else {
+                ""
+            };
+            *counts
+                .entry(format!("{}{}", &template[..end], space))
+                .or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| *count * 2 > firsts.len())
+        .max_by_key(|(_, count)| *count)
+        .map_or_else(
+            || "{question}".to_string(),
+            |(label, _)| format!("{}{{question}}", label.replacen('#', "{n}", 1)),
+        )
+}
+
+fn most_common_text(lines: &[&Line]) -> Option<String> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for line in lines {
+        *counts.entry(line.text.trim()).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(text, _)| text.to_string())
+}
+
+/// A paragraph with `text` and the formatting most of `lines` share, noting
+/// the formatting that differs between them.
+fn paragraph(
+    text: impl Into<String>,
+    role: &str,
+    lines: &[&Line],
+    notes: &mut Vec<String>,
+) -> Paragraph {
+    let mut paragraph = Paragraph {
+        text: text.into(),
+        ..Default::default()
+    };
+    if lines.is_empty() {
+        return paragraph;
+    }
+
+    // Code is often a single paragraph of many lines, so the formatting
+    // of most of the text counts rather than that of most paragraphs
+    let weight = |line: &Line| line.text.chars().count().max(1);
+    macro_rules! most_common {
+        ($field:ident, $what:expr, $show:expr) => {{
+            let mut counts: Vec<(_, usize)> = Vec::new();
+            for line in lines {
+                match counts
+                    .iter_mut()
+                    .find(|(value, _)| *value == line.format.$field)
+                {
+                    Some((_, count)) => *count += weight(line),
+                    None => counts.push((line.format.$field.clone(), weight(line))),
+                }
+            }
+            counts.sort_by(|a, b| b.1.cmp(&a.1));
+            if counts.len() > 1 {
+                let show = $show;
+                let found = counts
+                    .iter()
+                    .map(|(value, _)| show(value))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                notes.push(format!(
+                    "{} {} differs between paragraphs ({}), used {}",
+                    role,
+                    $what,
+                    found,
+                    show(&counts[0].0)
+                ));
+            }
+            counts.swap_remove(0).0
+        }};
+    }
+
+    // Formatting that matches `[defaults]` is left for it to set
+    let defaults = TextDefaults::default();
+    let yes_no = |value: &bool| if *value { "yes" } else { "no" }.to_string();
+    let size = most_common!(size, "size", |size: &usize| format!("{} pt", size));
+    paragraph.size = Some(size).filter(|size| *size != defaults.size);
+    paragraph.bold = Some(most_common!(bold, "bold", yes_no)).filter(|bold| *bold);
+    paragraph.italic = Some(most_common!(italic, "italic", yes_no)).filter(|italic| *italic);
+    let underline = most_common!(underline, "underline", yes_no);
+    paragraph.underline = Some(underline).filter(|underline| *underline);
+    let font = most_common!(font, "font", |font: &String| font.clone());
+    paragraph.font = Some(font).filter(|font| *font != defaults.font);
+    let color = most_common!(color, "color", |color: &String| color.clone());
+    paragraph.color = Some(color).filter(|color| color != "#000000");
+    let align = most_common!(align, "alignment", |align: &String| align.clone());
+    paragraph.align = Some(align).filter(|align| align != "left");
+    let style = most_common!(style, "style", |style: &String| style.clone());
+    if crate::schema::STYLES.contains(&style.as_str()) {
+        paragraph.style = style;
+    } else {
+        notes.push(format!(
+            "{} uses the style \"{}\", which create-docx does not have; its formatting is copied instead",
+            role, style
+        ));
+    }
+    paragraph
+}
+
+/// Enable `container` when most of `lines` sit in a table, as listings in
+/// a bordered box usually do.
+fn container(
+    container: &mut crate::utilities::Container,
+    role: &str,
+    lines: &[&Line],
+    notes: &mut Vec<String>,
+) {
+    let cells: Vec<&str> = lines
+        .iter()
+        .filter_map(|line| line.cell.as_deref())
+        .collect();
+    if cells.len() * 2 <= lines.len() {
+        return;
+    }
+    container.enabled = true;
+    if let Some(fill) = cells.iter().find(|fill| !fill.is_empty()) {
+        container.background = fill.to_string();
+    } else {
+        container.background = "#ffffff".to_string();
+    }
+    notes.push(format!(
+        "{} sits in a table, mapped to a container; check its border and padding",
+        role
+    ));
+}
+
+fn preview(text: &str) -> String {
+    let mut preview: String = text.chars().take(30).collect();
+    if text.chars().count() > 30 {
+        preview.push('…');
+    }
+    preview
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Content, Entry};
+
+    fn json(value: &impl serde::Serialize) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn inferred_config_round_trips() {
+        let config: DocumentConfig = toml::from_str(
+            r#"
+            [header]
+            text = "Experiment {n}"
+            size = 16
+            bold = true
+            align = "center"
+
+            [question]
+            text = "Aim: {question}"
+            size = 13
+
+            [solution]
+            text = "{solution}"
+            size = 10
+            font = "Courier New"
+
+            [solution.title]
+            text = "Program:"
+            bold = true
+
+            [output]
+            text = "{output}"
+
+            [output.title]
+            text = "Output:"
+            underline = true
+
+            [footer]
+            text = "Signature"
+            align = "right"
+            "#,
+        )
+        .unwrap();
+        let entries: Vec<Entry> = ["Print a greeting", "Add two numbers", "Reverse a string"]
+            .into_iter()
+            .enumerate()
+            .map(|(index, question)| {
+                Entry::new(index, question, ".py", "print('hi')\nprint(1)")
+                    .with_output(Content::text("hi\n1"))
+            })
+            .collect();
+        let mut docx = Vec::new();
+        config
+            .write_docx(&entries, std::io::Cursor::new(&mut docx))
+            .unwrap();
+
+        let inferred = infer_config(&docx).unwrap();
+        assert_eq!(inferred.notes, ["found 3 practicals"]);
+        let toml = inferred.to_toml("a generated record");
+        let inferred: DocumentConfig = toml::from_str(&toml).unwrap();
+        assert_eq!(json(&inferred.header), json(&config.header));
+        assert_eq!(json(&inferred.footer), json(&config.footer));
+        let pairs = [
+            (&inferred.question, &config.question),
+            (&inferred.solution.content, &config.solution.content),
+            (&inferred.solution.title, &config.solution.title),
+            // The output is printed in the terminal font, so only its
+            // title is compared
+            (&inferred.output.title, &config.output.title),
+        ];
+        for (inferred, written) in pairs {
+            assert_eq!(json(inferred), json(written));
+        }
+    }
+}
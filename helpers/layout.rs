@@ -0,0 +1,379 @@
+//! Fitting the lines of a code listing or program output to the page: tab
+//! expansion and what happens to lines wider than the text column.
+
+use docx_rs::{
+    BreakType, Paragraph, ParagraphChild, Run, RunChild, SpecialIndentType, Tab, TabValueType,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How the lines of a listing are fitted to the text width.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LineLayout {
+    /// What to do with lines wider than the text column.
+    #[serde(default)]
+    pub overflow: Overflow,
+    /// Marker printed at the start of a wrapped continuation line.
+    #[serde(default = "default_continuation")]
+    pub continuation: String,
+    /// Indentation of continuation lines, in characters.
+    #[serde(default = "default_hanging_indent")]
+    pub hanging_indent: usize,
+    /// Smallest font size in points `shrink` may use.
+    #[serde(default = "default_min_size")]
+    pub min_size: usize,
+    /// Distance between tab stops, in characters.
+    #[serde(default = "default_tab_width")]
+    pub tab_width: usize,
+    /// How tab characters are printed.
+    #[serde(default)]
+    pub tabs: TabMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Leave long lines to Word, which wraps them without a marker.
+    #[default]
+    None,
+    /// Break long lines, starting each continuation with a marker and a
+    /// hanging indent. A wrapped line keeps a single line number.
+    Wrap,
+    /// Shrink the font of the whole listing until the longest line fits,
+    /// down to `min_size`.
+    Shrink,
+    /// Cut long lines and end them with an ellipsis.
+    Truncate,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum TabMode {
+    /// Replace tabs with spaces up to the next tab stop.
+    #[default]
+    Spaces,
+    /// Keep tabs and give the paragraphs matching Word tab stops.
+    Stops,
+}
+
+fn default_continuation() -> String {
+    "\u{21aa} ".to_string()
+}
+
+fn default_hanging_indent() -> usize {
+    4
+}
+
+fn default_min_size() -> usize {
+    7
+}
+
+fn default_tab_width() -> usize {
+    4
+}
+
+impl Default for LineLayout {
+    fn default() -> Self {
+        LineLayout {
+            overflow: Overflow::default(),
+            continuation: default_continuation(),
+            hanging_indent: default_hanging_indent(),
+            min_size: default_min_size(),
+            tab_width: default_tab_width(),
+            tabs: TabMode::default(),
+        }
+    }
+}
+
+/// Width of a character of a monospace font, in twips, for a font size in
+/// half-points. Monospace glyphs are about 0.6 em wide.
+pub fn char_width(size: usize) -> usize {
+    size * 6
+}
+
+/// A piece of a run: one character, or anything else kept as is.
+enum Piece {
+    Char(char),
+    Other(RunChild),
+}
+
+impl LineLayout {
+    /// Fit `lines`, printed at `size` half-points, into `width` twips.
+    /// Returns the lines and the font size they ended up with.
+    pub fn apply(
+        &self,
+        lines: Vec<Paragraph>,
+        size: usize,
+        width: usize,
+    ) -> (Vec<Paragraph>, usize) {
+        let mut fitted = size;
+        if self.overflow == Overflow::Shrink {
+            let longest = lines
+                .iter()
+                .map(|line| self.columns(line))
+                .max()
+                .unwrap_or(0);
+            if longest * char_width(size) > width {
+                // `min_size` may exceed `size`, which is then kept
+                fitted = (width / char_width(longest))
+                    .max(self.min_size * 2)
+                    .min(size);
+            }
+        }
+
+        let columns = (width / char_width(fitted)).max(1);
+        let lines = lines
+            .into_iter()
+            .map(|line| {
+                let line = self.fit(line, columns, fitted);
+                if fitted == size {
+                    line
+                } else {
+                    resize(line, fitted)
+                }
+            })
+            .collect();
+        (lines, fitted)
+    }
+
+    /// Number of columns the line takes once tabs are expanded.
+    fn columns(&self, line: &Paragraph) -> usize {
+        let mut column = 0;
+        for piece in line_pieces(line) {
+            if let Piece::Char(ch) = piece {
+                column += self.advance(ch, column);
+            }
+        }
+        column
+    }
+
+    fn advance(&self, ch: char, column: usize) -> usize {
+        if ch == '\t' {
+            let tab_width = self.tab_width.max(1);
+            tab_width - column % tab_width
+        } else {
+            1
+        }
+    }
+
+    /// Expand tabs and wrap or truncate a line wider than `columns`.
+    fn fit(&self, mut line: Paragraph, columns: usize, size: usize) -> Paragraph {
+        let too_long = self.columns(&line) > columns;
+        let has_tab = line_pieces(&line)
+            .iter()
+            .any(|piece| matches!(piece, Piece::Char('\t')));
+        if !has_tab && (!too_long || self.overflow == Overflow::None) {
+            return line;
+        }
+
+        let marker: Vec<char> = self.continuation.chars().collect();
+        let mut limit = columns;
+        let mut column = 0;
+        let mut wrapped = false;
+        let mut truncated = false;
+
+        let mut children = Vec::new();
+        for child in std::mem::take(&mut line.children) {
+            let ParagraphChild::Run(original) = child else {
+                children.push(child);
+                continue;
+            };
+            let mut run = Run {
+                run_property: original.run_property.clone(),
+                children: Vec::new(),
+            };
+            let mut text = String::new();
+
+            for piece in pieces(&original) {
+                let ch = match piece {
+                    Piece::Char(ch) => ch,
+                    Piece::Other(child) => {
+                        if !truncated {
+                            run = flush(run, &mut text);
+                            run.children.push(child);
+                        }
+                        continue;
+                    }
+                };
+                if truncated {
+                    continue;
+                }
+
+                let advance = self.advance(ch, column);
+                match self.overflow {
+                    Overflow::Wrap if column > 0 && column + advance > limit => {
+                        run = flush(run, &mut text).add_break(BreakType::TextWrapping);
+                        text.extend(&marker);
+                        column = marker.len();
+                        limit = columns.saturating_sub(self.hanging_indent).max(column + 1);
+                        wrapped = true;
+                    }
+                    // Keep the last column for the ellipsis
+                    Overflow::Truncate if too_long && column + advance >= columns => {
+                        text.push('\u{2026}');
+                        truncated = true;
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                let advance = self.advance(ch, column);
+                if ch != '\t' {
+                    text.push(ch);
+                } else if self.tabs == TabMode::Stops {
+                    run = flush(run, &mut text).add_tab();
+                } else {
+                    text.extend(std::iter::repeat_n(' ', advance));
+                }
+                column += advance;
+            }
+
+            children.push(ParagraphChild::Run(Box::new(flush(run, &mut text))));
+        }
+        line.children = children;
+
+        if wrapped {
+            let indent = (self.hanging_indent * char_width(size)) as i32;
+            line = line.indent(
+                Some(indent),
+                Some(SpecialIndentType::Hanging(indent)),
+                None,
+                None,
+            );
+        }
+        if has_tab && self.tabs == TabMode::Stops {
+            let step = self.tab_width.max(1);
+            for stop in (step..columns).step_by(step) {
+                line = line.add_tab(
+                    Tab::new()
+                        .val(TabValueType::Left)
+                        .pos(stop * char_width(size)),
+                );
+            }
+        }
+        line
+    }
+}
+
+/// The pieces of all runs of a line. Other paragraph children are left out.
+fn line_pieces(line: &Paragraph) -> Vec<Piece> {
+    line.children
+        .iter()
+        .filter_map(|child| match child {
+            ParagraphChild::Run(run) => Some(pieces(run)),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+fn pieces(run: &Run) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    for child in &run.children {
+        match child {
+            RunChild::Text(text) => pieces.extend(unescape(&text.text).chars().map(Piece::Char)),
+            RunChild::Tab(_) => pieces.push(Piece::Char('\t')),
+            other => pieces.push(Piece::Other(other.clone())),
+        }
+    }
+    pieces
+}
+
+fn flush(run: Run, text: &mut String) -> Run {
+    if text.is_empty() {
+        return run;
+    }
+    run.add_text(std::mem::take(text))
+}
+
+fn resize(mut line: Paragraph, size: usize) -> Paragraph {
+    for child in &mut line.children {
+        if let ParagraphChild::Run(run) = child {
+            **run = std::mem::take(&mut **run).size(size);
+        }
+    }
+    line
+}
+
+/// Undo the XML escaping docx-rs applies when text is added to a run.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#xA;", "\n")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Paragraph {
+        Paragraph::new().add_run(Run::new().add_text(text))
+    }
+
+    /// The text of a line, with a line break for anything but text.
+    fn text(line: &Paragraph) -> String {
+        line_pieces(line)
+            .into_iter()
+            .map(|piece| match piece {
+                Piece::Char(ch) => ch,
+                Piece::Other(_) => '\n',
+            })
+            .collect()
+    }
+
+    fn layout(overflow: Overflow) -> LineLayout {
+        LineLayout {
+            overflow,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn shrink_fits_the_longest_line() {
+        let lines = vec![line(&"a".repeat(100)), line("short")];
+        let (lines, size) = layout(Overflow::Shrink).apply(lines, 20, 9000);
+        assert_eq!(size, 15);
+        assert_eq!(text(&lines[0]), "a".repeat(100));
+    }
+
+    #[test]
+    fn shrink_stops_at_min_size() {
+        let (_, size) = layout(Overflow::Shrink).apply(vec![line(&"a".repeat(100))], 20, 3000);
+        assert_eq!(size, 14);
+    }
+
+    #[test]
+    fn shrink_keeps_a_size_below_min_size() {
+        let layout = LineLayout {
+            min_size: 12,
+            ..layout(Overflow::Shrink)
+        };
+        let (_, size) = layout.apply(vec![line(&"a".repeat(100))], 20, 3000);
+        assert_eq!(size, 20);
+    }
+
+    #[test]
+    fn wrap_continues_with_marker() {
+        let (lines, size) =
+            layout(Overflow::Wrap).apply(vec![line(&"a".repeat(18))], 20, 10 * char_width(20));
+        assert_eq!(size, 20);
+        assert_eq!(text(&lines[0]), "aaaaaaaaaa\n\u{21aa} aaaa\n\u{21aa} aaaa");
+    }
+
+    #[test]
+    fn truncate_ends_with_ellipsis() {
+        let lines = vec![line(&"a".repeat(18)), line("short")];
+        let (lines, _) = layout(Overflow::Truncate).apply(lines, 20, 10 * char_width(20));
+        assert_eq!(text(&lines[0]), "aaaaaaaaa\u{2026}");
+        assert_eq!(text(&lines[1]), "short");
+    }
+
+    #[test]
+    fn tabs_expand_to_the_next_stop() {
+        let (lines, _) = layout(Overflow::None).apply(vec![line("ab\tc")], 20, 9000);
+        assert_eq!(text(&lines[0]), "ab  c");
+    }
+}
@@ -0,0 +1,52 @@
+//! Document generator behind `create-docx`: turns practical [`Entry`]s into a
+//! `.docx` record laid out by a [`DocumentConfig`].
+//!
+//! ```
+//! use helpers::{Content, DocumentConfig, Entry};
+//!
+//! let config = DocumentConfig::default();
+//! let entries = vec![
+//!     Entry::new(0, "Add two numbers", ".py", "print(2 + 3)").with_output(Content::text("5")),
+//!     Entry::new(1, "Greet the user", ".py", "print('hi')")
+//!         .with_output(Content::ansi("\u{1b}[32mhi\u{1b}[0m"))
+//!         .with_exit_status(0),
+//! ];
+//!
+//! let mut file = Vec::new();
+//! config.write_docx(&entries, &mut file)?;
+//! # Ok::<(), helpers::Error>(())
+//! ```
+//!
+//! Entries can also be read from the JSON produced by pfcreator with
+//! [`entry::parse_entries`] or streamed with [`entry::read_entries`], and
+//! rendered incrementally with a [`DocumentBuilder`].
+
+pub mod cache;
+pub mod check;
+pub mod entry;
+pub mod error;
+mod font;
+pub mod infer;
+pub mod layout;
+pub mod numbering;
+mod package;
+mod reference;
+pub mod schema;
+pub mod screenshot;
+pub mod update;
+pub mod utilities;
+
+pub use cache::{Cache, CacheReport};
+pub use entry::{
+    Code, Content, ContentType, Entry, ExpectedOutput, ImageFile, ProgramRun, SourceFile, Stream,
+    TranscriptChunk, Unit, ENTRY_VERSION,
+};
+pub use error::{EntryError, Error};
+pub use infer::{infer_config, InferredConfig};
+pub use screenshot::Screenshot;
+pub use update::{update_docx, UpdateReport};
+pub use utilities::{
+    write_docx, Block, CheckSummary, Container, Contents, DocumentBuilder, DocumentConfig,
+    FileListing, FileOrder, Headings, ImageLayout, LineNumbers, OutputCheck, Paragraph,
+    SectionWithTitle, StyleBorder, StyleDefinition, StyleKind, TextDefaults,
+};
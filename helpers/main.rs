@@ -1,101 +1,290 @@
-pub mod utilities;
+mod cli;
+mod watch;
 
-use crate::utilities::{create_document_from_config, DocumentConfig};
+use crate::cli::{BuildArgs, Cli, Command, InferArgs, OutputFormat};
+use clap::Parser;
+use helpers::{
+    entry, infer_config, numbering::NumberingFormat, schema, update_docx, Cache, DocumentBuilder,
+    DocumentConfig, UpdateReport,
+};
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::path::PathBuf;
-
-#[derive(serde::Deserialize)]
-pub struct ZigOutput {
-    question: String,
-    index: usize,
-    pub extension: String,
-    code: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    code_rtf: Option<String>,
-    output_rtf: String,
-}
+use std::io::{BufRead, BufReader, Write};
 
-fn get_full_dir_path() -> Result<PathBuf, Box<dyn Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: <program> <folder>");
-        std::process::exit(1);
-    }
+pub fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
 
-    let dir_path = &args[1];
-    let path = if std::path::Path::new(dir_path).is_absolute() {
-        PathBuf::from(dir_path)
-    } else {
-        let home = std::env::var("HOME").map_err(|_| "HOME environment variable not set")?;
-        PathBuf::from(home).join(dir_path)
-    };
+    match cli.command {
+        Some(Command::Schema) => {
+            println!("{}", schema::format_schema());
+            Ok(())
+        }
+        Some(Command::Watch(args)) => watch::watch(args),
+        Some(Command::InferConfig(args)) => infer(&args),
+        Some(Command::Build(args)) => build_once(&args),
+        None => build_once(&cli.build),
+    }
+}
 
-    Ok(path)
+fn build_once(args: &BuildArgs) -> Result<(), Box<dyn Error>> {
+    // Only the folder being watched is left to the watch, so that a stray
+    // variable never keeps other records from being built
+    if let Some(watched) = std::env::var_os(watch::WATCHING) {
+        let folder = args
+            .folder()
+            .ok()
+            .and_then(|f| std::fs::canonicalize(f).ok());
+        if folder.is_some() && folder == std::fs::canonicalize(&watched).ok() {
+            eprintln!(
+                "Leaving the build of {} to create-docx watch",
+                std::path::Path::new(&watched).display()
+            );
+            if args.verbose {
+                eprintln!(
+                    "Skipped the build, as {} names this folder; unset it to build here",
+                    watch::WATCHING
+                );
+            }
+            return Ok(());
+        }
+    }
+    build(args, None)
 }
 
-pub fn main() -> Result<(), Box<dyn Error>> {
-    let full_dir_path = get_full_dir_path().map_err(|e| {
+/// Build the record. When `keys` is given, it is filled with the cache key of
+/// every entry, by index.
+fn build(
+    args: &BuildArgs,
+    keys: Option<&mut BTreeMap<usize, String>>,
+) -> Result<(), Box<dyn Error>> {
+    let full_dir_path = args.folder().map_err(|e| {
         eprintln!("Error getting directory path: {}", e);
         e
     })?;
 
-    let path = full_dir_path.join("labfile.docx");
-
-    let toml_path = full_dir_path.join("format.toml");
-    let toml_string = std::fs::read_to_string(&toml_path).map_err(|e| {
-        eprintln!("Failed to read format.toml at {:?}: {}", toml_path, e);
-        format!("Failed to read format.toml: {}", e)
+    let toml_path = args.config_path(&full_dir_path)?;
+    let config = DocumentConfig::load(&toml_path).map_err(|e| {
+        eprintln!("{}", e);
+        e
     })?;
 
-    let config: DocumentConfig = toml::from_str(&toml_string).map_err(|e| {
-        eprintln!("Failed to parse format.toml: {}", e);
-        format!("Failed to parse format.toml: {}", e)
-    })?;
+    let path = args
+        .output_path(&full_dir_path, &config.variables)
+        .map_err(|e| {
+            eprintln!("Invalid output path: {}", e);
+            e
+        })?;
 
-    let json_path = full_dir_path.join("output.json");
-    let zig_out = std::fs::File::open(&json_path).map_err(|e| {
-        eprintln!("Failed to open output.json at {:?}: {}", json_path, e);
-        format!("Failed to open output.json: {}", e)
-    })?;
+    let json_path = args.input_path(&full_dir_path)?;
+    let reader: Box<dyn BufRead> = if args.reads_stdin() {
+        Box::new(std::io::stdin().lock())
+    } else {
+        let file = std::fs::File::open(&json_path).map_err(|e| {
+            eprintln!("Failed to open output.json at {:?}: {}", json_path, e);
+            format!("Failed to open output.json: {}", e)
+        })?;
+        Box::new(BufReader::new(file))
+    };
 
-    let mut json: Vec<ZigOutput> = serde_json::from_reader(zig_out).map_err(|e| {
+    let mut builder = DocumentBuilder::new(&config);
+    // The JSON model is always rendered in full
+    if !args.no_cache && args.format == OutputFormat::Docx {
+        let cache = Cache::open(args.cache_dir(&full_dir_path)?).map_err(|e| {
+            eprintln!("{}", e);
+            e
+        })?;
+        if args.clear_cache {
+            let removed = cache.clear()?;
+            if args.verbose {
+                println!("Cleared {} cached entries", removed);
+            }
+        }
+        builder = builder.with_cache(cache);
+    }
+    let mut skipped = 0;
+    entry::read_entries(
+        reader,
+        |mut entry| {
+            entry.resolve_files(&full_dir_path);
+            if args.verbose {
+                println!(
+                    "Entry {}: Index={}, Question={}, Highlighted code={}, Output type={:?}, Exit status={:?}",
+                    builder.len() + 1,
+                    entry.index,
+                    entry.question.chars().take(50).collect::<String>(),
+                    entry.code.highlighted.is_some(),
+                    entry.output.content_type,
+                    entry.exit_status
+                );
+            }
+            builder.add_entry(&entry);
+        },
+        |e| {
+            eprintln!("Skipping {}", e);
+            skipped += 1;
+        },
+    )
+    .map_err(|e| {
         eprintln!("Failed to parse output.json: {}", e);
         format!("Failed to parse output.json: {}", e)
     })?;
 
-    json.sort_by(|a, b| a.index.cmp(&b.index));
+    if !args.quiet {
+        println!("Creating document with {} entries", builder.len());
+        if skipped > 0 {
+            println!("Skipped {} lines that could not be parsed", skipped);
+        }
+    }
+
+    if let Some(keys) = keys {
+        *keys = builder.keys();
+    }
+
+    if let Some(cache) = builder.cache() {
+        let report = cache.report();
+        if !args.quiet {
+            let rebuilt = report
+                .rebuilt
+                .iter()
+                .map(|index| config.numbering.label(*index))
+                .collect::<Vec<_>>();
+            if rebuilt.is_empty() {
+                println!("Reused all {} entries from the cache", report.reused.len());
+            } else {
+                println!(
+                    "Reused {} entries from the cache, rebuilt {}: practical {}",
+                    report.reused.len(),
+                    rebuilt.len(),
+                    rebuilt.join(", ")
+                );
+            }
+        }
+        for (index, error) in &report.errors {
+            eprintln!(
+                "Failed to cache practical {}: {}",
+                config.numbering.label(*index),
+                error
+            );
+        }
+        if args.prune_cache {
+            let removed = cache.prune().map_err(|e| {
+                eprintln!("{}", e);
+                e
+            })?;
+            if !args.quiet {
+                println!("Pruned {} unused cache entries", removed);
+            }
+        }
+    }
 
-    println!("Creating document with {} entries", json.len());
+    builder.check().map_err(|e| {
+        eprintln!("{}", e);
+        e
+    })?;
 
-    // Debug each entry
-    for (i, entry) in json.iter().enumerate() {
+    let failed_checks = builder.failed_checks();
+    if !failed_checks.is_empty() && !args.quiet {
         println!(
-            "Entry {}: Index={}, Question={}, Has code_rtf={}, Has output_rtf={}",
-            i + 1,
-            entry.index,
-            entry.question.chars().take(50).collect::<String>(),
-            entry.code_rtf.is_some(),
-            !entry.output_rtf.is_empty()
+            "{} runs differ from the expected output: {}",
+            failed_checks.len(),
+            failed_checks.join(", ")
         );
     }
 
-    let docx = create_document_from_config(&config, json)?;
+    let unreadable_images = builder.unreadable_images();
+    if !unreadable_images.is_empty() && !args.quiet {
+        println!(
+            "{} images could not be read: {}",
+            unreadable_images.len(),
+            unreadable_images.join(", ")
+        );
+    }
 
-    // Create the file and write the docx content
-    let file = std::fs::File::create(&path).map_err(|e| {
-        eprintln!("Failed to create docx file at {:?}: {}", path, e);
-        format!("Failed to create docx file: {}", e)
-    })?;
+    let failed_screenshots = builder.failed_screenshots().to_vec();
+    if !failed_screenshots.is_empty() && !args.quiet {
+        println!(
+            "{} screenshots could not be drawn and were printed as text: {}",
+            failed_screenshots.len(),
+            failed_screenshots.join(", ")
+        );
+    }
 
-    docx.pack(file).map_err(|e| {
-        eprintln!("Failed to write docx document: {}", e);
-        format!("Failed to write docx document: {}", e)
-    })?;
+    let create = |path: &std::path::Path| {
+        std::fs::File::create(path).map_err(|e| {
+            eprintln!("Failed to create output file at {:?}: {}", path, e);
+            format!("Failed to create output file: {}", e)
+        })
+    };
 
-    std::fs::remove_file(&json_path).map_err(|e| {
-        eprintln!("Failed to cleanup output.json: {}", e);
-        format!("Failed to cleanup output.json: {}", e)
-    })?;
+    match args.format {
+        OutputFormat::Docx if args.update && path.exists() => {
+            // Render in memory so that the document is only overwritten once
+            // it has been updated
+            let existing = std::fs::read(&path).map_err(|e| {
+                eprintln!("Failed to read {:?}: {}", path, e);
+                format!("Failed to read the document to update: {}", e)
+            })?;
+            let mut rendered = Vec::new();
+            builder.write_docx(&mut rendered).map_err(|e| {
+                eprintln!("{}", e);
+                e
+            })?;
+            let (updated, report) = update_docx(&existing, &rendered).map_err(|e| {
+                eprintln!("{}", e);
+                e
+            })?;
+            create(&path)?.write_all(&updated).map_err(|e| {
+                eprintln!("Failed to write {:?}: {}", path, e);
+                format!("Failed to write the updated document: {}", e)
+            })?;
+            if !args.quiet {
+                println!("{}", describe_update(&report, &config.numbering));
+            }
+        }
+        OutputFormat::Docx => {
+            builder.write_docx(create(&path)?).map_err(|e| {
+                eprintln!("{}", e);
+                e
+            })?;
+        }
+        OutputFormat::Json => {
+            let docx = builder.finish();
+            create(&path)?
+                .write_all(docx.json().as_bytes())
+                .map_err(|e| {
+                    eprintln!("Failed to write JSON document: {}", e);
+                    format!("Failed to write JSON document: {}", e)
+                })?;
+        }
+    }
+
+    if !args.quiet {
+        println!("Wrote {}", path.display());
+    }
+
+    if args.strict && !failed_checks.is_empty() {
+        let e = helpers::Error::ChecksFailed(failed_checks);
+        eprintln!("{}", e);
+        return Err(e.into());
+    }
+    if args.strict && !failed_screenshots.is_empty() {
+        let e = helpers::Error::ScreenshotsFailed(failed_screenshots);
+        eprintln!("{}", e);
+        return Err(e.into());
+    }
+
+    if args.keep_intermediate {
+        return Ok(());
+    }
+
+    // Only clean up the intermediate file written by pfcreator, never an
+    // input the user pointed us at
+    if args.input.is_none() {
+        std::fs::remove_file(&json_path).map_err(|e| {
+            eprintln!("Failed to cleanup output.json: {}", e);
+            format!("Failed to cleanup output.json: {}", e)
+        })?;
+    }
 
     // Remove output_rtf folder if it exists
     let output_rtf_path = full_dir_path.join("output_rtf");
@@ -108,3 +297,60 @@ pub fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+fn infer(args: &InferArgs) -> Result<(), Box<dyn Error>> {
+    let sample = std::fs::read(&args.sample).map_err(|e| {
+        eprintln!("Failed to read {:?}: {}", args.sample, e);
+        e
+    })?;
+    let inferred = infer_config(&sample).map_err(|e| {
+        eprintln!("{}", e);
+        e
+    })?;
+    let name = args
+        .sample
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let toml = inferred.to_toml(&name);
+
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, &toml).map_err(|e| {
+                eprintln!("Failed to write {:?}: {}", path, e);
+                e
+            })?;
+            println!("Wrote {}", path.display());
+        }
+        None => print!("{}", toml),
+    }
+    for note in &inferred.notes {
+        eprintln!("Note: {}", note);
+    }
+    Ok(())
+}
+
+fn describe_update(report: &UpdateReport, numbering: &NumberingFormat) -> String {
+    let list = |indexes: &[usize]| {
+        indexes
+            .iter()
+            .map(|index| numbering.label(*index))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut parts = vec![format!("kept {} practicals", report.kept.len())];
+    if !report.replaced.is_empty() {
+        parts.push(format!("replaced practical {}", list(&report.replaced)));
+    }
+    if !report.added.is_empty() {
+        parts.push(format!("added practical {}", list(&report.added)));
+    }
+    let mut summary = format!("Updated the document: {}", parts.join(", "));
+    if !report.orphaned.is_empty() {
+        summary.push_str(&format!(
+            "\nPractical {} is no longer in the entries and was left in the document",
+            list(&report.orphaned)
+        ));
+    }
+    summary
+}
@@ -0,0 +1,326 @@
+//! How practical numbers are printed: the `[numbering]` table of
+//! `format.toml` and the filters number placeholders take.
+//!
+//! A number placeholder such as `{n}` may be followed by filters separated
+//! by `|`, which override `[numbering]` for that placeholder:
+//! `{n|upper-roman}`, `{n|pad:3}`, `{n|start:13}`, `{n|prefix:P-|suffix:)}`.
+
+use std::ops::Range;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How practical numbers are printed wherever `{n}` appears: headings,
+/// the check summary, captions and screenshot titles.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NumberingFormat {
+    /// Number of the first practical, e.g. 13 for a record that continues
+    /// one ending at practical 12.
+    #[serde(default = "default_start")]
+    pub start: usize,
+    /// How the number is written.
+    #[serde(default)]
+    pub style: NumberStyle,
+    /// Least number of digits of a decimal number, padded with zeros.
+    #[serde(default)]
+    pub width: usize,
+    /// Text printed before the number.
+    #[serde(default)]
+    pub prefix: String,
+    /// Text printed after the number.
+    #[serde(default)]
+    pub suffix: String,
+}
+
+/// How a number is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum NumberStyle {
+    /// 1, 2, 3
+    #[default]
+    Decimal,
+    /// i, ii, iii
+    LowerRoman,
+    /// I, II, III
+    UpperRoman,
+    /// a, b, c, and after z, aa, bb, cc as Word counts
+    LowerLetter,
+    /// A, B, C
+    UpperLetter,
+}
+
+fn default_start() -> usize {
+    1
+}
+
+impl Default for NumberingFormat {
+    fn default() -> Self {
+        NumberingFormat {
+            start: default_start(),
+            style: NumberStyle::default(),
+            width: 0,
+            prefix: String::new(),
+            suffix: String::new(),
+        }
+    }
+}
+
+impl NumberingFormat {
+    /// The number of the `position`th item, counted from 0.
+    pub fn number(&self, position: usize) -> usize {
+        self.start + position
+    }
+
+    /// The `position`th item's number, written in this format.
+    ///
+    /// ```
+    /// use helpers::numbering::{NumberStyle, NumberingFormat};
+    ///
+    /// let numbering = NumberingFormat {
+    ///     start: 13,
+    ///     style: NumberStyle::UpperRoman,
+    ///     suffix: ".".to_string(),
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(numbering.format(0), "XIII.");
+    /// ```
+    pub fn format(&self, position: usize) -> String {
+        let number = self.number(position);
+        let written = match self.style {
+            // Zero has no roman numeral or letter
+            _ if number == 0 => None,
+            NumberStyle::Decimal => None,
+            NumberStyle::LowerRoman => Some(roman(number).to_lowercase()),
+            NumberStyle::UpperRoman => Some(roman(number)),
+            NumberStyle::LowerLetter => Some(letter(number, b'a')),
+            NumberStyle::UpperLetter => Some(letter(number, b'A')),
+        };
+        let written = written.unwrap_or_else(|| format!("{:0width$}", number, width = self.width));
+        format!("{}{}{}", self.prefix, written, self.suffix)
+    }
+
+    /// The `position`th item's number in this style, without the prefix,
+    /// suffix and padding, as progress messages print it.
+    pub fn label(&self, position: usize) -> String {
+        NumberingFormat {
+            width: 0,
+            prefix: String::new(),
+            suffix: String::new(),
+            ..self.clone()
+        }
+        .format(position)
+    }
+
+    /// This format changed by `filters`, the `|`-separated filters of a
+    /// placeholder, or `None` if one of them is unknown.
+    pub(crate) fn filtered(&self, filters: &str) -> Option<NumberingFormat> {
+        let mut format = self.clone();
+        for filter in filters.split('|').filter(|filter| !filter.is_empty()) {
+            let (name, argument) = filter.split_once(':').unwrap_or((filter, ""));
+            match name.trim() {
+                "decimal" => format.style = NumberStyle::Decimal,
+                "lower-roman" => format.style = NumberStyle::LowerRoman,
+                "upper-roman" => format.style = NumberStyle::UpperRoman,
+                "lower-letter" => format.style = NumberStyle::LowerLetter,
+                "upper-letter" => format.style = NumberStyle::UpperLetter,
+                "pad" => format.width = argument.trim().parse().ok()?,
+                "start" => format.start = argument.trim().parse().ok()?,
+                "prefix" => format.prefix = argument.to_string(),
+                "suffix" => format.suffix = argument.to_string(),
+                _ => return None,
+            }
+        }
+        Some(format)
+    }
+
+    /// Word's `w:numFmt` for the style, as close as Word comes: it pads
+    /// decimal numbers to two digits at most.
+    pub(crate) fn word_format(&self) -> &'static str {
+        match self.style {
+            NumberStyle::Decimal if self.width > 1 => "decimalZero",
+            NumberStyle::Decimal => "decimal",
+            NumberStyle::LowerRoman => "lowerRoman",
+            NumberStyle::UpperRoman => "upperRoman",
+            NumberStyle::LowerLetter => "lowerLetter",
+            NumberStyle::UpperLetter => "upperLetter",
+        }
+    }
+}
+
+/// The first `{name}` placeholder in `text`, with or without filters: its
+/// span and the filters, without the leading `|`.
+pub(crate) fn find_placeholder<'t>(text: &'t str, name: &str) -> Option<(Range<usize>, &'t str)> {
+    let open = format!("{{{}", name);
+    let mut from = 0;
+    while let Some(found) = text[from..].find(&open) {
+        let start = from + found;
+        let rest = &text[start + open.len()..];
+        if rest.starts_with('}') {
+            return Some((start..start + open.len() + 1, ""));
+        }
+        if let Some(filters) = rest.strip_prefix('|') {
+            if let Some(end) = filters.find('}') {
+                return Some((start..start + open.len() + 2 + end, &filters[..end]));
+            }
+        }
+        from = start + open.len();
+    }
+    None
+}
+
+/// `text` with every `{name}` placeholder, filtered or not, replaced by the
+/// number of the `position`th item in `format`. A placeholder with an
+/// unknown filter is left as it is.
+pub(crate) fn replace_number(
+    text: &str,
+    name: &str,
+    position: usize,
+    format: &NumberingFormat,
+) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((span, filters)) = find_placeholder(rest, name) {
+        replaced.push_str(&rest[..span.start]);
+        match format.filtered(filters) {
+            Some(format) => replaced.push_str(&format.format(position)),
+            None => replaced.push_str(&rest[span.clone()]),
+        }
+        rest = &rest[span.end..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+fn roman(mut number: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut written = String::new();
+    for (value, numeral) in NUMERALS {
+        while number >= value {
+            written.push_str(numeral);
+            number -= value;
+        }
+    }
+    written
+}
+
+/// `number` as letters the way Word counts them: a to z, then aa, bb and
+/// so on.
+fn letter(number: usize, first: u8) -> String {
+    let letter = (first + ((number - 1) % 26) as u8) as char;
+    letter.to_string().repeat((number - 1) / 26 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled(style: NumberStyle) -> NumberingFormat {
+        NumberingFormat {
+            style,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn roman_numerals() {
+        let written: Vec<String> = [1, 4, 9, 14, 40, 90, 400, 1994]
+            .into_iter()
+            .map(roman)
+            .collect();
+        assert_eq!(
+            written,
+            ["I", "IV", "IX", "XIV", "XL", "XC", "CD", "MCMXCIV"]
+        );
+    }
+
+    #[test]
+    fn letters_repeat_after_z() {
+        assert_eq!(letter(1, b'a'), "a");
+        assert_eq!(letter(26, b'a'), "z");
+        assert_eq!(letter(27, b'a'), "aa");
+        assert_eq!(letter(28, b'A'), "BB");
+        assert_eq!(letter(53, b'a'), "aaa");
+    }
+
+    #[test]
+    fn formats_in_each_style() {
+        assert_eq!(styled(NumberStyle::Decimal).format(11), "12");
+        assert_eq!(styled(NumberStyle::LowerRoman).format(11), "xii");
+        assert_eq!(styled(NumberStyle::UpperRoman).format(11), "XII");
+        assert_eq!(styled(NumberStyle::LowerLetter).format(11), "l");
+        assert_eq!(styled(NumberStyle::UpperLetter).format(11), "L");
+    }
+
+    #[test]
+    fn zero_falls_back_to_decimal() {
+        let numbering = NumberingFormat {
+            start: 0,
+            width: 2,
+            ..styled(NumberStyle::UpperRoman)
+        };
+        assert_eq!(numbering.format(0), "00");
+        assert_eq!(numbering.format(1), "I");
+    }
+
+    #[test]
+    fn label_leaves_out_prefix_suffix_and_padding() {
+        let numbering = NumberingFormat {
+            start: 13,
+            width: 3,
+            prefix: "P-".to_string(),
+            suffix: ".".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(numbering.format(0), "P-013.");
+        assert_eq!(numbering.label(0), "13");
+    }
+
+    #[test]
+    fn filters_override_the_format() {
+        let numbering = NumberingFormat::default();
+        let filtered = numbering
+            .filtered("upper-roman|start:13|prefix:P-|suffix:)")
+            .unwrap();
+        assert_eq!(filtered.format(0), "P-XIII)");
+        assert_eq!(numbering.filtered("pad:3").unwrap().format(4), "005");
+        assert_eq!(numbering.filtered(""), Some(numbering.clone()));
+        assert_eq!(numbering.filtered("pad:x"), None);
+        assert_eq!(numbering.filtered("shout"), None);
+    }
+
+    #[test]
+    fn placeholders_are_found_with_and_without_filters() {
+        assert_eq!(find_placeholder("Task {n}", "n"), Some((5..8, "")));
+        assert_eq!(
+            find_placeholder("{name} {n|pad:2}", "n"),
+            Some((7..16, "pad:2"))
+        );
+        assert_eq!(find_placeholder("{n_in_unit}", "n"), None);
+        assert_eq!(find_placeholder("{n|pad:2", "n"), None);
+    }
+
+    #[test]
+    fn numbers_are_replaced() {
+        let numbering = styled(NumberStyle::LowerLetter);
+        assert_eq!(
+            replace_number("{n}, {n|decimal} and {n_in_unit}", "n", 2, &numbering),
+            "c, 3 and {n_in_unit}"
+        );
+        // An unknown filter leaves the placeholder as typed
+        assert_eq!(replace_number("{n|shout}", "n", 0, &numbering), "{n|shout}");
+    }
+}
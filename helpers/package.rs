@@ -0,0 +1,342 @@
+//! Reading and writing the files of a `.docx`, for the changes docx-rs
+//! cannot make, such as updating a record in place or taking the styles of
+//! a reference document.
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Write};
+use std::ops::Range;
+
+/// The files of a `.docx`, in order.
+pub(crate) struct Package {
+    pub(crate) files: Vec<(String, Vec<u8>)>,
+}
+
+impl Package {
+    pub(crate) fn read(bytes: &[u8]) -> Result<Self, String> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+        let mut files = Vec::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(|e| e.to_string())?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).map_err(|e| e.to_string())?;
+            files.push((file.name().to_string(), data));
+        }
+        Ok(Package { files })
+    }
+
+    pub(crate) fn file(&self, name: &str) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|(file, _)| file == name)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    pub(crate) fn text(&self, name: &str) -> Result<String, String> {
+        let data = self
+            .file(name)
+            .ok_or_else(|| format!("{} is missing, is it a .docx file?", name))?;
+        Ok(String::from_utf8_lossy(data).into_owned())
+    }
+
+    pub(crate) fn set(&mut self, name: &str, data: Vec<u8>) {
+        match self.files.iter_mut().find(|(file, _)| file == name) {
+            Some((_, old)) => *old = data,
+            None => self.files.push((name.to_string(), data)),
+        }
+    }
+
+    pub(crate) fn write(&self) -> Result<Vec<u8>, String> {
+        let invalid = |e: zip::result::ZipError| e.to_string();
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, data) in &self.files {
+            writer.start_file(name, options).map_err(invalid)?;
+            writer.write_all(data).map_err(|e| e.to_string())?;
+        }
+        Ok(writer.finish().map_err(invalid)?.into_inner())
+    }
+
+    /// Declare the content type of files with `extension`, unless it is.
+    pub(crate) fn add_default_type(&mut self, extension: &str, content_type: &str) {
+        let Ok(mut types) = self.text("[Content_Types].xml") else {
+            return;
+        };
+        if types.contains(&format!("Extension=\"{}\"", extension)) {
+            return;
+        }
+        if let Some(end) = types.rfind("</Types>") {
+            types.insert_str(
+                end,
+                &format!(
+                    r#"<Default Extension="{}" ContentType="{}" />"#,
+                    extension, content_type
+                ),
+            );
+            self.set("[Content_Types].xml", types.into_bytes());
+        }
+    }
+
+    /// Declare the content type of the file `part`, e.g. `/word/numbering.xml`,
+    /// unless it is.
+    pub(crate) fn add_override_type(&mut self, part: &str, content_type: &str) {
+        let Ok(mut types) = self.text("[Content_Types].xml") else {
+            return;
+        };
+        if types.contains(&format!("PartName=\"{}\"", part)) {
+            return;
+        }
+        if let Some(end) = types.rfind("</Types>") {
+            types.insert_str(
+                end,
+                &format!(
+                    r#"<Override PartName="{}" ContentType="{}" />"#,
+                    part, content_type
+                ),
+            );
+            self.set("[Content_Types].xml", types.into_bytes());
+        }
+    }
+}
+
+/// Every tag in `xml`, with its position.
+pub(crate) fn tags(xml: &str) -> Vec<(usize, &str)> {
+    let bytes = xml.as_bytes();
+    let mut tags = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = xml[position..].find('<') {
+        let start = position + offset;
+        let mut end = start + 1;
+        let mut quote = None;
+        while end < bytes.len() {
+            match (bytes[end], quote) {
+                (b'"' | b'\'', None) => quote = Some(bytes[end]),
+                (byte, Some(open)) if byte == open => quote = None,
+                (b'>', None) => break,
+                _ => {}
+            }
+            end += 1;
+        }
+        let end = (end + 1).min(xml.len());
+        tags.push((start, &xml[start..end]));
+        position = end;
+    }
+    tags
+}
+
+/// Whether `tag` opens an element called `name`.
+pub(crate) fn is_tag(tag: &str, name: &str) -> bool {
+    tag.strip_prefix('<')
+        .and_then(|tag| tag.strip_prefix(name))
+        .and_then(|rest| rest.chars().next())
+        .is_some_and(|next| matches!(next, ' ' | '/' | '>'))
+}
+
+pub(crate) fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!(" {}=\"", name);
+    let start = tag.find(&pattern)? + pattern.len();
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
+
+pub(crate) fn set_attribute(tag: &str, name: &str, value: &str) -> String {
+    let pattern = format!(" {}=\"", name);
+    let Some(start) = tag.find(&pattern).map(|start| start + pattern.len()) else {
+        return tag.to_string();
+    };
+    let end = tag[start..].find('"').map_or(tag.len(), |end| start + end);
+    format!("{}{}{}", &tag[..start], value, &tag[end..])
+}
+
+/// The elements called `name` in `xml`, each from its opening tag to its
+/// closing one, which must not be nested.
+pub(crate) fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    element_spans(xml, name)
+        .into_iter()
+        .map(|span| &xml[span])
+        .collect()
+}
+
+/// Where in `xml` each of the elements [`elements`] finds is.
+pub(crate) fn element_spans(xml: &str, name: &str) -> Vec<Range<usize>> {
+    let tags = tags(xml);
+    let close = format!("</{}>", name);
+    let mut spans = Vec::new();
+    for (i, &(start, tag)) in tags.iter().enumerate() {
+        if !is_tag(tag, name) {
+            continue;
+        }
+        let end = if tag.ends_with("/>") {
+            start + tag.len()
+        } else {
+            tags[i..]
+                .iter()
+                .find(|(_, tag)| tag.starts_with(&close))
+                .map_or(start + tag.len(), |(end, tag)| end + tag.len())
+        };
+        spans.push(start..end);
+    }
+    spans
+}
+
+/// Add the styles of `from`, a `styles.xml`, that `styles` lacks to it.
+pub(crate) fn add_missing_styles(styles: &mut String, from: &str) {
+    let missing: String = elements(from, "w:style")
+        .into_iter()
+        .filter(|style| {
+            attribute(style, "w:styleId")
+                .is_some_and(|id| !styles.contains(&format!("w:styleId=\"{}\"", id)))
+        })
+        .collect();
+    if let Some(end) = styles.rfind("</w:styles>") {
+        styles.insert_str(end, &missing);
+    }
+}
+
+/// The ids of the lists numbering paragraphs in `xml`, in order.
+pub(crate) fn numbering_ids(xml: &str) -> Vec<&str> {
+    let mut ids = Vec::new();
+    for (_, tag) in tags(xml) {
+        if !is_tag(tag, "w:numId") {
+            continue;
+        }
+        // List 0 turns numbering off
+        match attribute(tag, "w:val") {
+            Some(id) if id != "0" && !ids.contains(&id) => ids.push(id),
+            _ => {}
+        }
+    }
+    ids
+}
+
+/// Copy the lists `ids` of `from`, a `numbering.xml`, to `numbering` under
+/// ids it does not use, returning the new id of each.
+pub(crate) fn add_numbering(
+    numbering: &mut String,
+    from: &str,
+    ids: &[&str],
+) -> BTreeMap<String, String> {
+    let next = |name: &str, attribute_name: &str| {
+        tags(numbering)
+            .into_iter()
+            .filter(|(_, tag)| is_tag(tag, name))
+            .filter_map(|(_, tag)| attribute(tag, attribute_name)?.parse::<usize>().ok())
+            .max()
+            .map_or(1, |id| id + 1)
+    };
+    let mut next_list = next("w:num", "w:numId");
+    let mut next_abstract = next("w:abstractNum", "w:abstractNumId");
+
+    let lists = elements(from, "w:num");
+    let abstracts = elements(from, "w:abstractNum");
+    let mut added_lists = String::new();
+    let mut added_abstracts = String::new();
+    let mut renumbered = BTreeMap::new();
+    for &id in ids {
+        let Some(list) = lists
+            .iter()
+            .find(|list| attribute(list, "w:numId") == Some(id))
+        else {
+            continue;
+        };
+        let Some(definition) = tags(list)
+            .into_iter()
+            .find(|(_, tag)| is_tag(tag, "w:abstractNumId"))
+            .and_then(|(_, tag)| attribute(tag, "w:val"))
+            .and_then(|abstract_id| {
+                abstracts.iter().find(|definition| {
+                    attribute(definition, "w:abstractNumId") == Some(abstract_id)
+                })
+            })
+        else {
+            continue;
+        };
+
+        let abstract_id = next_abstract.to_string();
+        next_abstract += 1;
+        added_abstracts.push_str(&set_attribute(definition, "w:abstractNumId", &abstract_id));
+        let list = set_attribute(list, "w:numId", &next_list.to_string());
+        let list = match tags(&list)
+            .into_iter()
+            .find(|(_, tag)| is_tag(tag, "w:abstractNumId"))
+        {
+            Some((start, tag)) => format!(
+                "{}{}{}",
+                &list[..start],
+                set_attribute(tag, "w:val", &abstract_id),
+                &list[start + tag.len()..]
+            ),
+            None => list,
+        };
+        added_lists.push_str(&list);
+        renumbered.insert(id.to_string(), next_list.to_string());
+        next_list += 1;
+    }
+
+    // Definitions come before the lists using them
+    let end = numbering.rfind("</w:numbering>").unwrap_or(numbering.len());
+    let lists_end = element_spans(numbering, "w:num")
+        .last()
+        .map_or(end, |list| list.end);
+    let first_list = tags(numbering)
+        .into_iter()
+        .find(|(_, tag)| is_tag(tag, "w:num"))
+        .map_or(end, |(start, _)| start);
+    numbering.insert_str(lists_end, &added_lists);
+    numbering.insert_str(first_list, &added_abstracts);
+    renumbered
+}
+
+/// `xml` with its paragraphs numbered by the lists in `ids` numbered by
+/// their new ids instead.
+pub(crate) fn renumber_lists(xml: &str, ids: &BTreeMap<String, String>) -> String {
+    let mut renumbered = String::with_capacity(xml.len());
+    let mut position = 0;
+    for (start, tag) in tags(xml) {
+        renumbered.push_str(&xml[position..start]);
+        match attribute(tag, "w:val").and_then(|id| ids.get(id)) {
+            Some(id) if is_tag(tag, "w:numId") => {
+                renumbered.push_str(&set_attribute(tag, "w:val", id))
+            }
+            _ => renumbered.push_str(tag),
+        }
+        position = start + tag.len();
+    }
+    renumbered.push_str(&xml[position..]);
+    renumbered
+}
+
+/// Elements of `settings.xml` that come after `<w:updateFields>`.
+const AFTER_UPDATE_FIELDS: [&str; 9] = [
+    "w:hdrShapeDefaults",
+    "w:footnotePr",
+    "w:endnotePr",
+    "w:compat",
+    "w:docVars",
+    "w:rsids",
+    "m:mathPr",
+    "w:attachedSchema",
+    "w:themeFontLang",
+];
+
+/// `settings`, a `settings.xml`, asking Word to update the fields of the
+/// document, such as a table of contents, when it opens it.
+pub(crate) fn update_fields(settings: &str) -> String {
+    let tags = tags(settings);
+    if tags.iter().any(|(_, tag)| is_tag(tag, "w:updateFields")) {
+        return settings.to_string();
+    }
+    let position = tags
+        .iter()
+        .find(|(_, tag)| AFTER_UPDATE_FIELDS.iter().any(|name| is_tag(tag, name)))
+        .map(|&(start, _)| start)
+        .or_else(|| settings.rfind("</w:settings>"));
+    let mut updated = settings.to_string();
+    if let Some(position) = position {
+        updated.insert_str(position, r#"<w:updateFields w:val="true" />"#);
+    }
+    updated
+}
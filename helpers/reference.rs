@@ -0,0 +1,326 @@
+//! Taking the look of a record from a reference document, as pandoc does
+//! with `--reference-doc`.
+//!
+//! The record keeps its body but gets the reference's styles (keeping its
+//! own where the reference has no style of that name), numbering, theme,
+//! font table, page setup and headers and footers.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::package::{
+    add_missing_styles, add_numbering, attribute, elements, numbering_ids, renumber_lists,
+    set_attribute, tags, Package,
+};
+
+const RELATIONSHIPS: &str = "word/_rels/document.xml.rels";
+
+/// Parts of the reference that replace the record's, by relationship type.
+const REPLACED: [(&str, &str); 3] = [
+    ("numbering", "word/numbering.xml"),
+    ("theme", "word/theme/theme1.xml"),
+    ("fontTable", "word/fontTable.xml"),
+];
+
+/// A relationship of the reference's `document.xml`.
+struct Relationship {
+    id: String,
+    /// The full relationship type URI.
+    kind: String,
+    /// Path of the target in the package.
+    path: String,
+}
+
+impl Relationship {
+    fn is(&self, kind: &str) -> bool {
+        self.kind.ends_with(&format!("/{}", kind))
+    }
+}
+
+/// The `.docx` in `docx` with the styles, numbering, theme, fonts, page setup
+/// and headers and footers of the `.docx` in `reference`.
+pub(crate) fn apply(docx: &[u8], reference: &[u8]) -> Result<Vec<u8>, String> {
+    let mut package = Package::read(docx)?;
+    let reference = Package::read(reference)?;
+    let relationships = relationships(&reference.text(RELATIONSHIPS)?, "word");
+    let mut rels = package.text(RELATIONSHIPS)?;
+
+    if let Some(styles) = relationships.iter().find(|rel| rel.is("styles")) {
+        // Styles the record uses that the reference does not define keep
+        // the record's definition
+        let mut merged = reference.text(&styles.path)?;
+        if let Ok(own) = package.text("word/styles.xml") {
+            add_missing_styles(&mut merged, &own);
+        }
+        package.set("word/styles.xml", merged.into_bytes());
+    }
+
+    for (kind, name) in REPLACED {
+        let Some(part) = relationships.iter().find(|rel| rel.is(kind)) else {
+            continue;
+        };
+        let own = package.text(name).ok();
+        copy(&reference, &part.path, &mut package, name)?;
+        if let (Some(own), "numbering") = (own, kind) {
+            keep_lists(&mut package, &own)?;
+        }
+        let target = name.trim_start_matches("word/");
+        if !rels.contains(&format!("/relationships/{}\"", kind)) {
+            add_relationship(
+                &mut rels,
+                &format!("rIdReference{}", kind),
+                &part.kind,
+                target,
+            );
+        }
+    }
+
+    // Headers and footers, under new names and ids for the page setup to
+    // refer to
+    let mut ids = BTreeMap::new();
+    for part in relationships
+        .iter()
+        .filter(|rel| rel.is("header") || rel.is("footer"))
+    {
+        let name = renamed(&part.path);
+        copy(&reference, &part.path, &mut package, &name)?;
+        let id = format!("rIdReference{}", ids.len() + 1);
+        add_relationship(&mut rels, &id, &part.kind, name.trim_start_matches("word/"));
+        ids.insert(part.id.clone(), id);
+    }
+    package.set(RELATIONSHIPS, rels.into_bytes());
+
+    let reference_document = reference.text("word/document.xml")?;
+    if let Some(section) = body_section(&reference_document) {
+        let mut remapped = String::new();
+        let mut position = 0;
+        for (start, tag) in tags(section) {
+            remapped.push_str(&section[position..start]);
+            match attribute(tag, "r:id").and_then(|id| ids.get(id)) {
+                Some(id) => remapped.push_str(&set_attribute(tag, "r:id", id)),
+                None => remapped.push_str(tag),
+            }
+            position = start + tag.len();
+        }
+        remapped.push_str(&section[position..]);
+
+        let mut document = package.text("word/document.xml")?;
+        if let Some(own) = body_section_span(&document) {
+            document.replace_range(own, &remapped);
+            package.set("word/document.xml", document.into_bytes());
+        }
+    }
+
+    // Different headers on even pages are switched on in the settings
+    let even_and_odd = reference
+        .text("word/settings.xml")
+        .is_ok_and(|settings| !elements(&settings, "w:evenAndOddHeaders").is_empty());
+    if even_and_odd {
+        if let Ok(mut settings) = package.text("word/settings.xml") {
+            if let Some(start) = tags(&settings)
+                .into_iter()
+                .find(|(_, tag)| tag.starts_with("<w:settings"))
+                .map(|(start, tag)| start + tag.len())
+            {
+                settings.insert_str(start, "<w:evenAndOddHeaders />");
+                package.set("word/settings.xml", settings.into_bytes());
+            }
+        }
+    }
+
+    package.write()
+}
+
+/// Width between the margins of the last page of the `.docx` in
+/// `reference`, in twips, or `None` when it sets no page size.
+pub(crate) fn text_width(reference: &[u8]) -> Option<usize> {
+    let document = Package::read(reference)
+        .ok()?
+        .text("word/document.xml")
+        .ok()?;
+    let section = body_section(&document)?;
+    let twips = |element: &str, name: &str| -> Option<usize> {
+        let tag = elements(section, element).first().copied()?;
+        // Word writes some measures as decimals
+        attribute(tag, name)?
+            .parse::<f64>()
+            .ok()
+            .map(|value| value as usize)
+    };
+    let page = twips("w:pgSz", "w:w")?;
+    let margins = ["w:left", "w:right", "w:gutter"]
+        .iter()
+        .filter_map(|name| twips("w:pgMar", name))
+        .sum::<usize>();
+    Some(page.saturating_sub(margins)).filter(|width| *width > 0)
+}
+
+/// Add the lists the record numbers paragraphs with, such as practical
+/// headings, from `own`, its `numbering.xml`, to the reference's numbering.
+fn keep_lists(package: &mut Package, own: &str) -> Result<(), String> {
+    let document = package.text("word/document.xml")?;
+    let lists = numbering_ids(&document);
+    if lists.is_empty() {
+        return Ok(());
+    }
+    let mut numbering = package.text("word/numbering.xml")?;
+    let ids = add_numbering(&mut numbering, own, &lists);
+    let document = renumber_lists(&document, &ids);
+    package.set("word/numbering.xml", numbering.into_bytes());
+    package.set("word/document.xml", document.into_bytes());
+    Ok(())
+}
+
+/// The relationships in `rels`, the relationships of a part in `folder`,
+/// leaving out external ones such as hyperlinks.
+fn relationships(rels: &str, folder: &str) -> Vec<Relationship> {
+    elements(rels, "Relationship")
+        .into_iter()
+        .filter(|rel| attribute(rel, "TargetMode") != Some("External"))
+        .filter_map(|rel| {
+            let target = attribute(rel, "Target")?;
+            let path = match target.strip_prefix('/') {
+                Some(absolute) => absolute.to_string(),
+                None if folder.is_empty() => target.to_string(),
+                None => format!("{}/{}", folder, target),
+            };
+            Some(Relationship {
+                id: attribute(rel, "Id")?.to_string(),
+                kind: attribute(rel, "Type")?.to_string(),
+                path,
+            })
+        })
+        .collect()
+}
+
+fn add_relationship(rels: &mut String, id: &str, kind: &str, target: &str) {
+    if let Some(end) = rels.rfind("</Relationships>") {
+        rels.insert_str(
+            end,
+            &format!(
+                r#"<Relationship Id="{}" Type="{}" Target="{}" />"#,
+                id, kind, target
+            ),
+        );
+    }
+}
+
+/// `path` with its file name prefixed, so that it cannot clash with a part
+/// of the record.
+fn renamed(path: &str) -> String {
+    match path.rsplit_once('/') {
+        Some((folder, name)) => format!("{}/reference-{}", folder, name),
+        None => format!("reference-{}", path),
+    }
+}
+
+/// Copy the part `from` of the reference to `to` in `package` with its
+/// content type and the files it refers to, such as a logo in a header.
+fn copy(reference: &Package, from: &str, package: &mut Package, to: &str) -> Result<(), String> {
+    let data = reference
+        .file(from)
+        .ok_or_else(|| format!("{} is missing", from))?;
+    package.set(to, data.to_vec());
+    if let Some(content_type) = content_type(reference, from) {
+        package.add_override_type(&format!("/{}", to), &content_type);
+    }
+
+    let (folder, name) = from.rsplit_once('/').unwrap_or(("", from));
+    let Some(rels) = reference.file(&format!("{}/_rels/{}.rels", folder, name)) else {
+        return Ok(());
+    };
+    let mut rels = String::from_utf8_lossy(rels).into_owned();
+    for dependency in relationships(&rels.clone(), folder) {
+        let Some(data) = reference.file(&dependency.path) else {
+            continue;
+        };
+        let path = renamed(&dependency.path);
+        package.set(&path, data.to_vec());
+        if let Some(content_type) = content_type(reference, &dependency.path) {
+            match path.rsplit_once('.') {
+                Some((_, extension)) if !dependency.path.ends_with(".xml") => {
+                    package.add_default_type(extension, &content_type)
+                }
+                _ => package.add_override_type(&format!("/{}", path), &content_type),
+            }
+        }
+        // Point the relationship at the renamed file
+        let target = format!("Target=\"{}", folder_relative(&dependency.path, folder));
+        let renamed_target = format!("Target=\"{}", folder_relative(&path, folder));
+        rels = rels.replacen(&target, &renamed_target, 1);
+    }
+    let (to_folder, to_name) = to.rsplit_once('/').unwrap_or(("", to));
+    package.set(
+        &format!("{}/_rels/{}.rels", to_folder, to_name),
+        rels.into_bytes(),
+    );
+    Ok(())
+}
+
+fn folder_relative<'a>(path: &'a str, folder: &str) -> &'a str {
+    path.strip_prefix(folder)
+        .and_then(|path| path.strip_prefix('/'))
+        .unwrap_or(path)
+}
+
+/// The content type the reference declares for the part at `path`.
+fn content_type(reference: &Package, path: &str) -> Option<String> {
+    let types = reference.text("[Content_Types].xml").ok()?;
+    let part = format!("/{}", path);
+    let extension = path.rsplit_once('.').map(|(_, extension)| extension);
+    let overridden = elements(&types, "Override")
+        .into_iter()
+        .find(|tag| attribute(tag, "PartName") == Some(&part));
+    let default = || {
+        elements(&types, "Default")
+            .into_iter()
+            .find(|tag| attribute(tag, "Extension") == extension)
+    };
+    overridden
+        .or_else(default)
+        .and_then(|tag| attribute(tag, "ContentType"))
+        .map(str::to_string)
+}
+
+/// The page setup of the last section, which closes `<w:body>`.
+fn body_section(document: &str) -> Option<&str> {
+    body_section_span(document).map(|span| &document[span])
+}
+
+/// Where in `document` [`body_section`] is.
+fn body_section_span(document: &str) -> Option<Range<usize>> {
+    let start = document.rfind("<w:sectPr")?;
+    let rest = &document[start..];
+    let open = rest.find('>')? + 1;
+    let end = if rest[..open].ends_with("/>") {
+        open
+    } else {
+        rest.find("</w:sectPr>")? + "</w:sectPr>".len()
+    };
+    Some(start..start + end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use docx_rs::{Docx, PageMargin, Paragraph, Run};
+
+    fn docx(doc: Docx) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        doc.add_paragraph(Paragraph::new().add_run(Run::new().add_text("text")))
+            .build()
+            .pack(std::io::Cursor::new(&mut bytes))
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn text_width_is_the_page_less_its_margins() {
+        let letter = docx(
+            Docx::new()
+                .page_size(12240, 15840)
+                .page_margin(PageMargin::new().left(1440).right(1440)),
+        );
+        assert_eq!(text_width(&letter), Some(12240 - 2 * 1440));
+    }
+}
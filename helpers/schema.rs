@@ -0,0 +1,64 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject, SubschemaValidation};
+use schemars::schema_for;
+
+use crate::utilities::DocumentConfig;
+
+const ALIGNMENTS: [&str; 4] = ["left", "center", "right", "justify"];
+
+/// Styles the base document defines, which `style` may name along with
+/// those in `[styles]`.
+pub(crate) const STYLES: [&str; 12] = [
+    "Heading1", "Heading2", "Heading3", "Heading4", "Heading5", "Heading6", "Title", "Subtitle",
+    "Normal", "Quote", "Emphasis", "Strong",
+];
+
+fn string_enum(values: &[&str]) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        enum_values: Some(values.iter().map(|value| (*value).into()).collect()),
+        ..Default::default()
+    }
+    .into()
+}
+
+pub fn align_schema(_: &mut SchemaGenerator) -> Schema {
+    string_enum(&ALIGNMENTS)
+}
+
+pub fn style_schema(_: &mut SchemaGenerator) -> Schema {
+    let any_name = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        ..Default::default()
+    };
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(vec![string_enum(&STYLES), any_name.into()]),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// JSON Schema describing `format.toml`, pretty printed.
+pub fn format_schema() -> String {
+    let schema = schema_for!(DocumentConfig);
+    serde_json::to_string_pretty(&schema).expect("schema serializes to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_schema;
+
+    #[test]
+    fn committed_schema_matches_config() {
+        let committed: serde_json::Value =
+            serde_json::from_str(include_str!("../format.schema.json")).unwrap();
+        let generated: serde_json::Value = serde_json::from_str(&format_schema()).unwrap();
+        assert!(
+            committed == generated,
+            "format.schema.json is out of date, regenerate it with `create-docx schema > format.schema.json`"
+        );
+    }
+}
@@ -0,0 +1,777 @@
+//! Program output drawn as a picture of a terminal window, for records that
+//! ask for a "screenshot of output".
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::entry::{ContentType, ProgramRun, Stream};
+use crate::font::{Bitmap, Font};
+
+/// Terminal window the output of each run is drawn in when enabled.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Screenshot {
+    /// Embed a picture of the output instead of the output text.
+    #[serde(default)]
+    pub enabled: bool,
+    /// TrueType font file of the terminal text; a monospace system font such
+    /// as DejaVu Sans Mono is used when unset.
+    #[serde(default)]
+    pub font: Option<PathBuf>,
+    /// TrueType font file of bold text; bold is drawn by thickening the
+    /// regular font when unset.
+    #[serde(default)]
+    pub bold_font: Option<PathBuf>,
+    /// Size of the terminal text, in pixels.
+    #[serde(default = "default_font_size")]
+    pub font_size: f32,
+    /// Height of a terminal line as a multiple of the font size.
+    #[serde(default = "default_line_height")]
+    pub line_height: f32,
+    /// Space between the window edge and the text, in pixels.
+    #[serde(default = "default_padding")]
+    pub padding: f32,
+    /// Pixel density of the picture; 2 keeps the text sharp when printed.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Background color of the terminal.
+    #[serde(default = "default_background")]
+    pub background: String,
+    /// Color of text printed without an ANSI color.
+    #[serde(default = "default_foreground")]
+    pub foreground: String,
+    /// The 16 ANSI colors: black, red, green, yellow, blue, magenta, cyan and
+    /// white, followed by their bright variants.
+    #[serde(default = "default_palette")]
+    pub palette: Vec<String>,
+    /// Draw a title bar with window buttons above the text.
+    #[serde(default = "default_true")]
+    pub title_bar: bool,
+    /// Title of the window; `{n}`, `{run}` and `{label}` are replaced.
+    #[serde(default = "default_title")]
+    pub title: String,
+    /// Background color of the title bar.
+    #[serde(default = "default_title_bar_color")]
+    pub title_bar_color: String,
+    /// Color of the title.
+    #[serde(default = "default_title_color")]
+    pub title_color: String,
+    #[serde(skip)]
+    #[schemars(skip)]
+    fonts: OnceLock<Result<Fonts, String>>,
+}
+
+#[derive(Debug)]
+struct Fonts {
+    regular: Font,
+    bold: Option<Font>,
+}
+
+fn default_font_size() -> f32 {
+    14.0
+}
+
+fn default_line_height() -> f32 {
+    1.2
+}
+
+fn default_padding() -> f32 {
+    16.0
+}
+
+fn default_scale() -> f32 {
+    2.0
+}
+
+fn default_background() -> String {
+    "#1e1e2e".to_string()
+}
+
+fn default_foreground() -> String {
+    "#cdd6f4".to_string()
+}
+
+fn default_palette() -> Vec<String> {
+    [
+        "#45475a", "#f38ba8", "#a6e3a1", "#f9e2af", "#89b4fa", "#f5c2e7", "#94e2d5", "#bac2de",
+        "#585b70", "#f38ba8", "#a6e3a1", "#f9e2af", "#89b4fa", "#f5c2e7", "#94e2d5", "#a6adc8",
+    ]
+    .iter()
+    .map(|color| color.to_string())
+    .collect()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_title() -> String {
+    "Output".to_string()
+}
+
+fn default_title_bar_color() -> String {
+    "#181825".to_string()
+}
+
+fn default_title_color() -> String {
+    "#a6adc8".to_string()
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Screenshot {
+            enabled: false,
+            font: None,
+            bold_font: None,
+            font_size: default_font_size(),
+            line_height: default_line_height(),
+            padding: default_padding(),
+            scale: default_scale(),
+            background: default_background(),
+            foreground: default_foreground(),
+            palette: default_palette(),
+            title_bar: default_true(),
+            title: default_title(),
+            title_bar_color: default_title_bar_color(),
+            title_color: default_title_color(),
+            fonts: OnceLock::new(),
+        }
+    }
+}
+
+/// A rendered screenshot.
+pub struct Image {
+    /// The picture encoded as PNG.
+    pub png: Vec<u8>,
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Pixel density the picture was drawn at.
+    pub scale: f32,
+}
+
+impl Image {
+    /// Printed size in EMU, at most `max_width` EMU wide.
+    pub fn display_size(&self, max_width: u32) -> (u32, u32) {
+        // A pixel is 9525 EMU at 96 dpi
+        let width = self.width as f32 / self.scale * 9525.0;
+        let height = self.height as f32 / self.scale * 9525.0;
+        let shrink = (max_width as f32 / width).min(1.0);
+        ((width * shrink) as u32, (height * shrink) as u32)
+    }
+}
+
+impl Screenshot {
+    /// Load the fonts and check the colors, so that a broken config is
+    /// reported before any output is drawn.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if self.palette.len() != 16 {
+            return Err(format!(
+                "`palette` needs 16 colors, got {}",
+                self.palette.len()
+            ));
+        }
+        for color in [
+            &self.background,
+            &self.foreground,
+            &self.title_bar_color,
+            &self.title_color,
+        ]
+        .into_iter()
+        .chain(&self.palette)
+        {
+            parse_color(color).ok_or_else(|| format!("invalid color {:?}", color))?;
+        }
+        if self.font_size <= 0.0 || self.scale <= 0.0 || self.line_height <= 0.0 {
+            return Err("`font_size`, `scale` and `line_height` must be positive".to_string());
+        }
+        self.fonts().map(|_| ())
+    }
+
+    /// Make relative font paths relative to `dir`, the folder of the
+    /// `format.toml` they were read from.
+    pub fn resolve_fonts(&mut self, dir: &Path) {
+        for font in [&mut self.font, &mut self.bold_font].into_iter().flatten() {
+            if font.is_relative() {
+                *font = dir.join(&*font);
+            }
+        }
+    }
+
+    fn fonts(&self) -> Result<&Fonts, String> {
+        self.fonts
+            .get_or_init(|| {
+                let regular = match &self.font {
+                    Some(path) => Font::load(path)?,
+                    None => Font::system_monospace()?,
+                };
+                let bold = self.bold_font.as_deref().map(Font::load).transpose()?;
+                Ok(Fonts { regular, bold })
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Draw what a run printed, with `title` in the title bar.
+    pub fn render(&self, run: &ProgramRun, title: &str) -> Result<Image, String> {
+        let fonts = self.fonts()?;
+        let mut terminal = Terminal::default();
+        if run.transcript.is_empty() {
+            match run.output.content_type {
+                ContentType::Ansi => terminal.write(&run.output.data),
+                _ => terminal.write_plain(&run.output.plain_text()),
+            }
+        } else {
+            for chunk in &run.transcript {
+                match chunk.stream {
+                    Stream::Input => terminal.write_plain(&chunk.text),
+                    Stream::Output => terminal.write(&chunk.text),
+                }
+            }
+        }
+        self.draw(fonts, &terminal.finish(), title)
+    }
+
+    fn draw(&self, fonts: &Fonts, lines: &[Vec<Cell>], title: &str) -> Result<Image, String> {
+        let color = |hex: &str| parse_color(hex).unwrap_or([0, 0, 0]);
+        let background = color(&self.background);
+        let foreground = color(&self.foreground);
+        let palette: Vec<[u8; 3]> = self.palette.iter().map(|hex| color(hex)).collect();
+
+        let scale = self.scale;
+        let size = self.font_size * scale;
+        let font = &fonts.regular;
+        let cell_width = font.advance(font.glyph_index('M'), size).round().max(1.0) as usize;
+        let cell_height = (size * self.line_height).round().max(1.0) as usize;
+        let baseline = (cell_height as f32 - font.line_height(size)) / 2.0 + font.ascent(size);
+        let padding = (self.padding * scale).round() as usize;
+        let title_height = if self.title_bar {
+            (size * 2.2).round() as usize
+        } else {
+            0
+        };
+
+        // Leave room for the buttons and the title
+        let columns = lines
+            .iter()
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0)
+            .max(title.chars().count() + 16)
+            .max(20);
+        let width = 2 * padding + columns * cell_width;
+        let height = title_height + 2 * padding + lines.len().max(1) * cell_height;
+        let mut canvas = Canvas::new(width, height, background);
+
+        if self.title_bar {
+            canvas.fill(0, 0, width, title_height, color(&self.title_bar_color));
+            let radius = 6.0 * scale;
+            for (position, button) in [[0xff, 0x5f, 0x57], [0xfe, 0xbc, 0x2e], [0x28, 0xc8, 0x40]]
+                .into_iter()
+                .enumerate()
+            {
+                let x = padding as f32 + radius + position as f32 * 20.0 * scale;
+                canvas.circle(x, title_height as f32 / 2.0, radius, button);
+            }
+            let title_width = title.chars().count() * cell_width;
+            let x = width.saturating_sub(title_width) / 2;
+            let y = (title_height.saturating_sub(cell_height)) / 2;
+            let mut glyphs = GlyphCache::new(font, size, cell_width, cell_height, baseline);
+            for (column, ch) in title.chars().enumerate() {
+                let bitmap = glyphs.get(ch);
+                canvas.blend(bitmap, x + column * cell_width, y, color(&self.title_color));
+            }
+        }
+
+        let mut regular = GlyphCache::new(font, size, cell_width, cell_height, baseline);
+        let mut bold = fonts
+            .bold
+            .as_ref()
+            .map(|font| GlyphCache::new(font, size, cell_width, cell_height, baseline));
+        let thickness = (size / 14.0).round().max(1.0) as usize;
+        let resolve = |color: Color, default: [u8; 3]| match color {
+            Color::Default => default,
+            Color::Indexed(index) => indexed_color(index, &palette),
+            Color::Rgb(rgb) => rgb,
+        };
+
+        for (row, line) in lines.iter().enumerate() {
+            let y = title_height + padding + row * cell_height;
+            for (column, cell) in line.iter().enumerate() {
+                let x = padding + column * cell_width;
+                let mut fg = resolve(cell.style.fg, foreground);
+                let mut bg = resolve(cell.style.bg, background);
+                if cell.style.inverse {
+                    std::mem::swap(&mut fg, &mut bg);
+                }
+                if bg != background {
+                    canvas.fill(x, y, cell_width, cell_height, bg);
+                }
+                if cell.ch == ' ' {
+                    continue;
+                }
+                match (&mut bold, cell.style.bold) {
+                    (Some(bold), true) => canvas.blend(bold.get(cell.ch), x, y, fg),
+                    (None, true) => {
+                        let bitmap = regular.get(cell.ch);
+                        for offset in 0..=thickness {
+                            canvas.blend(bitmap, x + offset, y, fg);
+                        }
+                    }
+                    _ => canvas.blend(regular.get(cell.ch), x, y, fg),
+                }
+            }
+        }
+
+        canvas.round_corners(6.0 * scale);
+        Ok(Image {
+            png: canvas.encode()?,
+            width: width as u32,
+            height: height as u32,
+            scale,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Color {
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb([u8; 3]),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Style {
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    inverse: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    ch: char,
+    style: Style,
+}
+
+/// Just enough of a terminal to lay out captured output: SGR colors,
+/// carriage returns, backspaces and tabs. Other escape sequences are
+/// dropped.
+#[derive(Default)]
+struct Terminal {
+    lines: Vec<Vec<Cell>>,
+    row: usize,
+    column: usize,
+    style: Style,
+}
+
+impl Terminal {
+    /// Write text as typed by the user, in the default colors.
+    fn write_plain(&mut self, text: &str) {
+        let style = std::mem::take(&mut self.style);
+        for ch in text.chars().filter(|&ch| ch != '\u{1b}') {
+            self.put(ch);
+        }
+        self.style = style;
+    }
+
+    fn write(&mut self, text: &str) {
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if ch != '\u{1b}' {
+                self.put(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('[') => {
+                    let mut parameters = String::new();
+                    for ch in chars.by_ref() {
+                        if ('@'..='~').contains(&ch) {
+                            if ch == 'm' {
+                                self.select_graphic_rendition(&parameters);
+                            }
+                            break;
+                        }
+                        parameters.push(ch);
+                    }
+                }
+                // Operating system commands such as window titles end with
+                // BEL or ESC \
+                Some(']') => {
+                    while let Some(ch) = chars.next() {
+                        if ch == '\u{7}' {
+                            break;
+                        }
+                        if ch == '\u{1b}' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn put(&mut self, ch: char) {
+        match ch {
+            '\n' => {
+                self.row += 1;
+                self.column = 0;
+            }
+            '\r' => self.column = 0,
+            '\u{8}' => self.column = self.column.saturating_sub(1),
+            '\t' => self.column = (self.column / 8 + 1) * 8,
+            ch if ch.is_control() => {}
+            ch => {
+                if self.lines.len() <= self.row {
+                    self.lines.resize(self.row + 1, Vec::new());
+                }
+                let line = &mut self.lines[self.row];
+                if line.len() <= self.column {
+                    line.resize(
+                        self.column + 1,
+                        Cell {
+                            ch: ' ',
+                            style: Style::default(),
+                        },
+                    );
+                }
+                line[self.column] = Cell {
+                    ch,
+                    style: self.style,
+                };
+                self.column += 1;
+            }
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, parameters: &str) {
+        let codes: Vec<u16> = parameters
+            .split([';', ':'])
+            .map(|code| code.parse().unwrap_or(0))
+            .collect();
+        let mut codes = codes.into_iter();
+        while let Some(code) = codes.next() {
+            match code {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                22 => self.style.bold = false,
+                7 => self.style.inverse = true,
+                27 => self.style.inverse = false,
+                30..=37 => self.style.fg = Color::Indexed((code - 30) as u8),
+                40..=47 => self.style.bg = Color::Indexed((code - 40) as u8),
+                90..=97 => self.style.fg = Color::Indexed((code - 90 + 8) as u8),
+                100..=107 => self.style.bg = Color::Indexed((code - 100 + 8) as u8),
+                39 => self.style.fg = Color::Default,
+                49 => self.style.bg = Color::Default,
+                38 | 48 => {
+                    let color = match codes.next() {
+                        Some(5) => codes.next().map(|index| Color::Indexed(index as u8)),
+                        Some(2) => {
+                            let rgb: Vec<u8> = codes.by_ref().take(3).map(|c| c as u8).collect();
+                            (rgb.len() == 3).then(|| Color::Rgb([rgb[0], rgb[1], rgb[2]]))
+                        }
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        if code == 38 {
+                            self.style.fg = color;
+                        } else {
+                            self.style.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The lines of the screen, without trailing blank lines.
+    fn finish(mut self) -> Vec<Vec<Cell>> {
+        while self
+            .lines
+            .last()
+            .is_some_and(|line| line.iter().all(|cell| cell.ch == ' '))
+        {
+            self.lines.pop();
+        }
+        self.lines
+    }
+}
+
+/// Colors of the 256-color palette: the 16 configured colors, a 6×6×6
+/// color cube and 24 grays.
+fn indexed_color(index: u8, palette: &[[u8; 3]]) -> [u8; 3] {
+    match index {
+        0..=15 => palette[index as usize],
+        16..=231 => {
+            let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+            let index = index - 16;
+            [level(index / 36), level(index / 6 % 6), level(index % 6)]
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            [gray, gray, gray]
+        }
+    }
+}
+
+fn parse_color(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |range| u8::from_str_radix(&hex[range], 16).ok();
+    Some([channel(0..2)?, channel(2..4)?, channel(4..6)?])
+}
+
+/// Glyphs of one font rasterized into terminal cells, each drawn once.
+struct GlyphCache<'a> {
+    font: &'a Font,
+    size: f32,
+    width: usize,
+    height: usize,
+    baseline: f32,
+    glyphs: HashMap<char, Bitmap>,
+}
+
+impl<'a> GlyphCache<'a> {
+    fn new(font: &'a Font, size: f32, width: usize, height: usize, baseline: f32) -> Self {
+        GlyphCache {
+            font,
+            size,
+            width,
+            height,
+            baseline,
+            glyphs: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, ch: char) -> &Bitmap {
+        self.glyphs.entry(ch).or_insert_with(|| {
+            let glyph = self.font.glyph_index(ch);
+            // Some glyphs spill a little into the next cell
+            self.font
+                .rasterize(glyph, self.size, self.width * 2, self.height, self.baseline)
+        })
+    }
+}
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize, color: [u8; 3]) -> Self {
+        let [r, g, b] = color;
+        Canvas {
+            width,
+            height,
+            pixels: vec![[r, g, b, 255]; width * height],
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        let [r, g, b] = color;
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.pixels[row * self.width + column] = [r, g, b, 255];
+            }
+        }
+    }
+
+    fn mix(&mut self, x: usize, y: usize, color: [u8; 3], alpha: f32) {
+        if x >= self.width || y >= self.height || alpha <= 0.0 {
+            return;
+        }
+        let pixel = &mut self.pixels[y * self.width + x];
+        for channel in 0..3 {
+            let value = pixel[channel] as f32 * (1.0 - alpha) + color[channel] as f32 * alpha;
+            pixel[channel] = value.round() as u8;
+        }
+    }
+
+    fn blend(&mut self, bitmap: &Bitmap, x: usize, y: usize, color: [u8; 3]) {
+        for row in 0..bitmap.height {
+            for column in 0..bitmap.width {
+                let alpha = bitmap.coverage[row * bitmap.width + column];
+                self.mix(x + column, y + row, color, alpha);
+            }
+        }
+    }
+
+    fn circle(&mut self, cx: f32, cy: f32, radius: f32, color: [u8; 3]) {
+        let top = (cy - radius - 1.0).max(0.0) as usize;
+        let left = (cx - radius - 1.0).max(0.0) as usize;
+        for y in top..(cy + radius + 1.0).ceil() as usize {
+            for x in left..(cx + radius + 1.0).ceil() as usize {
+                let distance =
+                    ((x as f32 + 0.5 - cx).powi(2) + (y as f32 + 0.5 - cy).powi(2)).sqrt();
+                self.mix(x, y, color, (radius - distance + 0.5).clamp(0.0, 1.0));
+            }
+        }
+    }
+
+    /// Make the corners outside a circle of `radius` transparent.
+    fn round_corners(&mut self, radius: f32) {
+        let size = radius.ceil() as usize;
+        for y in 0..size.min(self.height) {
+            for x in 0..size.min(self.width) {
+                let distance =
+                    ((radius - x as f32 - 0.5).powi(2) + (radius - y as f32 - 0.5).powi(2)).sqrt();
+                let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+                for (px, py) in [
+                    (x, y),
+                    (self.width - 1 - x, y),
+                    (x, self.height - 1 - y),
+                    (self.width - 1 - x, self.height - 1 - y),
+                ] {
+                    let pixel = &mut self.pixels[py * self.width + px];
+                    pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+                }
+            }
+        }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data: Vec<u8> = self.pixels.iter().flatten().copied().collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(|e| format!("failed to encode the screenshot: {}", e))?;
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(text: &str) -> Vec<Vec<Cell>> {
+        let mut terminal = Terminal::default();
+        terminal.write(text);
+        terminal.finish()
+    }
+
+    fn text(line: &[Cell]) -> String {
+        line.iter().map(|cell| cell.ch).collect()
+    }
+
+    #[test]
+    fn graphic_renditions_set_colors_and_attributes() {
+        let lines = written(
+            "\u{1b}[31mr\u{1b}[92mg\u{1b}[38;5;196mx\u{1b}[48;2;1;2;3my\u{1b}[1;7mb\u{1b}[22;27;39mc\u{1b}[0mn",
+        );
+        let styles: Vec<Style> = lines[0].iter().map(|cell| cell.style).collect();
+        assert_eq!(text(&lines[0]), "rgxybcn");
+        assert_eq!(styles[0].fg, Color::Indexed(1));
+        assert_eq!(styles[1].fg, Color::Indexed(10));
+        assert_eq!(styles[2].fg, Color::Indexed(196));
+        assert_eq!(
+            (styles[3].fg, styles[3].bg),
+            (Color::Indexed(196), Color::Rgb([1, 2, 3]))
+        );
+        assert!(styles[4].bold && styles[4].inverse);
+        assert!(!styles[5].bold && !styles[5].inverse);
+        assert_eq!(
+            (styles[5].fg, styles[5].bg),
+            (Color::Default, Color::Rgb([1, 2, 3]))
+        );
+        assert_eq!(
+            (styles[6].fg, styles[6].bg),
+            (Color::Default, Color::Default)
+        );
+
+        // Colons separate the parts of a color too
+        let lines = written("\u{1b}[38:2:10:20:30mt");
+        assert_eq!(lines[0][0].style.fg, Color::Rgb([10, 20, 30]));
+    }
+
+    #[test]
+    fn indexed_colors_cover_the_palette_cube_and_grays() {
+        let palette: Vec<[u8; 3]> = (0..16).map(|index| [index, 0, 0]).collect();
+        assert_eq!(indexed_color(9, &palette), [9, 0, 0]);
+        assert_eq!(indexed_color(16, &palette), [0, 0, 0]);
+        assert_eq!(indexed_color(196, &palette), [255, 0, 0]);
+        assert_eq!(indexed_color(110, &palette), [135, 175, 215]);
+        assert_eq!(indexed_color(232, &palette), [8, 8, 8]);
+        assert_eq!(indexed_color(255, &palette), [238, 238, 238]);
+    }
+
+    #[test]
+    fn carriage_returns_and_backspaces_overwrite() {
+        let lines = written("12345\rab\u{8}c\n\tX\n\u{8}\u{8}y\n\n");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(text(&lines[0]), "ac345");
+        assert_eq!(text(&lines[1]), "        X");
+        assert_eq!(text(&lines[2]), "y");
+    }
+
+    #[test]
+    fn other_escape_sequences_are_dropped() {
+        let lines = written("\u{1b}]0;title\u{7}A\u{1b}]2;other\u{1b}\\B\u{1b}[2KC\u{1b}[?25hD");
+        assert_eq!(text(&lines[0]), "ABCD");
+
+        // Input is printed as typed
+        let mut terminal = Terminal::default();
+        terminal.write("\u{1b}[31m> ");
+        terminal.write_plain("\u{1b}[1m42");
+        terminal.write("!");
+        let lines = terminal.finish();
+        assert_eq!(text(&lines[0]), "> [1m42!");
+        assert_eq!(lines[0][0].style.fg, Color::Indexed(1));
+        assert_eq!(lines[0][2].style.fg, Color::Default);
+        assert_eq!(lines[0][7].style.fg, Color::Indexed(1));
+    }
+
+    #[test]
+    fn picture_fits_the_longest_line() {
+        let screenshot = Screenshot::default();
+        let fonts = Fonts {
+            regular: Font::parse(crate::font::tests::square_font()).unwrap(),
+            bold: None,
+        };
+        let lines = written(&format!("{}\n\u{1b}[1;41mshort", "A".repeat(30)));
+        let image = screenshot.draw(&fonts, &lines, "run 1").unwrap();
+
+        // Cells are as wide as the font's "M", 600 of its 1000 units
+        let size = screenshot.font_size * screenshot.scale;
+        let cell_width = (0.6 * size).round() as usize;
+        let cell_height = (size * screenshot.line_height).round() as usize;
+        let padding = (screenshot.padding * screenshot.scale).round() as usize;
+        let title_height = (size * 2.2).round() as usize;
+        assert_eq!(image.width as usize, 2 * padding + 30 * cell_width);
+        assert_eq!(
+            image.height as usize,
+            title_height + 2 * padding + 2 * cell_height
+        );
+        let decoded = image::load_from_memory(&image.png).unwrap();
+        assert_eq!(
+            (decoded.width(), decoded.height()),
+            (image.width, image.height)
+        );
+
+        // Without a title bar, a short output keeps room for 20 columns
+        let screenshot = Screenshot {
+            title_bar: false,
+            ..Default::default()
+        };
+        let image = screenshot.draw(&fonts, &written("A"), "").unwrap();
+        assert_eq!(image.width as usize, 2 * padding + 20 * cell_width);
+        assert_eq!(image.height as usize, 2 * padding + cell_height);
+    }
+}
@@ -0,0 +1,699 @@
+//! Updating a record written by an earlier build in place.
+//!
+//! Every practical is written between the start and the end of a hidden
+//! bookmark named after its number and the hash of its contents. An update
+//! replaces the practicals whose hash changed and adds the new ones, and
+//! leaves everything else in the document alone, such as a teacher's
+//! annotations between practicals or inside unchanged ones.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::error::Error;
+use crate::package::{
+    add_missing_styles, add_numbering, attribute, is_tag, numbering_ids, set_attribute, tags,
+    update_fields, Package,
+};
+
+/// Name of the bookmark around the check summary.
+pub(crate) const SUMMARY_BOOKMARK: &str = "_CreateDocx_summary";
+
+/// Name of the bookmark around the table of contents.
+pub(crate) const CONTENTS_BOOKMARK: &str = "_CreateDocx_contents";
+
+const PREFIX: &str = "_CreateDocx_";
+
+const PAGE_BREAK: &str = r#"<w:p><w:r><w:br w:type="page" /></w:r></w:p>"#;
+
+/// Name of the bookmark around practical `index`. Word only keeps the first
+/// 40 characters of a name, so only part of the hash fits.
+pub(crate) fn practical_bookmark(index: usize, key: &str) -> String {
+    format!("{}{}_{}", PREFIX, index + 1, &key[..key.len().min(20)])
+}
+
+/// What an update did to each practical.
+#[derive(Debug, Default, Clone)]
+pub struct UpdateReport {
+    /// Indexes of the practicals that were left as they were.
+    pub kept: Vec<usize>,
+    /// Indexes of the practicals that changed and were replaced.
+    pub replaced: Vec<usize>,
+    /// Indexes of the practicals that were added.
+    pub added: Vec<usize>,
+    /// Indexes of the practicals in the document that are no longer in the
+    /// entries. They are left as they were.
+    pub orphaned: Vec<usize>,
+}
+
+/// A bookmarked part of `document.xml`.
+struct Region {
+    part: Part,
+    hash: String,
+    /// The body elements holding the bookmark.
+    span: Range<usize>,
+}
+
+/// What a region holds.
+#[derive(Clone, Copy, PartialEq)]
+enum Part {
+    /// The practical at this index.
+    Practical(usize),
+    Summary,
+    Contents,
+}
+
+impl std::fmt::Display for Part {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Part::Practical(index) => write!(f, "practical {}", index + 1),
+            Part::Summary => write!(f, "the check summary"),
+            Part::Contents => write!(f, "the table of contents"),
+        }
+    }
+}
+
+/// Update `existing`, a record written by an earlier build, with
+/// `rendered`, the same record as it would be written now.
+pub fn update_docx(existing: &[u8], rendered: &[u8]) -> Result<(Vec<u8>, UpdateReport), Error> {
+    let mut package = Package::read(existing).map_err(Error::Update)?;
+    let new = Package::read(rendered).map_err(Error::Update)?;
+    let document = package.text("word/document.xml").map_err(Error::Update)?;
+    let new_document = new.text("word/document.xml").map_err(Error::Update)?;
+
+    let old_regions = regions(&document);
+    let mut practicals = BTreeMap::new();
+    let mut summary = None;
+    let mut contents = None;
+    for region in &old_regions {
+        match region.part {
+            Part::Practical(index) => {
+                practicals.insert(index, region);
+            }
+            Part::Summary => summary = Some(region),
+            Part::Contents => contents = Some(region),
+        }
+    }
+    let tags = tags(&document);
+    let children = body_children(&tags);
+    let has_content = children
+        .iter()
+        .any(|child| !is_tag(&document[child.clone()], "w:sectPr"));
+    let body_start = children.first().map_or(0, |child| child.start);
+    // Before the section properties that end the body
+    let body_end = match children
+        .iter()
+        .find(|child| is_tag(&document[(*child).clone()], "w:sectPr"))
+    {
+        Some(properties) => properties.start,
+        None => document.find("</w:body>").unwrap_or(document.len()),
+    };
+
+    // Practicals numbered by Word keep the list they are numbered with, so
+    // that added ones continue it
+    let list = old_regions.iter().find_map(|region| {
+        numbering_ids(&document[region.span.clone()])
+            .first()
+            .copied()
+    });
+    let mut import = Import::new(&package, &new, list)?;
+    let mut report = UpdateReport::default();
+    // Replaced spans and insertions at a position, applied in order, with
+    // the part they bring in
+    let mut edits: Vec<(Range<usize>, String, Part)> = Vec::new();
+    for region in regions(&new_document) {
+        let xml = &new_document[region.span.clone()];
+        let index = match region.part {
+            Part::Practical(index) => index,
+            Part::Summary => {
+                let xml = import.region(xml);
+                match summary {
+                    Some(old) => edits.push((old.span.clone(), xml, region.part)),
+                    None => edits.push((
+                        body_end..body_end,
+                        format!("{}{}", PAGE_BREAK, xml),
+                        region.part,
+                    )),
+                }
+                continue;
+            }
+            // Always replaced, as the headings it lists may have changed
+            Part::Contents => {
+                let xml = import.region(xml);
+                match contents {
+                    Some(old) => edits.push((old.span.clone(), xml, region.part)),
+                    None if has_content => edits.push((
+                        body_start..body_start,
+                        format!("{}{}", xml, PAGE_BREAK),
+                        region.part,
+                    )),
+                    None => edits.push((body_start..body_start, xml, region.part)),
+                }
+                continue;
+            }
+        };
+
+        match practicals.get(&index) {
+            Some(old) if old.hash == region.hash => report.kept.push(index),
+            Some(old) => {
+                edits.push((old.span.clone(), import.region(xml), region.part));
+                report.replaced.push(index);
+            }
+            None => {
+                let xml = import.region(xml);
+                // After the practical before it, or else before the one
+                // after it
+                let (span, xml) = if let Some((_, before)) = practicals.range(..index).next_back() {
+                    let end = before.span.end;
+                    (end..end, format!("{}{}", PAGE_BREAK, xml))
+                } else if let Some(after) = practicals.values().next().copied().or(summary) {
+                    let start = after.span.start;
+                    (start..start, format!("{}{}", xml, PAGE_BREAK))
+                } else if has_content || !report.added.is_empty() {
+                    (body_end..body_end, format!("{}{}", PAGE_BREAK, xml))
+                } else {
+                    (body_end..body_end, xml)
+                };
+                edits.push((span, xml, region.part));
+                report.added.push(index);
+            }
+        }
+    }
+    report.orphaned = practicals
+        .keys()
+        .filter(|index| !report.kept.contains(index) && !report.replaced.contains(index))
+        .copied()
+        .collect();
+
+    // Insertions go before a replacement starting at the same position
+    edits.sort_by_key(|(span, ..)| (span.start, !span.is_empty()));
+    let mut updated = String::with_capacity(document.len());
+    let mut position = 0;
+    let mut previous = None;
+    for (span, xml, part) in edits {
+        // Bookmarks moved by hand may nest one region in another, and
+        // replacing both would write part of the document twice
+        if let (true, Some(previous)) = (span.start < position, previous) {
+            return Err(Error::Update(format!(
+                "the bookmarks around {} and {} overlap",
+                previous, part
+            )));
+        }
+        updated.push_str(&document[position..span.start]);
+        updated.push_str(&xml);
+        position = span.end;
+        previous = Some(part);
+    }
+    updated.push_str(&document[position..]);
+
+    package.set("word/document.xml", updated.into_bytes());
+    import.finish(&mut package)?;
+    Ok((package.write().map_err(Error::Update)?, report))
+}
+
+/// The regions marked by create-docx bookmarks in `document`.
+fn regions(document: &str) -> Vec<Region> {
+    let tags = tags(document);
+    let children = body_children(&tags);
+    // Word may move a bookmark into the paragraph or table next to it
+    let block = |position: usize| children.iter().find(|child| child.contains(&position));
+
+    let mut regions = Vec::new();
+    for (i, &(start, tag)) in tags.iter().enumerate() {
+        if !is_tag(tag, "w:bookmarkStart") {
+            continue;
+        }
+        let Some(name) = attribute(tag, "w:name").and_then(|name| name.strip_prefix(PREFIX)) else {
+            continue;
+        };
+        let id = attribute(tag, "w:id");
+        let Some(&(end, _)) = tags[i..]
+            .iter()
+            .find(|(_, tag)| is_tag(tag, "w:bookmarkEnd") && attribute(tag, "w:id") == id)
+        else {
+            continue;
+        };
+        let (Some(first), Some(last)) = (block(start), block(end)) else {
+            continue;
+        };
+
+        let (part, hash) = match name.split_once('_') {
+            Some((number, hash)) => match number.parse::<usize>() {
+                // Bookmarks name practicals by their index plus one
+                Ok(number) if number > 0 => (Part::Practical(number - 1), hash),
+                _ => continue,
+            },
+            None if PREFIX.to_string() + name == SUMMARY_BOOKMARK => (Part::Summary, ""),
+            None if PREFIX.to_string() + name == CONTENTS_BOOKMARK => (Part::Contents, ""),
+            None => continue,
+        };
+        regions.push(Region {
+            part,
+            hash: hash.to_string(),
+            span: first.start..last.end,
+        });
+    }
+    regions
+}
+
+/// Spans of the elements directly inside `<w:body>`.
+fn body_children(tags: &[(usize, &str)]) -> Vec<Range<usize>> {
+    let mut children = Vec::new();
+    let mut body = tags
+        .iter()
+        .skip_while(|(_, tag)| !is_tag(tag, "w:body"))
+        .skip(1);
+    let mut depth = 0;
+    let mut start = 0;
+    for &(position, tag) in &mut body {
+        let end = position + tag.len();
+        if tag.starts_with("</") {
+            if depth == 0 {
+                // </w:body>
+                break;
+            }
+            depth -= 1;
+            if depth == 0 {
+                children.push(start..end);
+            }
+        } else if tag.ends_with("/>") || tag.starts_with("<?") || tag.starts_with("<!") {
+            if depth == 0 {
+                children.push(position..end);
+            }
+        } else {
+            if depth == 0 {
+                start = position;
+            }
+            depth += 1;
+        }
+    }
+    children
+}
+
+/// Brings regions of the rendered document into the existing one, giving
+/// their paragraphs, pictures and bookmarks ids the existing document does
+/// not use and copying their images and styles.
+struct Import<'a> {
+    new: &'a Package,
+    /// `word/_rels/document.xml.rels` of the existing document.
+    rels: String,
+    /// Image files to add to the existing document.
+    media: Vec<(String, Vec<u8>)>,
+    /// Relationship ids of the imported images, by their rendered id.
+    images: BTreeMap<String, String>,
+    next_paragraph: u64,
+    next_picture: u64,
+    next_bookmark: u64,
+    next_image: usize,
+    existing: Vec<String>,
+    /// Ids in the existing document of the lists numbering the rendered
+    /// paragraphs, by their rendered id.
+    lists: BTreeMap<String, String>,
+    /// `word/numbering.xml` with the lists added to it, if any were.
+    numbering: Option<String>,
+}
+
+impl<'a> Import<'a> {
+    /// Import from `new` into `package`, numbering paragraphs with the
+    /// existing `list` if there is one.
+    fn new(package: &Package, new: &'a Package, list: Option<&str>) -> Result<Self, Error> {
+        let mut next_paragraph = 1;
+        let mut next_picture = 1;
+        let mut next_bookmark = 0;
+        for (name, data) in &package.files {
+            if !name.ends_with(".xml") {
+                continue;
+            }
+            let xml = String::from_utf8_lossy(data);
+            for (_, tag) in tags(&xml) {
+                let id = |name, radix| {
+                    attribute(tag, name).and_then(|id| u64::from_str_radix(id, radix).ok())
+                };
+                if let Some(id) = id("w14:paraId", 16) {
+                    next_paragraph = next_paragraph.max(id + 1);
+                }
+                if is_tag(tag, "wp:docPr") {
+                    if let Some(id) = id("id", 10) {
+                        next_picture = next_picture.max(id + 1);
+                    }
+                }
+                if is_tag(tag, "w:bookmarkStart") {
+                    if let Some(id) = id("w:id", 10) {
+                        next_bookmark = next_bookmark.max(id + 1);
+                    }
+                }
+            }
+        }
+
+        let rendered = new.text("word/document.xml").map_err(Error::Update)?;
+        let new_lists = numbering_ids(&rendered);
+        let mut lists = BTreeMap::new();
+        let mut numbering = None;
+        match (list, package.text("word/numbering.xml")) {
+            _ if new_lists.is_empty() => {}
+            (Some(list), _) => {
+                for id in &new_lists {
+                    lists.insert(id.to_string(), list.to_string());
+                }
+            }
+            (None, Ok(mut existing)) => {
+                let from = new.text("word/numbering.xml").map_err(Error::Update)?;
+                lists = add_numbering(&mut existing, &from, &new_lists);
+                numbering = Some(existing);
+            }
+            (None, Err(_)) => {
+                numbering = Some(new.text("word/numbering.xml").map_err(Error::Update)?);
+                for id in &new_lists {
+                    lists.insert(id.to_string(), id.to_string());
+                }
+            }
+        }
+
+        Ok(Import {
+            new,
+            rels: package
+                .text("word/_rels/document.xml.rels")
+                .map_err(Error::Update)?,
+            media: Vec::new(),
+            images: BTreeMap::new(),
+            next_paragraph,
+            next_picture,
+            next_bookmark,
+            next_image: 1,
+            existing: package.files.iter().map(|(name, _)| name.clone()).collect(),
+            lists,
+            numbering,
+        })
+    }
+
+    fn region(&mut self, xml: &str) -> String {
+        let mut bookmarks = BTreeMap::new();
+        let mut imported = String::with_capacity(xml.len());
+        let mut position = 0;
+        for (start, tag) in tags(xml) {
+            imported.push_str(&xml[position..start]);
+            position = start + tag.len();
+
+            let mut tag = tag.to_string();
+            if attribute(&tag, "w14:paraId").is_some() {
+                let id = format!("{:08x}", self.next_paragraph & 0x7fff_ffff);
+                self.next_paragraph += 1;
+                tag = set_attribute(&tag, "w14:paraId", &id);
+            }
+            if is_tag(&tag, "wp:docPr") {
+                tag = set_attribute(&tag, "id", &self.next_picture.to_string());
+                self.next_picture += 1;
+            }
+            if is_tag(&tag, "w:bookmarkStart") || is_tag(&tag, "w:bookmarkEnd") {
+                if let Some(id) = attribute(&tag, "w:id") {
+                    let id = bookmarks.entry(id.to_string()).or_insert_with(|| {
+                        self.next_bookmark += 1;
+                        (self.next_bookmark - 1).to_string()
+                    });
+                    tag = set_attribute(&tag, "w:id", id);
+                }
+            }
+            if is_tag(&tag, "w:numId") {
+                if let Some(id) = attribute(&tag, "w:val").and_then(|id| self.lists.get(id)) {
+                    tag = set_attribute(&tag, "w:val", id);
+                }
+            }
+            if let Some(id) = attribute(&tag, "r:embed").map(str::to_string) {
+                if let Some(id) = self.image(&id) {
+                    tag = set_attribute(&tag, "r:embed", &id);
+                }
+            }
+            imported.push_str(&tag);
+        }
+        imported.push_str(&xml[position..]);
+        imported
+    }
+
+    /// The relationship id in the existing document of the image with
+    /// relationship id `id` in the rendered one.
+    fn image(&mut self, id: &str) -> Option<String> {
+        if let Some(imported) = self.images.get(id) {
+            return Some(imported.clone());
+        }
+        let rels = self.new.text("word/_rels/document.xml.rels").ok()?;
+        let target = tags(&rels)
+            .into_iter()
+            .find(|(_, tag)| is_tag(tag, "Relationship") && attribute(tag, "Id") == Some(id))
+            .and_then(|(_, tag)| attribute(tag, "Target"))?
+            .to_string();
+        let data = self.new.file(&format!("word/{}", target))?.to_vec();
+
+        let (name, imported) = loop {
+            let n = self.next_image;
+            self.next_image += 1;
+            let name = format!("media/create-docx-{}.png", n);
+            let imported = format!("rIdCreateDocx{}", n);
+            let taken = self.existing.contains(&format!("word/{}", name))
+                || self.rels.contains(&format!("Id=\"{}\"", imported));
+            if !taken {
+                break (name, imported);
+            }
+        };
+        let relationship = format!(
+            r#"<Relationship Id="{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="{}" />"#,
+            imported, name
+        );
+        match self.rels.rfind("</Relationships>") {
+            Some(end) => self.rels.insert_str(end, &relationship),
+            None => return None,
+        }
+        self.media.push((format!("word/{}", name), data));
+        self.images.insert(id.to_string(), imported.clone());
+        Some(imported)
+    }
+
+    /// Write the relationships, images, lists and styles the imported
+    /// regions need into `package`.
+    fn finish(mut self, package: &mut Package) -> Result<(), Error> {
+        if !self.media.is_empty() {
+            package.add_default_type("png", "image/png");
+        }
+        if let Some(numbering) = self.numbering {
+            package.set("word/numbering.xml", numbering.into_bytes());
+            package.add_override_type(
+                "/word/numbering.xml",
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml",
+            );
+            // docx-rs only refers to the numbering when the record uses it
+            if !self.rels.contains("/relationships/numbering\"") {
+                if let Some(end) = self.rels.rfind("</Relationships>") {
+                    self.rels.insert_str(
+                        end,
+                        r#"<Relationship Id="rIdCreateDocxNumbering" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering" Target="numbering.xml" />"#,
+                    );
+                }
+            }
+        }
+        package.set("word/_rels/document.xml.rels", self.rels.into_bytes());
+        for (name, data) in self.media {
+            package.set(&name, data);
+        }
+
+        // Styles the regions use that the existing document lacks
+        if let (Ok(mut styles), Ok(new_styles)) = (
+            package.text("word/styles.xml"),
+            self.new.text("word/styles.xml"),
+        ) {
+            add_missing_styles(&mut styles, &new_styles);
+            package.set("word/styles.xml", styles.into_bytes());
+        }
+
+        // A table of contents is refreshed by Word when it opens the record
+        let refresh = self.new.text("word/settings.xml").is_ok_and(|settings| {
+            tags(&settings)
+                .iter()
+                .any(|(_, tag)| is_tag(tag, "w:updateFields"))
+        });
+        if let (true, Ok(settings)) = (refresh, package.text("word/settings.xml")) {
+            package.set("word/settings.xml", update_fields(&settings).into_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DocumentConfig, Entry};
+
+    fn record(entries: &[Entry]) -> Vec<u8> {
+        record_with(&DocumentConfig::default(), entries)
+    }
+
+    fn record_with(config: &DocumentConfig, entries: &[Entry]) -> Vec<u8> {
+        let mut docx = Vec::new();
+        config.write_docx(entries, &mut docx).unwrap();
+        docx
+    }
+
+    fn document(docx: &[u8]) -> String {
+        Package::read(docx)
+            .unwrap()
+            .text("word/document.xml")
+            .unwrap()
+    }
+
+    fn paragraph(text: &str) -> String {
+        format!("<w:p><w:r><w:t>{}</w:t></w:r></w:p>", text)
+    }
+
+    fn at(document: &str, text: &str) -> usize {
+        document
+            .find(text)
+            .unwrap_or_else(|| panic!("{:?} is missing", text))
+    }
+
+    #[test]
+    fn update_keeps_annotations_and_replaces_changed_practicals() {
+        let entry = |index, question: &str| Entry::new(index, question, ".py", "print(1)");
+        let existing = record(&[
+            entry(0, "Say one"),
+            entry(1, "Say two"),
+            entry(3, "Say four"),
+        ]);
+
+        // A teacher's remarks inside the first practical and before the
+        // second
+        let mut package = Package::read(&existing).unwrap();
+        let mut xml = document(&existing);
+        let first = at(&xml, "Say one");
+        let end = first + xml[first..].find("</w:p>").unwrap() + "</w:p>".len();
+        xml.insert_str(end, &paragraph("Good question"));
+        let second = xml.find(r#"w:name="_CreateDocx_2_"#).unwrap();
+        let second = xml[..second].rfind('<').unwrap();
+        xml.insert_str(second, &paragraph("Well done"));
+        package.set("word/document.xml", xml.into_bytes());
+        let existing = package.write().unwrap();
+
+        let rendered = record(&[
+            entry(0, "Say one"),
+            entry(1, "Say two again"),
+            entry(2, "Say three"),
+        ]);
+        let (updated, report) = update_docx(&existing, &rendered).unwrap();
+        assert_eq!(report.kept, [0]);
+        assert_eq!(report.replaced, [1]);
+        assert_eq!(report.added, [2]);
+        assert_eq!(report.orphaned, [3]);
+
+        let xml = document(&updated);
+        assert!(at(&xml, "Say one") < at(&xml, "Good question"));
+        assert!(at(&xml, "Good question") < at(&xml, "Well done"));
+        assert!(at(&xml, "Well done") < at(&xml, "Say two again"));
+        assert!(at(&xml, "Say two again") < at(&xml, "Say three"));
+        assert!(at(&xml, "Say three") < at(&xml, "Say four"));
+        assert!(!xml.contains("Say two<"));
+    }
+
+    #[test]
+    fn update_of_an_unchanged_record_keeps_everything() {
+        let entries = [Entry::new(0, "Say one", ".py", "print(1)")];
+        let existing = record(&entries);
+        let (updated, report) = update_docx(&existing, &record(&entries)).unwrap();
+        assert_eq!(report.kept, [0]);
+        assert!(report.replaced.is_empty() && report.added.is_empty());
+        assert_eq!(document(&updated), document(&existing));
+    }
+
+    #[test]
+    fn contents_and_summary_are_added_to_a_record_without_them() {
+        let existing = record(&[Entry::new(0, "Say one", ".py", "print(1)")]);
+        assert!(!document(&existing).contains(CONTENTS_BOOKMARK));
+        assert!(!document(&existing).contains(SUMMARY_BOOKMARK));
+
+        let mut config = DocumentConfig::default();
+        config.toc.show = true;
+        let entries = [Entry::new(0, "Say one", ".py", "print(1)")
+            .with_output(crate::Content::text("1"))
+            .with_expected(crate::ExpectedOutput::text("1"))];
+        let rendered = record_with(&config, &entries);
+        let (updated, report) = update_docx(&existing, &rendered).unwrap();
+        assert_eq!(report.replaced, [0]);
+        let xml = document(&updated);
+        assert!(at(&xml, CONTENTS_BOOKMARK) < at(&xml, "Say one"));
+        assert!(at(&xml, "Say one") < at(&xml, SUMMARY_BOOKMARK));
+        assert!(at(&xml, SUMMARY_BOOKMARK) < at(&xml, "Output checks"));
+        let settings = Package::read(&updated)
+            .unwrap()
+            .text("word/settings.xml")
+            .unwrap();
+        assert!(settings.contains("<w:updateFields "));
+
+        // Updating again replaces them rather than adding them twice
+        let (again, report) = update_docx(&updated, &rendered).unwrap();
+        assert_eq!(report.kept, [0]);
+        let xml = document(&again);
+        assert_eq!(xml.matches(CONTENTS_BOOKMARK).count(), 1);
+        assert_eq!(xml.matches(SUMMARY_BOOKMARK).count(), 1);
+        assert_eq!(xml.matches(">Output checks<").count(), 1);
+    }
+
+    #[test]
+    fn annotations_inside_a_replaced_practical_go_with_it() {
+        let entry = |index, question: &str| Entry::new(index, question, ".py", "print(1)");
+        let existing = record(&[entry(0, "Say one"), entry(1, "Say two")]);
+
+        // Word moved the bookmark of the second practical into its
+        // heading, and a teacher annotated both practicals
+        let mut package = Package::read(&existing).unwrap();
+        let mut xml = document(&existing);
+        let name = at(&xml, r#"w:name="_CreateDocx_2_"#);
+        let start = xml[..name].rfind('<').unwrap();
+        let end = name + xml[name..].find('>').unwrap() + 1;
+        let bookmark = xml[start..end].to_string();
+        xml.replace_range(start..end, "");
+        let heading = start + at(&xml[start..], "<w:r>");
+        xml.insert_str(heading, &bookmark);
+        for question in ["Say one", "Say two"] {
+            let position = at(&xml, question);
+            let end = position + at(&xml[position..], "</w:p>") + "</w:p>".len();
+            xml.insert_str(end, &paragraph(&format!("Remark on {}", question)));
+        }
+        package.set("word/document.xml", xml.into_bytes());
+        let existing = package.write().unwrap();
+
+        let rendered = record(&[entry(0, "Say one"), entry(1, "Say two again")]);
+        let (updated, report) = update_docx(&existing, &rendered).unwrap();
+        assert_eq!(report.kept, [0]);
+        assert_eq!(report.replaced, [1]);
+        let xml = document(&updated);
+        assert!(at(&xml, "Say one") < at(&xml, "Remark on Say one"));
+        assert!(at(&xml, "Remark on Say one") < at(&xml, "Say two again"));
+        assert!(!xml.contains("Remark on Say two"));
+        assert_eq!(xml.matches(r#"w:name="_CreateDocx_2_"#).count(), 1);
+    }
+
+    #[test]
+    fn overlapping_practicals_are_not_replaced() {
+        let entry = |index, question: &str| Entry::new(index, question, ".py", "print(1)");
+        let existing = record(&[entry(0, "Say one"), entry(1, "Say two")]);
+
+        // The bookmark of the first practical ends after the second one
+        let mut package = Package::read(&existing).unwrap();
+        let mut xml = document(&existing);
+        let name = at(&xml, r#"w:name="_CreateDocx_1_"#);
+        let start = xml[..name].rfind('<').unwrap();
+        let id = attribute(&xml[start..], "w:id").unwrap().to_string();
+        let end_tag = format!(r#"<w:bookmarkEnd w:id="{}" />"#, id);
+        xml = xml.replacen(&end_tag, "", 1);
+        let last = xml.rfind("<w:sectPr").unwrap();
+        xml.insert_str(last, &end_tag);
+        package.set("word/document.xml", xml.into_bytes());
+        let existing = package.write().unwrap();
+
+        let rendered = record(&[entry(0, "Say one again"), entry(1, "Say two again")]);
+        match update_docx(&existing, &rendered) {
+            Err(Error::Update(message)) => {
+                assert_eq!(
+                    message,
+                    "the bookmarks around practical 1 and practical 2 overlap"
+                )
+            }
+            other => panic!("{:?}", other.map(|(_, report)| report)),
+        }
+    }
+}
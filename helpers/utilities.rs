@@ -1,65 +1,811 @@
 use docx_rs::*;
 use rtf_parser::RtfDocument;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-
-use crate::ZigOutput;
-
-#[derive(Debug, Serialize, Deserialize)]
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::cache::{self, Cache, CachedPractical};
+use crate::check::{CheckOutcome, DiffLine, Normalization};
+use crate::entry::{
+    remove_ansi_codes, signal_name, Content, ContentType, Entry, ImageFile, ProgramRun, SourceFile,
+    Stream, TranscriptChunk, Unit,
+};
+use crate::error::Error;
+use crate::layout::{self, LineLayout};
+use crate::numbering::{find_placeholder, replace_number, NumberingFormat};
+use crate::package;
+use crate::reference;
+use crate::screenshot::Screenshot;
+use crate::update;
+
+/// Width between the margins of the default A4 page, in twips, used unless
+/// `reference_docx` sets another page.
+const TEXT_WIDTH: usize = 11906 - 2 * 1701;
+
+const EMU_PER_TWIP: u32 = 635;
+
+/// Size of a pixel at 96 dpi.
+const EMU_PER_PIXEL: u32 = 9525;
+
+/// Id of the list numbering practical headings. docx-rs defines list 1.
+pub(crate) const HEADING_NUMBERING: usize = 2;
+
+/// Stands in for the `{n}` of a heading while it is rendered.
+const NUMBER_MARK: char = '\u{FFFC}';
+
+/// Layout of the generated record, read from `format.toml`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DocumentConfig {
+    /// Paragraph printed above every practical, e.g. "Task {n}".
     #[serde(default)]
     pub header: Option<Paragraph>,
+    /// Paragraph holding the question text taken from questions.txt.
     pub question: Paragraph,
+    /// Titled section holding the source code of the practical.
     pub solution: SectionWithTitle,
+    /// Titled section holding the captured program output.
     pub output: SectionWithTitle,
+    /// Paragraph printed below every practical.
     #[serde(default)]
     pub footer: Option<Paragraph>,
+    /// Images shown below questions and program outputs.
+    #[serde(default)]
+    pub images: ImageLayout,
+    /// List of the expected output checks printed at the end of the record.
+    #[serde(default)]
+    pub check_summary: CheckSummary,
+    /// How practicals show in Word's navigation pane and cross-references.
+    #[serde(default)]
+    pub headings: Headings,
+    /// Table of contents printed before the first practical.
+    #[serde(default)]
+    pub toc: Contents,
+    /// Heading opening each unit of the syllabus, when practicals are
+    /// grouped into units.
+    #[serde(default)]
+    pub units: Units,
+    /// How practical numbers are printed, e.g. `start = 13` or
+    /// `style = "upper-roman"`.
+    #[serde(default)]
+    pub numbering: NumberingFormat,
+    /// Refuse to generate the record when any program exited with a
+    /// non-zero status or was killed.
+    #[serde(default = "default_false")]
+    pub fail_on_error: bool,
+    /// Values for custom placeholders in the output file name, e.g.
+    /// `roll_no = "21CS042"` for `--output "{roll_no}.docx"`.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// A .docx whose styles, numbering, theme, fonts, page setup and headers
+    /// and footers the record takes, like pandoc's reference document.
+    /// Relative to the folder of format.toml.
+    #[serde(default)]
+    pub reference_docx: Option<PathBuf>,
+    /// Formatting of the text no style or paragraph formats.
+    #[serde(default)]
+    pub defaults: TextDefaults,
+    /// Word styles written to the document, by style id, e.g.
+    /// `[styles.Code]`. Defining one of Word's built-in styles such as
+    /// `Heading1` replaces it.
+    #[serde(default)]
+    pub styles: BTreeMap<String, StyleDefinition>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Formatting and text of a single block. `text` may contain the
+/// placeholders `{n}`, `{question}`, `{solution}` and `{output}`, and
+/// `{unit}`, `{unit_title}` and `{n_in_unit}` for practicals in units.
+/// Numbers take filters such as `{n|upper-roman}`, see [`crate::numbering`].
+///
+/// Formatting left unset comes from the paragraph's `style`, and from
+/// `[defaults]` where the style does not set it either, so that restyling
+/// the style in Word restyles the paragraph.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Paragraph {
-    #[serde(default = "default_size")]
-    pub size: usize,
+    /// Font size in points.
+    #[serde(default)]
+    pub size: Option<usize>,
+    /// Text to print; placeholders are replaced per practical.
     pub text: String,
-    #[serde(default = "default_align")]
-    pub align: String,
-    #[serde(default = "default_false")]
-    pub bold: bool,
-    #[serde(default = "default_false")]
-    pub italic: bool,
-    #[serde(default = "default_false")]
-    pub underline: bool,
+    /// Horizontal alignment of the paragraph.
+    #[serde(default)]
+    #[schemars(schema_with = "crate::schema::align_schema")]
+    pub align: Option<String>,
+    /// Print the text in bold.
+    #[serde(default)]
+    pub bold: Option<bool>,
+    /// Print the text in italics.
+    #[serde(default)]
+    pub italic: Option<bool>,
+    /// Underline the text with a single line.
+    #[serde(default)]
+    pub underline: Option<bool>,
+    /// Font family used for the text.
+    #[serde(default)]
+    pub font: Option<String>,
+    /// Text color as a hex string such as "#1e66f5".
+    #[serde(default)]
+    pub color: Option<String>,
+    /// Line spacing multiplier.
+    #[serde(default)]
+    pub line_spacing: Option<f32>,
+    /// Space above the paragraph, in points.
+    #[serde(default)]
+    pub margin_top: Option<u32>,
+    /// Space below the paragraph, in points.
+    #[serde(default)]
+    pub margin_bottom: Option<u32>,
+    /// Left indentation of the paragraph, in points.
+    #[serde(default)]
+    pub indent: Option<u32>,
+    /// Paragraph style of the paragraph: one of Word's built-in styles or a
+    /// style defined under `[styles]`.
+    #[serde(default = "default_style")]
+    #[schemars(schema_with = "crate::schema::style_schema")]
+    pub style: String,
+    /// Character style applied to the text, defined under `[styles]` with
+    /// `kind = "character"`.
+    #[serde(default)]
+    pub character_style: Option<String>,
+}
+
+/// Formatting of the text no style or paragraph formats, written as the
+/// document defaults.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TextDefaults {
+    /// Font family.
     #[serde(default = "default_font")]
     pub font: String,
-    #[serde(default = "default_color")]
-    pub color: String,
+    /// Font size in points.
+    #[serde(default = "default_size")]
+    pub size: usize,
+    /// Line spacing multiplier.
     #[serde(default = "default_line_spacing")]
     pub line_spacing: f32,
+    /// Space above every paragraph, in points.
     #[serde(default = "default_zero")]
     pub margin_top: u32,
+    /// Space below every paragraph, in points.
     #[serde(default = "default_zero")]
     pub margin_bottom: u32,
-    #[serde(default = "default_zero")]
-    pub indent: u32,
-    #[serde(default = "default_style")]
-    pub style: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A named Word style, written to the document so that it can be changed
+/// there later. Paragraphs use it through `style`, or `character_style` for
+/// a character style.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct StyleDefinition {
+    /// Name shown in Word's style gallery; the key of the table when unset.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Whether the style formats whole paragraphs or runs of text.
+    #[serde(default)]
+    pub kind: StyleKind,
+    /// Style whose formatting this one inherits where it sets none.
+    #[serde(default)]
+    pub based_on: Option<String>,
+    /// Style Word gives the paragraph typed after one in this style.
+    #[serde(default)]
+    pub next: Option<String>,
+    /// Font size in points.
+    #[serde(default)]
+    pub size: Option<usize>,
+    /// Font family.
+    #[serde(default)]
+    pub font: Option<String>,
+    /// Text color as a hex string.
+    #[serde(default)]
+    pub color: Option<String>,
+    /// Print the text in bold.
+    #[serde(default)]
+    pub bold: Option<bool>,
+    /// Print the text in italics.
+    #[serde(default)]
+    pub italic: Option<bool>,
+    /// Underline the text with a single line.
+    #[serde(default)]
+    pub underline: Option<bool>,
+    /// Horizontal alignment. Paragraph styles only.
+    #[serde(default)]
+    #[schemars(schema_with = "crate::schema::align_schema")]
+    pub align: Option<String>,
+    /// Line spacing multiplier. Paragraph styles only.
+    #[serde(default)]
+    pub line_spacing: Option<f32>,
+    /// Space above the paragraph, in points. Paragraph styles only.
+    #[serde(default)]
+    pub margin_top: Option<u32>,
+    /// Space below the paragraph, in points. Paragraph styles only.
+    #[serde(default)]
+    pub margin_bottom: Option<u32>,
+    /// Left indentation, in points. Paragraph styles only.
+    #[serde(default)]
+    pub indent: Option<u32>,
+    /// Border drawn around the paragraph. Paragraph styles only.
+    #[serde(default)]
+    pub border: Option<StyleBorder>,
+    /// Level in Word's navigation pane and table of contents, 1 for the
+    /// top. `Heading1` to `Heading6` default to their number. Paragraph
+    /// styles only.
+    #[serde(default)]
+    pub outline_level: Option<usize>,
+}
+
+/// What a [`StyleDefinition`] formats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StyleKind {
+    /// Whole paragraphs.
+    #[default]
+    Paragraph,
+    /// Runs of text within a paragraph.
+    Character,
+}
+
+/// Border on all four sides of the paragraphs in a style.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StyleBorder {
+    /// Border color as a hex string.
+    #[serde(default = "default_color")]
+    pub color: String,
+    /// Border width in eighths of a point.
+    #[serde(default = "default_container_border_size")]
+    pub size: usize,
+    /// Space between the border and the text, in points.
+    #[serde(default = "default_border_space")]
+    pub space: usize,
+}
+
+/// A section made of a title paragraph followed by its content. The content
+/// fields sit directly in the table, the title lives in a `title` sub-table.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub struct SectionWithTitle {
     #[serde(flatten)]
     pub content: Paragraph,
+    /// Paragraph printed above the section content, e.g. "Code:".
+    pub title: Paragraph,
+    /// Captions and order of the files of a multi-file solution. Only used
+    /// by `[solution]`.
+    #[serde(default)]
+    pub files: FileListing,
+    /// Line numbers printed left of the code. Only used by `[solution]`.
+    #[serde(default)]
+    pub line_numbers: LineNumbers,
+    /// Shaded, bordered box around each code listing or program output.
+    #[serde(default)]
+    pub container: Container,
+    /// Tab expansion and handling of lines wider than the page.
+    #[serde(default)]
+    pub lines: LineLayout,
+    /// Titles of the runs when a program was run on several inputs. Only
+    /// used by `[output]`.
+    #[serde(default)]
+    pub runs: RunListing,
+    /// Style of what the user typed in an interactive transcript. Only used
+    /// by `[output]`.
+    #[serde(default = "default_user_input")]
+    pub user_input: TextStyle,
+    /// Line telling how the program ended. Only used by `[output]`.
+    #[serde(default)]
+    pub status: StatusLine,
+    /// Block showing what the program printed on stderr. Only used by
+    /// `[output]`.
+    #[serde(default)]
+    pub stderr: StderrBlock,
+    /// Comparison of the output with the expected output. Only used by
+    /// `[output]`.
+    #[serde(default)]
+    pub check: OutputCheck,
+    /// Picture of a terminal window embedded in place of the output text.
+    /// Only used by `[output]`.
+    #[serde(default)]
+    pub screenshot: Screenshot,
+}
+
+/// How runs that declare an expected output are compared and reported.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct OutputCheck {
+    #[serde(flatten)]
+    pub normalization: Normalization,
+    /// Badge printed below a run whose output matches.
+    #[serde(default = "default_check_pass")]
+    pub pass: Paragraph,
+    /// Badge printed below a run whose output differs. When the expected
+    /// output could not be read, the reason is printed on the next line.
+    #[serde(default = "default_check_fail")]
+    pub fail: Paragraph,
+    /// Print a line-level diff below the fail badge.
+    #[serde(default = "default_true")]
+    pub diff: bool,
+    /// Formatting of lines only in the expected output, prefixed with "-".
+    #[serde(default = "default_diff_expected")]
+    pub diff_expected: TextStyle,
+    /// Formatting of lines only in the actual output, prefixed with "+".
+    #[serde(default = "default_diff_actual")]
+    pub diff_actual: TextStyle,
+}
+
+/// List of the checked practicals printed after the last one. Only printed
+/// when at least one run declares an expected output.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CheckSummary {
+    /// Print the summary.
+    #[serde(default = "default_true")]
+    pub show: bool,
+    /// Title of the summary.
+    #[serde(default = "default_summary_title")]
+    pub title: Paragraph,
+    /// Line printed per checked practical; `{n}`, `{question}`, `{result}`,
+    /// `{passed}` and `{total}` (matching and checked runs) are replaced.
+    #[serde(default = "default_summary_line")]
+    pub line: Paragraph,
+    /// Value of `{result}` when every checked run matches.
+    #[serde(default = "default_summary_passed")]
+    pub passed: String,
+    /// Value of `{result}` when any checked run differs.
+    #[serde(default = "default_summary_failed")]
+    pub failed: String,
+    /// Closing line; `{passed}` and `{total}` (matching and checked
+    /// practicals) are replaced.
+    #[serde(default = "default_summary_total")]
+    pub total: Paragraph,
+}
+
+/// Headings marking each practical, which Word lists in its navigation pane
+/// and offers to "Go To" and cross-references.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Headings {
+    /// Print `[header]` as Heading 1 and `[question]` as Heading 2 when they
+    /// leave `style` at Normal. Only their line holding `{n}`, or else their
+    /// first line, becomes a heading.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Number the practicals with Word's numbering instead of printing
+    /// `{n}`, so that Word renumbers them when they are moved. The text
+    /// before `{n}` on its line, e.g. "Practical ", becomes part of the
+    /// number. Applies to `[header]`, or `[question]` without one, when its
+    /// text holds `{n}`.
+    #[serde(default = "default_false")]
+    pub word_numbering: bool,
+    /// Name of the bookmark around the heading of each practical, for
+    /// cross-references; `{n}` is replaced by its number in decimal, from
+    /// `[numbering] start`. Empty to leave it out.
+    #[serde(default = "default_heading_bookmark")]
+    pub bookmark: String,
+}
+
+/// A Word table of contents over the practical headings. Word fills in the
+/// page numbers when it opens the record; until then, and in viewers that
+/// do not update fields, it lists the headings without them.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Contents {
+    /// Print the table of contents.
+    #[serde(default = "default_false")]
+    pub show: bool,
+    /// Paragraph printed above the table.
+    #[serde(default = "default_contents_title")]
+    pub title: Paragraph,
+    /// Heading levels listed: 1 lists the `[header]` of every practical,
+    /// 2 adds the `[question]`. Units, when there are any, come first and
+    /// push the practicals down a level.
+    #[serde(default = "default_contents_depth")]
+    pub depth: usize,
+}
+
+/// Units of the syllabus, such as "Unit 1: Arrays", started by `@unit:` in
+/// questions.txt. Each opens with its heading above its first practical.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Units {
+    /// Paragraph printed above the first practical of every unit, as
+    /// Heading 1. `[header]` and `[question]` then become Heading 2 and 3.
+    #[serde(default = "default_unit_heading")]
+    pub heading: Paragraph,
+    /// Print the unit heading on a page of its own, before the first
+    /// practical of the unit.
+    #[serde(default = "default_false")]
+    pub page_break: bool,
+}
+
+/// When an optional block is printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Show {
+    Always,
+    /// Only when the program exited with a non-zero status or was killed.
+    #[default]
+    OnFailure,
+    Never,
+}
+
+/// Line printed below a run telling how the program ended. `{status}`
+/// (e.g. "exited with code 1"), `{exit_code}`, `{signal}` and `{duration}`
+/// are replaced.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StatusLine {
+    /// When to print the line.
+    #[serde(default)]
+    pub show: Show,
+    #[serde(flatten)]
+    pub paragraph: Paragraph,
+}
+
+/// Block printed below a run with what the program wrote to stderr.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StderrBlock {
+    /// When to print the block; it is never printed when stderr is empty.
+    #[serde(default = "default_show_always")]
+    pub show: Show,
+    /// Title printed above the block; leave `text` empty to print none.
+    #[serde(default = "default_stderr_title")]
+    pub title: Paragraph,
+    /// Formatting of the stderr text.
+    #[serde(default = "default_stderr_style")]
+    pub style: TextStyle,
+}
+
+/// Character formatting applied on top of a section's own formatting.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TextStyle {
+    /// Print the text in bold.
+    #[serde(default = "default_false")]
+    pub bold: bool,
+    /// Print the text in italics.
+    #[serde(default = "default_false")]
+    pub italic: bool,
+    /// Underline the text with a single line.
+    #[serde(default = "default_false")]
+    pub underline: bool,
+    /// Text color as a hex string such as "#1e66f5".
+    #[serde(default)]
+    pub color: Option<String>,
+    /// Word highlight color name such as "yellow" or "lightGray".
+    #[serde(default)]
+    pub highlight: Option<String>,
+}
+
+/// Line numbers of a code listing. The numbers sit in their own table
+/// column, so a wrapped line keeps a single number and copying the code
+/// leaves the numbers behind.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct LineNumbers {
+    /// Print line numbers.
+    #[serde(default = "default_false")]
+    pub enabled: bool,
+    /// Number of the first line.
+    #[serde(default = "default_line_start")]
+    pub start: usize,
+    /// Only number lines whose number is a multiple of `step`; 1 numbers
+    /// every line.
+    #[serde(default = "default_line_step")]
+    pub step: usize,
+    /// Text printed after each number, between the gutter and the code.
+    #[serde(default = "default_line_separator")]
+    pub separator: String,
+    /// Color of the numbers as a hex string such as "#8c8fa1".
+    #[serde(default = "default_line_number_color")]
+    pub color: String,
+}
+
+/// Box drawn around a listing: a table with a background, a border and
+/// inner padding, holding the line numbers in a second column if enabled.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Container {
+    /// Draw the box.
+    #[serde(default = "default_false")]
+    pub enabled: bool,
+    /// Background color as a hex string such as "#eff1f5".
+    #[serde(default = "default_container_background")]
+    pub background: String,
+    /// Border color as a hex string.
+    #[serde(default = "default_container_border_color")]
+    pub border_color: String,
+    /// Border width in eighths of a point.
+    #[serde(default = "default_container_border_size")]
+    pub border_size: usize,
+    /// Space between the border and the text, in twips (1/20 of a point).
+    #[serde(default = "default_container_padding")]
+    pub padding: usize,
+    /// Label printed in the top right corner; `{language}` is replaced with
+    /// the language of the file. Printed only when not empty after
+    /// replacement.
+    #[serde(default = "default_container_label")]
+    pub label: Paragraph,
+}
+
+/// How images listed by an entry are embedded. An image keeps its size at
+/// 96 dpi unless it is wider than `max_width`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ImageLayout {
+    /// Horizontal alignment of the images.
+    #[serde(default = "default_image_align")]
+    #[schemars(schema_with = "crate::schema::align_schema")]
+    pub align: String,
+    /// Largest width of an image, in percent of the text width.
+    #[serde(default = "default_image_max_width")]
+    pub max_width: usize,
+    /// Caption printed below each image that has one; `{caption}`,
+    /// `{filename}` and `{image}` (the number of the image within its
+    /// question or run) are replaced.
+    #[serde(default = "default_image_caption")]
+    pub caption: Paragraph,
+    /// Alt text of images that do not give one; `{caption}` (the caption,
+    /// or the file name when there is none) and `{filename}` are replaced.
+    #[serde(default = "default_image_alt")]
+    pub alt: String,
+    /// Paragraph printed in place of an image that cannot be read;
+    /// `{filename}` and `{error}` are replaced.
+    #[serde(default = "default_image_missing")]
+    pub missing: Paragraph,
+}
+
+/// How several runs of a program are printed.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RunListing {
+    /// Title printed above each run; `{run}` (the run number), `{label}` and
+    /// `{stdin}` are replaced. Only printed when there is more than one run.
+    #[serde(default = "default_run_title")]
     pub title: Paragraph,
+    /// Paragraph showing what was fed to the program, printed for runs that
+    /// have stdin. `{stdin}` is replaced; leave `text` empty to hide it.
+    #[serde(default = "default_run_input")]
+    pub input: Paragraph,
+}
+
+/// How the files of a multi-file solution are listed.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FileListing {
+    /// Caption printed above each file; `{filename}` and `{language}` are
+    /// replaced. Only printed when a solution has more than one file.
+    #[serde(default = "default_file_title")]
+    pub title: Paragraph,
+    /// Order in which the files are printed.
+    #[serde(default)]
+    pub order: FileOrder,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileOrder {
+    /// Keep the order the files were listed in.
+    #[default]
+    AsListed,
+    /// Sort the files by name.
+    Alphabetical,
+    /// Headers first, then other sources, then `main.*`.
+    HeadersFirst,
+}
+
+fn default_file_title() -> Paragraph {
+    Paragraph {
+        text: "File: {filename}".to_string(),
+        bold: Some(true),
+        ..Default::default()
+    }
+}
+
+fn default_line_start() -> usize {
+    1
+}
+
+fn default_line_step() -> usize {
+    1
+}
+
+fn default_line_separator() -> String {
+    " \u{2502}".to_string()
+}
+
+fn default_line_number_color() -> String {
+    "#8c8fa1".to_string()
+}
+
+fn default_container_background() -> String {
+    "#eff1f5".to_string()
+}
+
+fn default_container_border_color() -> String {
+    "#9ca0b0".to_string()
+}
+
+fn default_container_border_size() -> usize {
+    4
+}
+
+fn default_container_padding() -> usize {
+    120
+}
+
+fn default_container_label() -> Paragraph {
+    Paragraph {
+        size: Some(9),
+        text: "{language}".to_string(),
+        align: Some("right".to_string()),
+        italic: Some(true),
+        color: Some("#6c6f85".to_string()),
+        ..Default::default()
+    }
+}
+
+fn default_image_align() -> String {
+    "center".to_string()
+}
+
+fn default_image_max_width() -> usize {
+    100
+}
+
+fn default_image_caption() -> Paragraph {
+    Paragraph {
+        size: Some(10),
+        text: "Figure {n}.{image}: {caption}".to_string(),
+        align: Some("center".to_string()),
+        italic: Some(true),
+        ..Default::default()
+    }
+}
+
+fn default_image_alt() -> String {
+    "{caption}".to_string()
+}
+
+fn default_image_missing() -> Paragraph {
+    Paragraph {
+        text: "[Image {filename} could not be read: {error}]".to_string(),
+        italic: Some(true),
+        color: Some(default_error_color()),
+        ..Default::default()
+    }
+}
+
+fn default_run_title() -> Paragraph {
+    Paragraph {
+        text: "Run {run}".to_string(),
+        bold: Some(true),
+        ..Default::default()
+    }
+}
+
+fn default_run_input() -> Paragraph {
+    Paragraph {
+        text: "Input: {stdin}".to_string(),
+        italic: Some(true),
+        ..Default::default()
+    }
+}
+
+fn default_user_input() -> TextStyle {
+    TextStyle {
+        bold: true,
+        ..Default::default()
+    }
+}
+
+fn default_show_always() -> Show {
+    Show::Always
+}
+
+fn default_stderr_title() -> Paragraph {
+    Paragraph {
+        text: String::new(),
+        bold: Some(true),
+        ..Default::default()
+    }
+}
+
+fn default_stderr_style() -> TextStyle {
+    TextStyle {
+        color: Some(default_error_color()),
+        ..Default::default()
+    }
+}
+
+fn default_error_color() -> String {
+    "#d20f39".to_string()
+}
+
+fn default_success_color() -> String {
+    "#40a02b".to_string()
+}
+
+fn default_check_pass() -> Paragraph {
+    Paragraph {
+        text: "\u{2714} Output matches the expected output".to_string(),
+        bold: Some(true),
+        color: Some(default_success_color()),
+        ..Default::default()
+    }
+}
+
+fn default_check_fail() -> Paragraph {
+    Paragraph {
+        text: "\u{2718} Output differs from the expected output".to_string(),
+        bold: Some(true),
+        color: Some(default_error_color()),
+        ..Default::default()
+    }
+}
+
+fn default_diff_expected() -> TextStyle {
+    TextStyle {
+        color: Some(default_error_color()),
+        ..Default::default()
+    }
+}
+
+fn default_diff_actual() -> TextStyle {
+    TextStyle {
+        color: Some(default_success_color()),
+        ..Default::default()
+    }
+}
+
+fn default_summary_title() -> Paragraph {
+    Paragraph {
+        size: Some(14),
+        text: "Output checks".to_string(),
+        bold: Some(true),
+        underline: Some(true),
+        ..Default::default()
+    }
+}
+
+fn default_contents_title() -> Paragraph {
+    Paragraph {
+        size: Some(16),
+        text: "Contents".to_string(),
+        bold: Some(true),
+        ..Default::default()
+    }
+}
+
+fn default_contents_depth() -> usize {
+    2
+}
+
+fn default_unit_heading() -> Paragraph {
+    Paragraph {
+        size: Some(20),
+        text: "Unit {unit}: {unit_title}".to_string(),
+        align: Some("center".to_string()),
+        bold: Some(true),
+        ..Default::default()
+    }
+}
+
+fn default_summary_line() -> Paragraph {
+    Paragraph {
+        text: "Practical {n}: {result}".to_string(),
+        ..Default::default()
+    }
+}
+
+fn default_summary_passed() -> String {
+    "passed".to_string()
+}
+
+fn default_summary_failed() -> String {
+    "failed".to_string()
+}
+
+fn default_summary_total() -> Paragraph {
+    Paragraph {
+        text: "{passed} of {total} practicals match the expected output".to_string(),
+        bold: Some(true),
+        ..Default::default()
+    }
 }
 
 fn default_size() -> usize {
     12
 }
-fn default_align() -> String {
-    "left".to_string()
-}
 fn default_false() -> bool {
     false
 }
+fn default_true() -> bool {
+    true
+}
 fn default_font() -> String {
     "Arial".to_string()
 }
@@ -72,112 +818,513 @@ fn default_line_spacing() -> f32 {
 fn default_zero() -> u32 {
     0
 }
+fn default_heading_bookmark() -> String {
+    "practical_{n}".to_string()
+}
+fn default_border_space() -> usize {
+    1
+}
 fn default_style() -> String {
     "Normal".to_string()
 }
 
 impl Paragraph {
-    fn get_alignment(&self) -> AlignmentType {
-        match self.align.to_lowercase().as_str() {
-            "center" => AlignmentType::Center,
-            "right" => AlignmentType::Right,
-            "justify" => AlignmentType::Justified,
-            _ => AlignmentType::Left,
-        }
+    pub fn to_docx(&self, replacer: &Entry) -> Vec<docx_rs::Paragraph> {
+        self.text_to_docx(&self.replace_text(replacer))
     }
 
-    pub fn to_docx(&self, replacer: &ZigOutput) -> Vec<docx_rs::Paragraph> {
-        let replaced = self.replace_text(replacer);
+    /// Format already replaced text, one docx paragraph per line.
+    pub fn text_to_docx(&self, replaced: &str) -> Vec<docx_rs::Paragraph> {
         let mut paragraphs: Vec<docx_rs::Paragraph> = Vec::new();
 
         if replaced.is_empty() {
-            paragraphs.push(
-                docx_rs::Paragraph::new()
-                    .add_run(Run::new().add_text(""))
-                    .style(&self.style),
-            );
+            let run = Run::new().add_text("");
+            paragraphs.push(self.format_paragraph(docx_rs::Paragraph::new().add_run(run)));
             return paragraphs;
         }
 
-        let lines = replaced.split('\n');
-
-        for line in lines {
-            let mut run = Run::new()
-                .size(self.size * 2)
-                .fonts(
-                    RunFonts::new()
-                        .east_asia(&self.font)
-                        .ascii(&self.font)
-                        .hi_ansi(&self.font),
-                )
-                .color(&self.color.replace('#', ""));
-
-            if self.bold {
-                run = run.bold();
-            }
-            if self.italic {
-                run = run.italic();
-            }
-            if self.underline {
-                run = run.underline("single");
-            }
-
-            run = run.add_text(line);
-
-            let p = docx_rs::Paragraph::new()
-                .align(self.get_alignment())
-                .add_run(run)
-                .style(&self.style);
-
-            paragraphs.push(p);
+        for line in replaced.split('\n') {
+            let run = self.format_run(Run::new()).add_text(line);
+            paragraphs.push(self.format_paragraph(docx_rs::Paragraph::new().add_run(run)));
         }
 
         paragraphs
     }
 
-    pub fn replace_text(&self, replacer: &ZigOutput) -> String {
-        let mut replaced = self.text.clone();
-        if replaced.contains("{n}") {
-            replaced = replaced.replace("{n}", &(replacer.index + 1).to_string());
+    /// `run` with the character formatting set on the paragraph.
+    fn format_run(&self, mut run: Run) -> Run {
+        if let Some(style) = &self.character_style {
+            run = run.style(style);
         }
-        if replaced.contains("{question}") {
-            replaced = replaced.replace("{question}", &replacer.question);
+        if let Some(size) = self.size {
+            run = run.size(size * 2);
         }
-        if replaced.contains("{solution}") {
-            replaced = replaced.replace("{solution}", &replacer.code);
+        if let Some(font) = &self.font {
+            run = run.fonts(RunFonts::new().east_asia(font).ascii(font).hi_ansi(font));
         }
-        if replaced.contains("{output}") {
-            replaced = replaced.replace("{output}", &replacer.output_rtf);
+        if let Some(color) = &self.color {
+            run = run.color(color.replace('#', ""));
         }
-        replaced
+        match self.bold {
+            Some(true) => run = run.bold(),
+            Some(false) => run = run.disable_bold(),
+            None => {}
+        }
+        match self.italic {
+            Some(true) => run = run.italic(),
+            Some(false) => run = run.disable_italic(),
+            None => {}
+        }
+        match self.underline {
+            Some(true) => run = run.underline("single"),
+            Some(false) => run = run.underline("none"),
+            None => {}
+        }
+        run
     }
-}
-
-impl SectionWithTitle {
-    pub fn to_docx(&self, replacer: &ZigOutput) -> Vec<docx_rs::Paragraph> {
-        let mut paragraphs = self.title.to_docx(replacer);
 
-        if self.content.text.contains("{solution}") {
-            if let Some(ref code_rtf) = replacer.code_rtf {
-                paragraphs.extend(self.parse_code_with_rtf(&replacer.code, code_rtf));
+    /// `paragraph` in the paragraph's style, with the paragraph formatting
+    /// set on it.
+    fn format_paragraph(&self, mut paragraph: docx_rs::Paragraph) -> docx_rs::Paragraph {
+        paragraph = paragraph.style(&self.style);
+        if let Some(align) = &self.align {
+            paragraph = paragraph.align(alignment(align));
+        }
+        if let Some(spacing) = spacing(self.line_spacing, self.margin_top, self.margin_bottom) {
+            paragraph = paragraph.line_spacing(spacing);
+        }
+        if let Some(indent) = self.indent {
+            paragraph = paragraph.indent(Some(indent as i32 * 20), None, None, None);
+        }
+        paragraph
+    }
+
+    /// The text for `replacer` and the line of it holding `{n}`, or else 0.
+    /// When `numbered`, the number and the text before it on its line are
+    /// left for Word's numbering to print.
+    fn heading_text(&self, replacer: &Entry, numbered: bool) -> (String, usize) {
+        let mut marked = self.clone();
+        let mut number = String::new();
+        if let Some((span, _)) = find_placeholder(&self.text, "n") {
+            let (before, after) = (&self.text[..span.start], &self.text[span.end..]);
+            // Word prints the text before the number as part of it
+            let kept = if numbered {
+                before.rfind('\n').map_or("", |end| &before[..=end])
             } else {
-                paragraphs.extend(self.content.to_docx(replacer));
+                number = replace_number(&self.text[span], "n", replacer.index, &replacer.numbering);
+                before
+            };
+            marked.text = format!("{}{}{}", kept, NUMBER_MARK, after);
+        }
+        let replaced = marked.replace_text(replacer);
+        let line = replaced
+            .find(NUMBER_MARK)
+            .map_or(0, |mark| replaced[..mark].matches('\n').count());
+        (replaced.replacen(NUMBER_MARK, &number, 1), line)
+    }
+
+    pub fn replace_text(&self, replacer: &Entry) -> String {
+        let solution = replacer
+            .source_files()
+            .iter()
+            .map(|file| file.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        self.replace_text_with_solution(replacer, &solution)
+    }
+
+    fn replace_text_with_solution(&self, replacer: &Entry, solution: &str) -> String {
+        let mut replaced = replace_number(&self.text, "n", replacer.index, &replacer.numbering);
+        match &replacer.unit {
+            Some(unit) => {
+                let position = unit.position.unwrap_or(0);
+                // Counted from 0 so that a unit numbered 0 prints as 0,
+                // in decimal whatever the filters
+                let from_zero = NumberingFormat {
+                    start: 0,
+                    ..Default::default()
+                };
+                let number = unit.number.unwrap_or(1);
+                replaced = replace_number(&replaced, "unit", number, &from_zero);
+                replaced = replace_number(
+                    &replaced,
+                    "n_in_unit",
+                    position,
+                    &NumberingFormat::default(),
+                );
+            }
+            None => {
+                while let Some((span, _)) = find_placeholder(&replaced, "unit") {
+                    remove_label(&mut replaced, span);
+                }
+                while let Some(start) = replaced.find("{unit_title}") {
+                    remove_label(&mut replaced, start..start + "{unit_title}".len());
+                }
+                // Outside units, practicals are numbered through the record
+                replaced =
+                    replace_number(&replaced, "n_in_unit", replacer.index, &replacer.numbering);
+            }
+        }
+        if replaced.contains("{unit_title}") {
+            let title = replacer
+                .unit
+                .as_ref()
+                .map_or("", |unit| unit.title.as_str());
+            replaced = replaced.replace("{unit_title}", title);
+        }
+        if replaced.contains("{question}") {
+            replaced = replaced.replace("{question}", &replacer.question);
+        }
+        if replaced.contains("{solution}") {
+            replaced = replaced.replace("{solution}", solution);
+        }
+        if replaced.contains("{output}") {
+            let output = replacer
+                .runs()
+                .iter()
+                .map(|run| run.plain_text())
+                .collect::<Vec<_>>()
+                .join("\n");
+            replaced = replaced.replace("{output}", &output);
+        }
+        replaced
+    }
+}
+
+/// Remove the placeholder at `span` of `text` with the label around it,
+/// for a practical outside units: "Unit {unit}: " and "(Unit {unit})" go
+/// as a whole, and of "{unit}.{n_in_unit}" only "{unit}." goes.
+fn remove_label(text: &mut String, span: Range<usize>) {
+    let rest = &text[span.end..];
+    let separator = rest
+        .find(|c: char| c.is_alphanumeric() || c == '{' || c == '\n')
+        .unwrap_or(rest.len());
+    let end = span.end + separator;
+    let joined = separator < rest.len()
+        && !rest[..separator].contains(char::is_whitespace)
+        && !rest[separator..].starts_with('\n');
+    if joined {
+        text.replace_range(span.start..end, "");
+        return;
+    }
+
+    // The word before the placeholder, with a bracket the separator closes
+    let mut start = span.start;
+    if let Some(before) = text[..start].strip_suffix(' ') {
+        let word = before.len()
+            - before
+                .chars()
+                .rev()
+                .take_while(|c| c.is_alphanumeric())
+                .map(char::len_utf8)
+                .sum::<usize>();
+        if word < before.len() {
+            start = word;
+        }
+    }
+    let closes = rest.starts_with([')', ']']);
+    if closes && text[..start].ends_with(['(', '[']) {
+        start -= 1;
+    }
+    // A label ending its line takes the separator before it along
+    if end == text.len() || text[end..].starts_with('\n') {
+        start = text[..start]
+            .trim_end_matches([' ', ',', ';', ':', '-', '–', '—', '|'])
+            .len();
+    }
+    text.replace_range(start..end, "");
+}
+
+impl SectionWithTitle {
+    pub fn to_docx(&self, replacer: &Entry) -> Vec<Block> {
+        let size = self.content.size.unwrap_or(default_size());
+        self.blocks(
+            replacer,
+            &ImageLayout::default(),
+            size,
+            TEXT_WIDTH,
+            &mut Vec::new(),
+        )
+    }
+
+    /// The section, with the images a program wrote laid out by `images`,
+    /// code `size` points large unless highlighted and `text_width` twips
+    /// between the margins. Screenshots that cannot be drawn are added to
+    /// `failed_screenshots` and replaced by the output text.
+    fn blocks(
+        &self,
+        replacer: &Entry,
+        images: &ImageLayout,
+        size: usize,
+        text_width: usize,
+        failed_screenshots: &mut Vec<String>,
+    ) -> Vec<Block> {
+        let mut blocks: Vec<Block> = as_blocks(self.title.to_docx(replacer)).collect();
+
+        if self.content.text.contains("{solution}") {
+            let mut files = replacer.source_files();
+            self.files.order.sort(&mut files);
+
+            for file in &files {
+                if files.len() > 1 {
+                    let caption = self
+                        .files
+                        .title
+                        .replace_text(replacer)
+                        .replace("{filename}", &file.filename)
+                        .replace("{language}", &file.language());
+                    blocks.extend(as_blocks(self.files.title.text_to_docx(&caption)));
+                }
+                let (lines, size) = self.file_to_docx(file, replacer, size);
+                blocks.extend(self.listing(
+                    lines,
+                    size,
+                    self.line_numbers.enabled,
+                    &file.language(),
+                    replacer,
+                    text_width,
+                ));
             }
         } else if self.content.text.contains("{output}") {
-            paragraphs.extend(self.parse_output_content(&replacer.output_rtf));
+            let runs = replacer.runs();
+            for (position, run) in runs.iter().enumerate() {
+                let stdin = run.stdin.as_deref().unwrap_or("");
+                let run_placeholders = |text: String| {
+                    text.replace("{run}", &(position + 1).to_string())
+                        .replace("{label}", &run.label)
+                        .replace("{stdin}", stdin)
+                };
+
+                if runs.len() > 1 {
+                    let title = run_placeholders(self.runs.title.replace_text(replacer));
+                    blocks.extend(as_blocks(self.runs.title.text_to_docx(&title)));
+                }
+                if run.stdin.is_some() && !self.runs.input.text.is_empty() {
+                    let input = run_placeholders(self.runs.input.replace_text(replacer));
+                    blocks.extend(as_blocks(self.runs.input.text_to_docx(&input)));
+                }
+                let title = run_placeholders(replace_number(
+                    &self.screenshot.title,
+                    "n",
+                    replacer.index,
+                    &replacer.numbering,
+                ));
+                let screenshot = self
+                    .screenshot_to_docx(run, &title, text_width)
+                    .unwrap_or_else(|error| {
+                        let label = if run.label.is_empty() {
+                            format!("run {}", position + 1)
+                        } else {
+                            run.label.clone()
+                        };
+                        failed_screenshots.push(format!(
+                            "practical {} ({}: {})",
+                            replacer.numbering.label(replacer.index),
+                            label,
+                            error
+                        ));
+                        None
+                    });
+                if let Some(picture) = screenshot {
+                    blocks.push(picture);
+                } else {
+                    let lines = if run.transcript.is_empty() {
+                        self.parse_output_content(&run.output)
+                    } else {
+                        self.parse_transcript(&run.transcript)
+                    };
+                    blocks.extend(self.listing(lines, 20, false, "", replacer, text_width));
+                }
+                blocks.extend(images.pictures(&run.images, replacer, text_width));
+                blocks.extend(as_blocks(self.stderr_to_docx(run, replacer)));
+                blocks.extend(as_blocks(self.status_to_docx(run, replacer)));
+                if let Some(outcome) = run.check(&self.check.normalization) {
+                    blocks.extend(as_blocks(self.check_to_docx(&outcome, replacer)));
+                }
+            }
         } else {
-            paragraphs.extend(self.content.to_docx(replacer));
+            blocks.extend(as_blocks(self.content.to_docx(replacer)));
         }
 
-        paragraphs
+        blocks
+    }
+
+    /// Lay out the lines of a code listing or program output, next to line
+    /// numbers and inside a container when those are enabled, in
+    /// `text_width` twips.
+    fn listing(
+        &self,
+        lines: Vec<docx_rs::Paragraph>,
+        size: usize,
+        numbered: bool,
+        language: &str,
+        replacer: &Entry,
+        text_width: usize,
+    ) -> Vec<Block> {
+        let container = self.container.enabled.then_some(&self.container);
+        if !numbered && container.is_none() {
+            let (lines, _) = self.lines.apply(lines, size, text_width);
+            return as_blocks(lines).collect();
+        }
+
+        let cell = |width: usize| {
+            let cell = TableCell::new().width(width, WidthType::Dxa);
+            match container {
+                Some(container) => cell.shading(
+                    Shading::new()
+                        .shd_type(ShdType::Clear)
+                        .fill(container.background.replace('#', "")),
+                ),
+                None => cell,
+            }
+        };
+        // Numbers too long for the page wrap in a gutter of half its width
+        let gutter = if numbered {
+            self.line_numbers
+                .gutter_width(lines.len(), size)
+                .min(text_width / 2)
+        } else {
+            0
+        };
+        let code_width = text_width - gutter;
+        let padding = container.map_or(0, |container| container.padding as u32);
+        // Word pads table cells by 108 twips on each side unless told otherwise
+        let cell_margins = container.map_or(2 * 108, |container| 2 * container.padding);
+        let (lines, size) = self
+            .lines
+            .apply(lines, size, code_width.saturating_sub(cell_margins));
+
+        let mut rows = Vec::new();
+        if let Some(container) = container {
+            let label = container
+                .label
+                .replace_text(replacer)
+                .replace("{language}", language);
+            if !label.trim().is_empty() {
+                let mut label_cell = cell(text_width).grid_span(if numbered { 2 } else { 1 });
+                for (position, paragraph) in
+                    container.label.text_to_docx(&label).into_iter().enumerate()
+                {
+                    let before = if position == 0 { padding } else { 0 };
+                    label_cell = label_cell
+                        .add_paragraph(paragraph.line_spacing(LineSpacing::new().before(before)));
+                }
+                rows.push(TableRow::new(vec![label_cell]));
+            }
+        }
+        let top = if rows.is_empty() { padding } else { 0 };
+
+        // Pad the first and last line rather than the cells, so that the
+        // rows of a numbered listing stay tight
+        let count = lines.len();
+        let pad = |position: usize, paragraph: docx_rs::Paragraph| {
+            let before = if position == 0 { top } else { 0 };
+            let after = if position + 1 == count { padding } else { 0 };
+            if before == 0 && after == 0 {
+                paragraph
+            } else {
+                paragraph.line_spacing(LineSpacing::new().before(before).after(after))
+            }
+        };
+
+        if numbered {
+            for (position, line) in lines.into_iter().enumerate() {
+                rows.push(TableRow::new(vec![
+                    cell(gutter)
+                        .add_paragraph(pad(position, self.line_numbers.gutter(position, size))),
+                    cell(code_width).add_paragraph(pad(position, line)),
+                ]));
+            }
+        } else {
+            let mut code = cell(text_width);
+            for (position, line) in lines.into_iter().enumerate() {
+                code = code.add_paragraph(pad(position, line));
+            }
+            rows.push(TableRow::new(vec![code]));
+        }
+
+        let grid = if numbered {
+            vec![gutter, code_width]
+        } else {
+            vec![text_width]
+        };
+        let mut table = docx_rs::Table::without_borders(rows)
+            .layout(TableLayoutType::Fixed)
+            .set_grid(grid)
+            .width(text_width, WidthType::Dxa);
+        if let Some(container) = container {
+            let color = container.border_color.replace('#', "");
+            for position in [
+                TableBorderPosition::Top,
+                TableBorderPosition::Left,
+                TableBorderPosition::Bottom,
+                TableBorderPosition::Right,
+            ] {
+                table = table.set_border(
+                    TableBorder::new(position)
+                        .color(&color)
+                        .size(container.border_size),
+                );
+            }
+            table = table.margins(TableCellMargins::new().margin(
+                0,
+                container.padding,
+                0,
+                container.padding,
+            ));
+        }
+        vec![table.into()]
+    }
+
+    /// The output of a run drawn as a terminal window, `None` when
+    /// screenshots are off, or why the picture could not be drawn.
+    fn screenshot_to_docx(
+        &self,
+        run: &ProgramRun,
+        title: &str,
+        text_width: usize,
+    ) -> Result<Option<Block>, String> {
+        if !self.screenshot.enabled {
+            return Ok(None);
+        }
+        let image = self.screenshot.render(run, title)?;
+        let (width, height) = image.display_size(text_width as u32 * EMU_PER_TWIP);
+        let paragraph = docx_rs::Paragraph::new().style(&self.content.style);
+        Ok(Some(picture(
+            &image.png,
+            (width, height),
+            run.plain_text(),
+            paragraph,
+        )))
+    }
+
+    /// The lines of a file and their font size in half-points, `size`
+    /// points unless highlighted.
+    fn file_to_docx(
+        &self,
+        file: &SourceFile,
+        replacer: &Entry,
+        size: usize,
+    ) -> (Vec<docx_rs::Paragraph>, usize) {
+        if let Some(Content {
+            content_type: ContentType::Rtf,
+            data: code_rtf,
+        }) = &file.highlighted
+        {
+            (self.parse_code_with_rtf(&file.content, code_rtf), 20)
+        } else {
+            let lines = self.content.text_to_docx(
+                &self
+                    .content
+                    .replace_text_with_solution(replacer, &file.content),
+            );
+            (lines, size * 2)
+        }
     }
 
     fn parse_code_with_rtf(&self, raw_code: &str, rtf_content: &str) -> Vec<docx_rs::Paragraph> {
         // Parse RTF using the proper parser
-        let rtf_doc = match RtfDocument::try_from(rtf_content) {
-            Ok(doc) => Some(doc),
-            Err(_) => None,
-        };
+        let rtf_doc = RtfDocument::try_from(rtf_content).ok();
 
         let mut paragraphs = Vec::new();
 
@@ -255,10 +1402,11 @@ impl SectionWithTitle {
                                     line_tokens.push(current_token.clone());
                                     current_token.clear();
                                 }
-                                // Preserve whitespace
-                                let mut whitespace = String::new();
-                                whitespace.push(ch);
-                                line_tokens.push(whitespace);
+                                // Preserve whitespace, one token per run of it
+                                match line_tokens.last_mut() {
+                                    Some(last) if last.trim().is_empty() => last.push(ch),
+                                    _ => line_tokens.push(ch.to_string()),
+                                }
                             }
                             '(' | ')' | '{' | '}' | '[' | ']' | ';' | ',' | '.' | '+' | '-'
                             | '*' | '/' | '=' | '<' | '>' | '!' | '&' | '|' => {
@@ -341,9 +1489,9 @@ impl SectionWithTitle {
         paragraphs
     }
 
-    fn parse_output_content(&self, output_content: &str) -> Vec<docx_rs::Paragraph> {
+    fn parse_output_content(&self, output: &Content) -> Vec<docx_rs::Paragraph> {
         let mut paragraphs = Vec::new();
-        let cleaned_text = self.remove_ansi_codes(output_content);
+        let cleaned_text = output.plain_text();
 
         for line in cleaned_text.lines() {
             if line.trim().is_empty() {
@@ -379,24 +1527,146 @@ impl SectionWithTitle {
         paragraphs
     }
 
-    fn remove_ansi_codes(&self, text: &str) -> String {
-        let mut result = String::new();
-        let mut chars = text.chars().peekable();
+    fn stderr_to_docx(&self, run: &ProgramRun, replacer: &Entry) -> Vec<docx_rs::Paragraph> {
+        let stderr = remove_ansi_codes(run.stderr.as_deref().unwrap_or(""));
+        if stderr.trim().is_empty() || !self.stderr.show.applies_to(run) {
+            return Vec::new();
+        }
 
-        while let Some(ch) = chars.next() {
-            if ch == '\u{001b}' && chars.peek() == Some(&'[') {
-                chars.next(); // consume '['
-                while let Some(next_ch) = chars.next() {
-                    if next_ch == 'm' {
-                        break;
-                    }
+        let mut paragraphs = Vec::new();
+        if !self.stderr.title.text.is_empty() {
+            paragraphs.extend(self.stderr.title.to_docx(replacer));
+        }
+        for line in stderr.lines() {
+            let run = Run::new()
+                .fonts(
+                    RunFonts::new()
+                        .ascii("CaskaydiaCove NF")
+                        .hi_ansi("CaskaydiaCove NF")
+                        .east_asia("CaskaydiaCove NF"),
+                )
+                .size(20);
+            let run = self.stderr.style.apply(run).add_text(line);
+            paragraphs.push(
+                docx_rs::Paragraph::new()
+                    .add_run(run)
+                    .style(&self.content.style),
+            );
+        }
+        paragraphs
+    }
+
+    fn status_to_docx(&self, run: &ProgramRun, replacer: &Entry) -> Vec<docx_rs::Paragraph> {
+        let status = run.status();
+        if (status.is_empty() && run.duration_ms.is_none()) || !self.status.show.applies_to(run) {
+            return Vec::new();
+        }
+
+        let text = self
+            .status
+            .paragraph
+            .replace_text(replacer)
+            .replace("{status}", &status)
+            .replace(
+                "{exit_code}",
+                &run.exit_status
+                    .map(|code| code.to_string())
+                    .unwrap_or_default(),
+            )
+            .replace("{signal}", &run.signal.map(signal_name).unwrap_or_default())
+            .replace("{duration}", &run.duration());
+        self.status.paragraph.text_to_docx(&text)
+    }
+
+    fn check_to_docx(&self, outcome: &CheckOutcome, replacer: &Entry) -> Vec<docx_rs::Paragraph> {
+        if outcome.passed {
+            return self.check.pass.to_docx(replacer);
+        }
+
+        let mut badge = self.check.fail.replace_text(replacer);
+        if let Some(error) = &outcome.error {
+            badge = format!("{}\n{}", badge, error);
+        }
+        let mut paragraphs = self.check.fail.text_to_docx(&badge);
+        if !self.check.diff {
+            return paragraphs;
+        }
+
+        for line in &outcome.diff {
+            let run = Run::new()
+                .fonts(
+                    RunFonts::new()
+                        .ascii("CaskaydiaCove NF")
+                        .hi_ansi("CaskaydiaCove NF")
+                        .east_asia("CaskaydiaCove NF"),
+                )
+                .size(20);
+            let run = match line {
+                DiffLine::Same(text) => run.add_text(format!("  {}", text)),
+                DiffLine::Expected(text) => self
+                    .check
+                    .diff_expected
+                    .apply(run)
+                    .add_text(format!("- {}", text)),
+                DiffLine::Actual(text) => self
+                    .check
+                    .diff_actual
+                    .apply(run)
+                    .add_text(format!("+ {}", text)),
+            };
+            paragraphs.push(
+                docx_rs::Paragraph::new()
+                    .add_run(run)
+                    .style(&self.content.style),
+            );
+        }
+        paragraphs
+    }
+
+    fn parse_transcript(&self, transcript: &[TranscriptChunk]) -> Vec<docx_rs::Paragraph> {
+        let mut paragraphs = Vec::new();
+        let mut current_paragraph = docx_rs::Paragraph::new();
+
+        for chunk in transcript {
+            let text = match chunk.stream {
+                Stream::Input => chunk.text.clone(),
+                Stream::Output => remove_ansi_codes(&chunk.text),
+            };
+
+            for (i, piece) in text.split('\n').enumerate() {
+                if i > 0 {
+                    paragraphs.push(current_paragraph.style(&self.content.style));
+                    current_paragraph = docx_rs::Paragraph::new();
                 }
-            } else {
-                result.push(ch);
+                if piece.is_empty() {
+                    continue;
+                }
+
+                let mut run = Run::new()
+                    .fonts(
+                        RunFonts::new()
+                            .ascii("CaskaydiaCove NF")
+                            .hi_ansi("CaskaydiaCove NF")
+                            .east_asia("CaskaydiaCove NF"),
+                    )
+                    .size(20);
+                if chunk.stream == Stream::Input {
+                    run = self.user_input.apply(run);
+                }
+                current_paragraph = current_paragraph.add_run(run.add_text(piece));
             }
         }
 
-        result
+        // A trailing newline leaves an empty paragraph behind, like `lines()`
+        if !current_paragraph.children.is_empty() || paragraphs.is_empty() {
+            paragraphs.push(
+                current_paragraph
+                    .add_run(Run::new().add_text(""))
+                    .style(&self.content.style),
+            );
+        }
+
+        paragraphs
     }
 
     fn find_best_format_match<'a>(
@@ -451,11 +1721,10 @@ impl SectionWithTitle {
                 if key.len() > 5
                     && ((key.len() <= token.len() && token.contains(key))
                         || (token.len() <= key.len() && key.contains(token)))
+                    && !used_tokens.contains(key)
                 {
-                    if !used_tokens.contains(key) {
-                        used_tokens.insert(key.clone());
-                        return Some(*painter);
-                    }
+                    used_tokens.insert(key.clone());
+                    return Some(*painter);
                 }
             }
         }
@@ -500,115 +1769,2240 @@ impl SectionWithTitle {
 }
 
 impl DocumentConfig {
-    pub fn create_document(&self, zig_output: Vec<ZigOutput>) -> docx_rs::Docx {
-        let mut doc = Docx::new();
+    /// Parse a config from the contents of a `format.toml`.
+    ///
+    /// ```
+    /// let config = helpers::DocumentConfig::from_toml(
+    ///     r#"
+    ///     [question]
+    ///     text = "Q) {question}"
+    ///
+    ///     [solution]
+    ///     text = "{solution}"
+    ///     title = { text = "Code:", bold = true }
+    ///
+    ///     [output]
+    ///     text = "{output}"
+    ///     title = { text = "Output:", bold = true }
+    ///     "#,
+    /// )
+    /// .unwrap();
+    /// assert_eq!(config.solution.title.text, "Code:");
+    /// ```
+    pub fn from_toml(toml: &str) -> Result<Self, Error> {
+        let config: Self = toml::from_str(toml)?;
+        config.validate()?;
+        Ok(config)
+    }
 
-        // Add common Microsoft Word paragraph styles
-        let heading1 = Style::new("Heading1", StyleType::Paragraph).name("Heading 1");
+    /// Width between the page margins in twips: that of the last page of
+    /// `reference_docx` when it sets a page size, or else of an A4 page
+    /// with the margins docx-rs gives it.
+    pub fn text_width(&self) -> usize {
+        self.reference_docx
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|reference| reference::text_width(&reference))
+            .unwrap_or(TEXT_WIDTH)
+    }
 
-        let heading2 = Style::new("Heading2", StyleType::Paragraph).name("Heading 2");
+    fn validate(&self) -> Result<(), Error> {
+        self.output
+            .screenshot
+            .validate()
+            .map_err(Error::Screenshot)?;
+        let text_width = self.text_width();
+        for (name, section) in [("solution", &self.solution), ("output", &self.output)] {
+            let container = &section.container;
+            if container.enabled && 2 * container.padding >= text_width {
+                return Err(Error::Container(format!(
+                    "[{}.container] padding of {} twips leaves no room for text {} twips wide",
+                    name, container.padding, text_width
+                )));
+            }
+        }
+        if self.headings.word_numbering {
+            let paragraph = self.header.as_ref().unwrap_or(&self.question);
+            if let Some((span, filters)) = find_placeholder(&paragraph.text, "n") {
+                if self.numbering.filtered(filters).is_none() {
+                    return Err(Error::Headings(format!(
+                        "word_numbering cannot number {}, which has a filter create-docx does not know",
+                        &paragraph.text[span]
+                    )));
+                }
+            }
+        }
+        for (id, style) in &self.styles {
+            let kind = |name: &str| match self.styles.get(name) {
+                Some(style) => Some(style.kind),
+                None if crate::schema::STYLES.contains(&name) => Some(StyleKind::Paragraph),
+                None => None,
+            };
+            for (field, name) in [("based_on", &style.based_on), ("next", &style.next)] {
+                let Some(name) = name else {
+                    continue;
+                };
+                match kind(name) {
+                    None => {
+                        return Err(Error::Style(format!(
+                            "{}.{} names the undefined style \"{}\"",
+                            id, field, name
+                        )))
+                    }
+                    Some(other) if other != style.kind => {
+                        return Err(Error::Style(format!(
+                            "{}.{} names \"{}\", a style of another kind",
+                            id, field, name
+                        )))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
 
-        let heading3 = Style::new("Heading3", StyleType::Paragraph).name("Heading 3");
+    /// Read and parse a `format.toml` file. Relative font paths in it are
+    /// relative to its folder.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path).map_err(|error| Error::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let mut config: Self = toml::from_str(&toml)?;
+        if let Some(dir) = path.parent() {
+            config.output.screenshot.resolve_fonts(dir);
+            if let Some(reference) = config
+                .reference_docx
+                .as_mut()
+                .filter(|path| path.is_relative())
+            {
+                *reference = dir.join(&*reference);
+            }
+        }
+        // Fail before the record is overwritten
+        if let Some(reference) = &config.reference_docx {
+            std::fs::metadata(reference).map_err(|error| Error::Read {
+                path: reference.clone(),
+                error,
+            })?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
 
-        let heading4 = Style::new("Heading4", StyleType::Paragraph).name("Heading 4");
+    pub fn create_document(&self, entries: &[Entry]) -> docx_rs::Docx {
+        let mut builder = DocumentBuilder::new(self);
+        for entry in entries {
+            builder.add_entry(entry);
+        }
+        builder.finish()
+    }
 
-        let heading5 = Style::new("Heading5", StyleType::Paragraph).name("Heading 5");
+    /// Render `entries` and write the `.docx` file to `writer`.
+    ///
+    /// ```
+    /// use helpers::{Content, DocumentConfig, Entry};
+    ///
+    /// let entry = Entry::new(0, "Print a greeting", ".py", "print('hello')")
+    ///     .with_output(Content::text("hello"));
+    ///
+    /// let mut docx = Vec::new();
+    /// DocumentConfig::default().write_docx(&[entry], &mut docx).unwrap();
+    /// assert!(docx.starts_with(b"PK"));
+    /// ```
+    pub fn write_docx<W: Write>(&self, entries: &[Entry], writer: W) -> Result<(), Error> {
+        let mut builder = DocumentBuilder::new(self);
+        for entry in entries {
+            builder.add_entry(entry);
+        }
+        builder.write_docx(writer)
+    }
 
-        let heading6 = Style::new("Heading6", StyleType::Paragraph).name("Heading 6");
+    /// The document before any practical, numbering practicals from the
+    /// one at index `first` when `headings.word_numbering` is set.
+    fn base_document(&self, first: usize) -> docx_rs::Docx {
+        let defaults = &self.defaults;
+        let font = &defaults.font;
+        let mut doc = Docx::new()
+            .default_fonts(RunFonts::new().east_asia(font).ascii(font).hi_ansi(font))
+            .default_size(defaults.size * 2);
+        if let Some(spacing) = spacing(
+            Some(defaults.line_spacing),
+            Some(defaults.margin_top),
+            Some(defaults.margin_bottom),
+        ) {
+            doc = doc.default_line_spacing(spacing);
+        }
 
-        let title = Style::new("Title", StyleType::Paragraph).name("Title");
+        // Word's common paragraph styles, which `style` may name without
+        // defining them
+        for id in crate::schema::STYLES {
+            if !self.styles.contains_key(id) {
+                let mut style = Style::new(id, StyleType::Paragraph).name(style_name(id));
+                if let Some(level) = heading_level(id) {
+                    style = style.outline_lvl(level - 1);
+                }
+                doc = doc.add_style(style);
+            }
+        }
+        if self.toc.show {
+            for level in 1..=9 {
+                let id = format!("TOC{}", level);
+                if !self.styles.contains_key(&id) {
+                    // Indented like Word's own
+                    let indent = (level - 1) * 220;
+                    let style = Style::new(&id, StyleType::Paragraph)
+                        .name(format!("toc {}", level))
+                        .next("Normal")
+                        .indent(Some(indent), None, None, None);
+                    doc = doc.add_style(style);
+                }
+            }
+        }
+        for (id, definition) in &self.styles {
+            doc = doc.add_style(definition.to_docx(id));
+        }
 
-        let subtitle = Style::new("Subtitle", StyleType::Paragraph).name("Subtitle");
+        if let Some((paragraph, prefix, numbering)) = self.numbered_heading() {
+            let text = format!("{}{}%1{}", prefix, numbering.prefix, numbering.suffix);
+            let mut level = Level::new(
+                0,
+                Start::new(numbering.number(first)),
+                NumberFormat::new(numbering.word_format()),
+                LevelText::new(text),
+                LevelJc::new("left"),
+            )
+            .suffix(LevelSuffixType::Nothing);
+            // The number is formatted like the text of the heading
+            level.run_property = paragraph.format_run(Run::new()).run_property;
+            doc = doc
+                .add_abstract_numbering(AbstractNumbering::new(HEADING_NUMBERING).add_level(level))
+                .add_numbering(Numbering::new(HEADING_NUMBERING, HEADING_NUMBERING));
+            // Cached practicals are added as placeholders, which docx-rs
+            // cannot see the numbering of
+            doc.document_rels.has_numberings = true;
+        }
 
-        let normal = Style::new("Normal", StyleType::Paragraph).name("Normal");
+        doc
+    }
 
-        let quote = Style::new("Quote", StyleType::Paragraph).name("Quote");
+    /// The paragraph Word's numbering numbers, the text before `{n}` on its
+    /// line and the format of its `{n}`, when `headings.word_numbering` is
+    /// set and it holds `{n}`. [`validate`](Self::validate) rejects filters
+    /// of that `{n}` it does not know.
+    fn numbered_heading(&self) -> Option<(&Paragraph, &str, NumberingFormat)> {
+        if !self.headings.word_numbering {
+            return None;
+        }
+        let paragraph = self.header.as_ref().unwrap_or(&self.question);
+        let (span, filters) = find_placeholder(&paragraph.text, "n")?;
+        let before = &paragraph.text[..span.start];
+        let prefix = before.rsplit('\n').next().unwrap_or(before);
+        Some((paragraph, prefix, self.numbering.filtered(filters)?))
+    }
 
-        let emphasis = Style::new("Emphasis", StyleType::Paragraph).name("Emphasis");
+    /// `paragraph` rendered for `parsed`, with its line holding `{n}`, or
+    /// else its first line, as a heading at `level`. The `main` heading of
+    /// the practical also gets the bookmark and Word's numbering.
+    fn heading(
+        &self,
+        paragraph: &Paragraph,
+        level: usize,
+        parsed: &Entry,
+        main: bool,
+    ) -> Vec<docx_rs::Paragraph> {
+        let numbered = main && self.numbered_heading().is_some();
+        let (text, line) = paragraph.heading_text(parsed, numbered);
+        let mut paragraphs = paragraph.text_to_docx(&text);
+
+        let Some(heading) = paragraphs.get_mut(line) else {
+            return paragraphs;
+        };
+        if self.headings.enabled && paragraph.style == default_style() {
+            *heading = std::mem::take(heading).style(&format!("Heading{}", level));
+        }
+        if numbered {
+            *heading = std::mem::take(heading)
+                .numbering(NumberingId::new(HEADING_NUMBERING), IndentLevel::new(0));
+        }
+        if let Some(name) = self.heading_bookmark(parsed.index).filter(|_| main) {
+            // Numbered like the practical, which keeps it apart from
+            // the other bookmarks
+            let id = parsed.index;
+            heading.children.insert(
+                0,
+                ParagraphChild::BookmarkStart(BookmarkStart::new(id, name)),
+            );
+            heading
+                .children
+                .push(ParagraphChild::BookmarkEnd(BookmarkEnd::new(id)));
+        }
+        paragraphs
+    }
 
-        let strong = Style::new("Strong", StyleType::Paragraph).name("Strong");
+    /// Name of the bookmark around the heading of practical `index`, if
+    /// headings are bookmarked.
+    fn heading_bookmark(&self, index: usize) -> Option<String> {
+        let bookmark = &self.headings.bookmark;
+        // Only the start applies, which keeps the name a valid one
+        let numbering = NumberingFormat {
+            start: self.numbering.start,
+            ..Default::default()
+        };
+        let name = replace_number(bookmark, "n", index, &numbering);
+        (!bookmark.is_empty()).then(|| name.replace(' ', "_"))
+    }
 
-        // Add all styles to the document
-        doc = doc
-            .add_style(heading1)
-            .add_style(heading2)
-            .add_style(heading3)
-            .add_style(heading4)
-            .add_style(heading5)
-            .add_style(heading6)
-            .add_style(title)
-            .add_style(subtitle)
-            .add_style(normal)
-            .add_style(quote)
-            .add_style(emphasis)
-            .add_style(strong);
+    /// Outline level of the paragraphs in style `id`, 1 for the top, if
+    /// Word lists them in its navigation pane.
+    fn outline_level(&self, id: &str) -> Option<usize> {
+        match self.styles.get(id) {
+            Some(style) => style.outline_level.or_else(|| heading_level(id)),
+            None => heading_level(id),
+        }
+    }
 
-        for (index, parsed) in zig_output.iter().enumerate() {
-            let mut paragraphs = Vec::new();
+    /// The headings of practical `parsed` with their levels: the unit
+    /// heading when the practical opens a unit, `[header]` and `[question]`.
+    fn headings_of(&self, parsed: &Entry) -> Vec<(&Paragraph, usize)> {
+        let mut headings = Vec::new();
+        if let Some(unit) = &parsed.unit {
+            if unit.position == Some(0) {
+                headings.push((&self.units.heading, 1));
+            }
+        }
+        let level = 1 + usize::from(parsed.unit.is_some());
+        if let Some(header) = &self.header {
+            headings.push((header, level));
+        }
+        headings.push((&self.question, level + 1));
+        headings
+    }
 
-            if let Some(header) = &self.header {
-                paragraphs.extend(header.to_docx(parsed));
-                paragraphs.push(docx_rs::Paragraph::new().add_run(Run::new()));
+    /// Outline level and text of the headings of practical `parsed` that
+    /// the table of contents lists.
+    fn contents_entries(&self, parsed: &Entry) -> Vec<(usize, String)> {
+        let mut entries = Vec::new();
+        for (paragraph, level) in self.headings_of(parsed) {
+            let style = if self.headings.enabled && paragraph.style == default_style() {
+                format!("Heading{}", level)
+            } else {
+                paragraph.style.clone()
+            };
+            let Some(level) = self.outline_level(&style) else {
+                continue;
+            };
+            if level > self.toc.depth {
+                continue;
             }
-            paragraphs.extend(self.question.to_docx(parsed));
-            paragraphs.push(docx_rs::Paragraph::new().add_run(Run::new()));
-            paragraphs.extend(self.solution.to_docx(parsed));
-            paragraphs.push(docx_rs::Paragraph::new().add_run(Run::new()));
-            paragraphs.extend(self.output.to_docx(parsed));
-            paragraphs.push(docx_rs::Paragraph::new().add_run(Run::new()));
+            let (text, line) = paragraph.heading_text(parsed, false);
+            let text = text.split('\n').nth(line).unwrap_or_default();
+            entries.push((level, text.to_string()));
+        }
+        entries
+    }
 
-            if let Some(footer) = &self.footer {
-                paragraphs.push(docx_rs::Paragraph::new().add_run(Run::new()));
-                paragraphs.extend(footer.to_docx(parsed));
+    /// The table of contents over `entries`, the index of every practical
+    /// with its [`contents_entries`](Self::contents_entries).
+    fn contents(&self, entries: &[(usize, Vec<(usize, String)>)]) -> Vec<docx_rs::Paragraph> {
+        let mut paragraphs = self.toc.title.text_to_docx(&self.toc.title.text);
+        let depth = self.toc.depth.clamp(1, 9);
+        let field = Run::new()
+            .add_field_char(FieldCharType::Begin, true)
+            .add_instr_text(InstrText::TOC(
+                InstrToC::new()
+                    .heading_styles_range(1, depth)
+                    .hyperlink()
+                    .hide_tab_and_page_numbers_in_webview()
+                    .use_applied_paragraph_line_level(),
+            ))
+            .add_field_char(FieldCharType::Separate, false);
+        let mut field = Some(field);
+
+        // The headings as listed until Word adds the page numbers
+        for (index, headings) in entries {
+            for (level, text) in headings {
+                let mut paragraph = docx_rs::Paragraph::new().style(&format!("TOC{}", level));
+                if let Some(field) = field.take() {
+                    paragraph = paragraph.add_run(field);
+                }
+                let run = Run::new().add_text(text);
+                paragraph = match self.heading_bookmark(*index) {
+                    Some(name) => paragraph
+                        .add_hyperlink(Hyperlink::new(name, HyperlinkType::Anchor).add_run(run)),
+                    None => paragraph.add_run(run),
+                };
+                paragraphs.push(paragraph);
             }
-            if index != zig_output.len() - 1 {
-                paragraphs
-                    .push(docx_rs::Paragraph::new().add_run(Run::new().add_break(BreakType::Page)));
+        }
+
+        let end = Run::new().add_field_char(FieldCharType::End, false);
+        paragraphs.push(match field {
+            Some(field) => docx_rs::Paragraph::new().add_run(field).add_run(end),
+            None => docx_rs::Paragraph::new().style("TOC1").add_run(end),
+        });
+        paragraphs
+    }
+
+    /// The font size `paragraph` ends up with in points, from the paragraph,
+    /// its style or the defaults.
+    fn text_size(&self, paragraph: &Paragraph) -> usize {
+        let mut style = Some(paragraph.style.as_str());
+        // Guard against styles based on each other
+        for _ in 0..16 {
+            let Some(definition) = style.and_then(|id| self.styles.get(id)) else {
+                break;
+            };
+            if let Some(size) = definition.size {
+                return paragraph.size.unwrap_or(size);
             }
+            style = definition.based_on.as_deref();
+        }
+        paragraph.size.unwrap_or(self.defaults.size)
+    }
 
-            for p in paragraphs {
-                doc = doc.add_paragraph(p);
+    /// The blocks of one practical, laid out in `text_width` twips.
+    /// Screenshots that cannot be drawn are added to `failed_screenshots`.
+    fn entry_blocks(
+        &self,
+        parsed: &Entry,
+        text_width: usize,
+        failed_screenshots: &mut Vec<String>,
+    ) -> Vec<Block> {
+        let mut blocks = Vec::new();
+
+        let level = match &parsed.unit {
+            Some(unit) => {
+                if unit.position == Some(0) {
+                    blocks.extend(as_blocks(self.heading(
+                        &self.units.heading,
+                        1,
+                        parsed,
+                        false,
+                    )));
+                    let spacing = if self.units.page_break {
+                        Run::new().add_break(BreakType::Page)
+                    } else {
+                        Run::new()
+                    };
+                    blocks.push(docx_rs::Paragraph::new().add_run(spacing).into());
+                }
+                2
             }
+            None => 1,
+        };
+        if let Some(header) = &self.header {
+            blocks.extend(as_blocks(self.heading(header, level, parsed, true)));
+            blocks.push(docx_rs::Paragraph::new().add_run(Run::new()).into());
+        }
+        let question = self.heading(&self.question, level + 1, parsed, self.header.is_none());
+        blocks.extend(as_blocks(question));
+        blocks.extend(
+            self.images
+                .pictures(&parsed.question_images, parsed, text_width),
+        );
+        blocks.push(docx_rs::Paragraph::new().add_run(Run::new()).into());
+        blocks.extend(self.solution.blocks(
+            parsed,
+            &self.images,
+            self.text_size(&self.solution.content),
+            text_width,
+            failed_screenshots,
+        ));
+        blocks.push(docx_rs::Paragraph::new().add_run(Run::new()).into());
+        blocks.extend(self.output.blocks(
+            parsed,
+            &self.images,
+            self.text_size(&self.output.content),
+            text_width,
+            failed_screenshots,
+        ));
+        blocks.push(docx_rs::Paragraph::new().add_run(Run::new()).into());
+
+        if let Some(footer) = &self.footer {
+            blocks.push(docx_rs::Paragraph::new().add_run(Run::new()).into());
+            blocks.extend(as_blocks(footer.to_docx(parsed)));
         }
 
-        doc
+        blocks
     }
 }
 
-impl Default for Paragraph {
-    fn default() -> Self {
-        Paragraph {
-            size: default_size(),
-            text: String::new(),
-            align: default_align(),
-            bold: default_false(),
-            italic: default_false(),
-            underline: default_false(),
-            font: default_font(),
-            color: default_color(),
-            line_spacing: default_line_spacing(),
-            margin_top: default_zero(),
-            margin_bottom: default_zero(),
-            indent: default_zero(),
-            style: default_style(),
-        }
+/// Line spacing with a multiplier and space above and below in points, or
+/// `None` when none of them is set.
+fn spacing(line: Option<f32>, above: Option<u32>, below: Option<u32>) -> Option<LineSpacing> {
+    if line.is_none() && above.is_none() && below.is_none() {
+        return None;
+    }
+    let mut spacing = LineSpacing::new();
+    if let Some(line) = line {
+        spacing = spacing
+            .line_rule(LineSpacingType::Auto)
+            .line((line * 240.0).round() as i32);
     }
+    if let Some(above) = above {
+        spacing = spacing.before(above * 20);
+    }
+    if let Some(below) = below {
+        spacing = spacing.after(below * 20);
+    }
+    Some(spacing)
 }
 
-impl Default for SectionWithTitle {
-    fn default() -> Self {
-        SectionWithTitle {
-            content: Paragraph::default(),
-            title: Paragraph::default(),
-        }
+/// Level of the built-in heading style `id`, such as 1 for `Heading1`.
+fn heading_level(id: &str) -> Option<usize> {
+    id.strip_prefix("Heading")?.parse().ok()
+}
+
+/// Display name of a built-in style, such as "Heading 1" for `Heading1`.
+fn style_name(id: &str) -> String {
+    match id.strip_prefix("Heading") {
+        Some(level) => format!("Heading {}", level),
+        None => id.to_string(),
     }
 }
 
-pub fn create_document_from_config(
-    config: &DocumentConfig,
-    zig_output: Vec<ZigOutput>,
-) -> Result<XMLDocx, Box<dyn std::error::Error>> {
-    let doc = config.create_document(zig_output);
-    let xml_docx = doc.build();
-    Ok(xml_docx)
+impl StyleDefinition {
+    /// The style with id `id`, as written to `styles.xml`.
+    fn to_docx(&self, id: &str) -> Style {
+        let kind = match self.kind {
+            StyleKind::Paragraph => StyleType::Paragraph,
+            StyleKind::Character => StyleType::Character,
+        };
+        let name = self.name.clone().unwrap_or_else(|| style_name(id));
+        let mut style = Style::new(id, kind).name(name);
+        if let Some(based_on) = &self.based_on {
+            style = style.based_on(based_on);
+        }
+        if let Some(next) = &self.next {
+            style = style.next(next);
+        }
+
+        if let Some(size) = self.size {
+            style = style.size(size * 2);
+        }
+        if let Some(font) = &self.font {
+            style = style.fonts(RunFonts::new().east_asia(font).ascii(font).hi_ansi(font));
+        }
+        if let Some(color) = &self.color {
+            style = style.color(color.replace('#', ""));
+        }
+        let run = &mut style.run_property;
+        match self.bold {
+            Some(true) => *run = std::mem::take(run).bold(),
+            Some(false) => *run = std::mem::take(run).disable_bold(),
+            None => {}
+        }
+        match self.italic {
+            Some(true) => *run = std::mem::take(run).italic(),
+            Some(false) => *run = std::mem::take(run).disable_italic(),
+            None => {}
+        }
+        match self.underline {
+            Some(true) => style = style.underline("single"),
+            Some(false) => style = style.underline("none"),
+            None => {}
+        }
+        if kind == StyleType::Character {
+            return style;
+        }
+
+        if let Some(level) = self.outline_level.or_else(|| heading_level(id)) {
+            style = style.outline_lvl(level.saturating_sub(1));
+        }
+        if let Some(align) = &self.align {
+            style = style.align(alignment(align));
+        }
+        if let Some(indent) = self.indent {
+            style = style.indent(Some(indent as i32 * 20), None, None, None);
+        }
+        let paragraph = &mut style.paragraph_property;
+        if let Some(spacing) = spacing(self.line_spacing, self.margin_top, self.margin_bottom) {
+            *paragraph = std::mem::take(paragraph).line_spacing(spacing);
+        }
+        if let Some(border) = &self.border {
+            let mut borders = ParagraphBorders::new();
+            for position in [
+                ParagraphBorderPosition::Top,
+                ParagraphBorderPosition::Left,
+                ParagraphBorderPosition::Bottom,
+                ParagraphBorderPosition::Right,
+            ] {
+                borders = borders.set(
+                    ParagraphBorder::new(position)
+                        .color(border.color.replace('#', ""))
+                        .size(border.size)
+                        .space(border.space),
+                );
+            }
+            *paragraph = std::mem::take(paragraph).set_borders(borders);
+        }
+        style
+    }
+}
+
+fn alignment(align: &str) -> AlignmentType {
+    match align.to_lowercase().as_str() {
+        "center" => AlignmentType::Center,
+        "right" => AlignmentType::Right,
+        "justify" => AlignmentType::Justified,
+        _ => AlignmentType::Left,
+    }
+}
+
+/// A piece of the document body: a paragraph, or a table such as a listing
+/// with line numbers.
+#[derive(Debug, Clone)]
+pub enum Block {
+    Paragraph(Box<docx_rs::Paragraph>),
+    Table(Box<docx_rs::Table>),
+    /// A paragraph holding a picture, described to screen readers by
+    /// `description`.
+    Picture {
+        paragraph: Box<docx_rs::Paragraph>,
+        description: String,
+    },
+}
+
+impl From<docx_rs::Paragraph> for Block {
+    fn from(paragraph: docx_rs::Paragraph) -> Self {
+        Block::Paragraph(Box::new(paragraph))
+    }
+}
+
+impl From<docx_rs::Table> for Block {
+    fn from(table: docx_rs::Table) -> Self {
+        Block::Table(Box::new(table))
+    }
+}
+
+impl Block {
+    /// Append the block to the body of `doc`.
+    pub fn add_to(self, doc: docx_rs::Docx) -> docx_rs::Docx {
+        match self {
+            Block::Paragraph(paragraph) => doc.add_paragraph(*paragraph),
+            Block::Table(table) => doc.add_table(*table),
+            Block::Picture { paragraph, .. } => doc.add_paragraph(*paragraph),
+        }
+    }
+}
+
+/// A paragraph holding the picture in `bytes`, printed `size` EMU large.
+fn picture(
+    bytes: &[u8],
+    size: (u32, u32),
+    description: String,
+    paragraph: docx_rs::Paragraph,
+) -> Block {
+    let pic = Pic::new(bytes).size(size.0, size.1);
+    Block::Picture {
+        description,
+        paragraph: Box::new(paragraph.add_run(Run::new().add_image(pic))),
+    }
+}
+
+impl ImageLayout {
+    pub fn to_docx(&self, images: &[ImageFile], replacer: &Entry) -> Vec<Block> {
+        self.pictures(images, replacer, TEXT_WIDTH)
+    }
+
+    /// The images, scaled to fit `text_width` twips.
+    fn pictures(&self, images: &[ImageFile], replacer: &Entry, text_width: usize) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        for (position, file) in images.iter().enumerate() {
+            let filename = file.filename();
+            let bytes = match read_image(&file.path) {
+                Ok(bytes) => bytes,
+                Err(error) => {
+                    let text = self
+                        .missing
+                        .replace_text(replacer)
+                        .replace("{filename}", &filename)
+                        .replace("{error}", &error);
+                    blocks.extend(as_blocks(self.missing.text_to_docx(&text)));
+                    continue;
+                }
+            };
+
+            let caption = file.caption.as_deref().unwrap_or(&filename);
+            let description = file.alt.clone().unwrap_or_else(|| {
+                self.alt
+                    .replace("{caption}", caption)
+                    .replace("{filename}", &filename)
+            });
+            let paragraph = docx_rs::Paragraph::new().align(alignment(&self.align));
+            blocks.push(picture(
+                &bytes.data,
+                self.fit(bytes.width, bytes.height, text_width),
+                description,
+                paragraph,
+            ));
+
+            if let Some(caption) = &file.caption {
+                let text = replace_number(
+                    &self.caption.replace_text(replacer),
+                    "image",
+                    position,
+                    &NumberingFormat::default(),
+                )
+                .replace("{caption}", caption)
+                .replace("{filename}", &filename);
+                blocks.extend(as_blocks(self.caption.text_to_docx(&text)));
+            }
+        }
+        blocks
+    }
+
+    /// Printed size in EMU of an image `width` × `height` pixels large,
+    /// with `text_width` twips between the margins.
+    fn fit(&self, width: u32, height: u32, text_width: usize) -> (u32, u32) {
+        let max_width = (text_width * self.max_width / 100) as f64 * EMU_PER_TWIP as f64;
+        let width = width as f64 * EMU_PER_PIXEL as f64;
+        let height = height as f64 * EMU_PER_PIXEL as f64;
+        let shrink = (max_width / width).min(1.0);
+        ((width * shrink) as u32, (height * shrink) as u32)
+    }
+}
+
+/// The contents of an image file, checked to decode.
+struct ImageBytes {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+fn read_image(path: &Path) -> Result<ImageBytes, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    // docx-rs panics on images it cannot decode, so decode them first
+    let decoded = image::load_from_memory(&data).map_err(|e| e.to_string())?;
+    Ok(ImageBytes {
+        width: decoded.width(),
+        height: decoded.height(),
+        data,
+    })
+}
+
+fn as_blocks(paragraphs: Vec<docx_rs::Paragraph>) -> impl Iterator<Item = Block> {
+    paragraphs.into_iter().map(Block::from)
+}
+
+/// Renders entries as they arrive, grouped into units in `index` order.
+/// An entry that arrives out of order renders again the practicals after
+/// it whose unit it changes.
+pub struct DocumentBuilder<'a> {
+    config: &'a DocumentConfig,
+    /// Every practical added, as it was added, in `index` order.
+    entries: Vec<Entry>,
+    /// Every practical in `index` order, with its unit and numbering
+    /// resolved, its cache key and its rendering.
+    practicals: Vec<(Entry, String, Practical)>,
+    failures: Vec<String>,
+    checks: Vec<CheckedPractical>,
+    unreadable_images: Vec<String>,
+    failed_screenshots: Vec<String>,
+    cache: Option<Cache>,
+    /// Width between the page margins, in twips.
+    text_width: usize,
+}
+
+/// Blocks of one practical, or the practical as an earlier build rendered
+/// it.
+enum Practical {
+    /// The blocks, with the screenshots that could not be drawn.
+    Rendered(Vec<Block>, Vec<String>),
+    Cached(CachedPractical),
+}
+
+/// Expected output checks of one practical, kept for the summary.
+struct CheckedPractical {
+    index: usize,
+    passed: usize,
+    total: usize,
+    failed_runs: Vec<String>,
+}
+
+impl<'a> DocumentBuilder<'a> {
+    pub fn new(config: &'a DocumentConfig) -> Self {
+        DocumentBuilder {
+            config,
+            entries: Vec::new(),
+            practicals: Vec::new(),
+            failures: Vec::new(),
+            checks: Vec::new(),
+            unreadable_images: Vec::new(),
+            failed_screenshots: Vec::new(),
+            cache: None,
+            text_width: config.text_width(),
+        }
+    }
+
+    /// Reuse the practicals rendered by an earlier build from `cache`, and
+    /// store the ones rendered now. Set it before adding entries. Only
+    /// [`write_docx`](Self::write_docx) reuses them; [`finish`]
+    /// (Self::finish) renders every practical.
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// Add and render the next practical. Practicals may arrive in any
+    /// order: they are grouped into units in `index` order.
+    pub fn add_entry(&mut self, entry: &Entry) {
+        let mut checked = CheckedPractical {
+            index: entry.index,
+            passed: 0,
+            total: 0,
+            failed_runs: Vec::new(),
+        };
+        let runs = entry.runs();
+        let images = entry
+            .question_images
+            .iter()
+            .chain(runs.iter().flat_map(|run| &run.images));
+        for image in images {
+            if let Err(error) = ::image::image_dimensions(&image.path) {
+                self.unreadable_images.push(format!(
+                    "practical {} ({}: {})",
+                    self.config.numbering.label(entry.index),
+                    image.filename(),
+                    error
+                ));
+            }
+        }
+
+        for (position, run) in runs.iter().enumerate() {
+            let label = if run.label.is_empty() {
+                format!("run {}", position + 1)
+            } else {
+                run.label.clone()
+            };
+            if run.failed() {
+                self.failures.push(format!(
+                    "practical {} ({}) {}",
+                    self.config.numbering.label(entry.index),
+                    label,
+                    run.status()
+                ));
+            }
+            if let Some(outcome) = run.check(&self.config.output.check.normalization) {
+                checked.total += 1;
+                if outcome.passed {
+                    checked.passed += 1;
+                } else {
+                    checked.failed_runs.push(label);
+                }
+            }
+        }
+        if checked.total > 0 {
+            self.checks.push(checked);
+        }
+        let position = self
+            .entries
+            .partition_point(|added| added.index <= entry.index);
+        self.entries.insert(position, entry.clone());
+        self.render();
+    }
+
+    /// Group the practicals added since the last call into units in
+    /// `index` order, number them and render each one, or take it from
+    /// the cache. [`add_entry`](Self::add_entry) calls it.
+    pub fn render(&mut self) {
+        // A unit follows from the practicals before it, so those before the
+        // first one added out of order keep theirs
+        let kept = self
+            .practicals
+            .iter()
+            .zip(&self.entries)
+            .take_while(|((practical, ..), entry)| practical.index == entry.index)
+            .count();
+        if kept == self.entries.len() {
+            return;
+        }
+        // The units after it are resolved again, and only the practicals
+        // whose key changed are rendered again
+        let mut rendered: HashMap<String, Practical> = self
+            .practicals
+            .drain(kept..)
+            .map(|(_, key, practical)| (key, practical))
+            .collect();
+        let mut unit = self
+            .practicals
+            .last()
+            .and_then(|(entry, ..)| entry.unit.clone());
+        for mut entry in self.entries[kept..].iter().cloned() {
+            entry.numbering = self.config.numbering.clone();
+            entry.unit = Unit::follow(entry.unit, unit.take());
+            unit = entry.unit.clone();
+
+            let key = cache::key(self.config, &entry, self.text_width);
+            let practical = match (rendered.remove(&key), &mut self.cache) {
+                (Some(practical), _) => practical,
+                (None, Some(cache)) => match cache.get(&key, entry.index) {
+                    Some(cached) => Practical::Cached(cached),
+                    None => {
+                        let mut failed = Vec::new();
+                        let blocks = self
+                            .config
+                            .entry_blocks(&entry, self.text_width, &mut failed);
+                        // Draw a missing screenshot again next time
+                        if failed.is_empty() {
+                            cache.put(&key, entry.index, &blocks);
+                        }
+                        Practical::Rendered(blocks, failed)
+                    }
+                },
+                (None, None) => {
+                    let mut failed = Vec::new();
+                    let blocks = self
+                        .config
+                        .entry_blocks(&entry, self.text_width, &mut failed);
+                    Practical::Rendered(blocks, failed)
+                }
+            };
+            self.practicals.push((entry, key, practical));
+        }
+        if let Some(cache) = &mut self.cache {
+            for key in rendered.keys() {
+                cache.forget(key);
+            }
+        }
+        self.failed_screenshots = self
+            .practicals
+            .iter()
+            .flat_map(|(_, _, practical)| match practical {
+                Practical::Rendered(_, failed) => failed.as_slice(),
+                Practical::Cached(_) => &[],
+            })
+            .cloned()
+            .collect();
+    }
+
+    /// Cache key of every rendered practical, by index.
+    pub fn keys(&self) -> BTreeMap<usize, String> {
+        self.practicals
+            .iter()
+            .map(|(entry, key, _)| (entry.index, key.clone()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Runs that exited with a non-zero status or were killed.
+    pub fn failures(&self) -> &[String] {
+        &self.failures
+    }
+
+    /// Runs whose output differs from the expected output.
+    pub fn failed_checks(&self) -> Vec<String> {
+        self.checks
+            .iter()
+            .flat_map(|checked| {
+                checked.failed_runs.iter().map(move |label| {
+                    format!(
+                        "practical {} ({})",
+                        self.config.numbering.label(checked.index),
+                        label
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Images that are missing or in a format that cannot be read.
+    pub fn unreadable_images(&self) -> &[String] {
+        &self.unreadable_images
+    }
+
+    /// Runs whose output screenshot could not be drawn, found while
+    /// rendering. Their output is printed as text instead.
+    pub fn failed_screenshots(&self) -> &[String] {
+        &self.failed_screenshots
+    }
+
+    /// Fail when `fail_on_error` is set and any program failed.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.config.fail_on_error && !self.failures.is_empty() {
+            return Err(Error::ProgramsFailed(self.failures.clone()));
+        }
+        Ok(())
+    }
+
+    /// Assemble the document and write the `.docx` file to `writer`.
+    pub fn write_docx<W: Write>(mut self, writer: W) -> Result<(), Error> {
+        self.check()?;
+        self.render();
+        let config = self.config;
+        let (doc, descriptions, cached) = self.assemble(true);
+        let mut xml = doc.build();
+        xml.document = cache::splice(&xml.document, &cached);
+        xml.document = describe_pictures(&xml.document, &descriptions);
+        xml.document = number_paragraphs(&xml.document);
+        xml.styles = drop_shadowed_styles(&xml.styles);
+        xml.numberings = order_level_suffixes(&xml.numberings);
+        if config.toc.show {
+            let settings = String::from_utf8_lossy(&xml.settings);
+            xml.settings = package::update_fields(&settings).into_bytes();
+        }
+        match &config.reference_docx {
+            Some(path) => {
+                let mut docx = Vec::new();
+                pack(xml, &mut docx)?;
+                let reference = std::fs::read(path).map_err(|error| Error::Read {
+                    path: path.clone(),
+                    error,
+                })?;
+                let docx =
+                    reference::apply(&docx, &reference).map_err(|message| Error::Reference {
+                        path: path.clone(),
+                        message,
+                    })?;
+                let mut writer = writer;
+                writer.write_all(&docx)?;
+                Ok(())
+            }
+            None => pack(xml, writer),
+        }
+    }
+
+    pub fn finish(mut self) -> docx_rs::Docx {
+        self.render();
+        self.assemble(false).0
+    }
+
+    /// The document, the descriptions of its pictures in order and, when
+    /// `placeholders` is set, the cached practicals whose placeholders
+    /// stand in for them, in order. Otherwise cached practicals are
+    /// rendered again.
+    fn assemble(
+        mut self,
+        placeholders: bool,
+    ) -> (docx_rs::Docx, Vec<String>, Vec<CachedPractical>) {
+        let first = self.practicals.first().map_or(0, |(entry, ..)| entry.index);
+        let mut doc = self.config.base_document(first);
+        let mut descriptions = Vec::new();
+        let mut cached = Vec::new();
+
+        // Every practical is marked with a bookmark, which `--update` uses to
+        // find it again. The bookmarks around headings take the ids up to
+        // the last practical's index.
+        let count = self.practicals.len();
+        let ids = self
+            .practicals
+            .last()
+            .map_or(0, |(entry, ..)| entry.index + 1);
+
+        if self.config.toc.show {
+            let contents: Vec<_> = self
+                .practicals
+                .iter()
+                .map(|(entry, ..)| (entry.index, self.config.contents_entries(entry)))
+                .collect();
+            doc = doc.add_bookmark_start(ids + count + 1, update::CONTENTS_BOOKMARK);
+            for p in self.config.contents(&contents) {
+                doc = doc.add_paragraph(p);
+            }
+            doc = doc.add_bookmark_end(ids + count + 1);
+            doc = doc.add_paragraph(
+                docx_rs::Paragraph::new().add_run(Run::new().add_break(BreakType::Page)),
+            );
+        }
+        // The summary shows each checked practical's resolved entry
+        let summary = &self.config.check_summary;
+        self.checks.sort_by_key(|checked| checked.index);
+        let checked_entries: Vec<Entry> = self
+            .checks
+            .iter()
+            .filter_map(|checked| {
+                self.practicals
+                    .iter()
+                    .find(|(entry, ..)| entry.index == checked.index)
+                    .map(|(entry, ..)| entry.clone())
+            })
+            .collect();
+
+        for (position, (entry, key, practical)) in self.practicals.into_iter().enumerate() {
+            let id = ids + position;
+            doc = doc.add_bookmark_start(id, update::practical_bookmark(entry.index, &key));
+            let blocks = match practical {
+                Practical::Cached(practical) if placeholders => {
+                    doc = doc.add_paragraph(practical.placeholder(cached.len()));
+                    descriptions.extend(practical.descriptions.iter().cloned());
+                    cached.push(practical);
+                    Vec::new()
+                }
+                Practical::Cached(_) => {
+                    self.config
+                        .entry_blocks(&entry, self.text_width, &mut self.failed_screenshots)
+                }
+                Practical::Rendered(blocks, _) => blocks,
+            };
+            for block in blocks {
+                if let Block::Picture { description, .. } = &block {
+                    descriptions.push(description.clone());
+                }
+                doc = block.add_to(doc);
+            }
+            doc = doc.add_bookmark_end(id);
+            if position != count - 1 {
+                doc = doc.add_paragraph(
+                    docx_rs::Paragraph::new().add_run(Run::new().add_break(BreakType::Page)),
+                );
+            }
+        }
+
+        if summary.show && !self.checks.is_empty() {
+            doc = doc.add_paragraph(
+                docx_rs::Paragraph::new().add_run(Run::new().add_break(BreakType::Page)),
+            );
+            doc = doc.add_bookmark_start(ids + count, update::SUMMARY_BOOKMARK);
+            for p in summary.title.to_docx(&checked_entries[0]) {
+                doc = doc.add_paragraph(p);
+            }
+            doc = doc.add_paragraph(docx_rs::Paragraph::new().add_run(Run::new()));
+
+            for (checked, entry) in self.checks.iter().zip(&checked_entries) {
+                let result = if checked.failed_runs.is_empty() {
+                    &summary.passed
+                } else {
+                    &summary.failed
+                };
+                let line = summary
+                    .line
+                    .replace_text(entry)
+                    .replace("{result}", result)
+                    .replace("{passed}", &checked.passed.to_string())
+                    .replace("{total}", &checked.total.to_string());
+                for p in summary.line.text_to_docx(&line) {
+                    doc = doc.add_paragraph(p);
+                }
+            }
+
+            let passed = self
+                .checks
+                .iter()
+                .filter(|checked| checked.failed_runs.is_empty())
+                .count();
+            let total = summary
+                .total
+                .text
+                .replace("{passed}", &passed.to_string())
+                .replace("{total}", &self.checks.len().to_string());
+            doc = doc.add_paragraph(docx_rs::Paragraph::new().add_run(Run::new()));
+            for p in summary.total.text_to_docx(&total) {
+                doc = doc.add_paragraph(p);
+            }
+            doc = doc.add_bookmark_end(ids + count);
+        }
+
+        (doc, descriptions, cached)
+    }
+}
+
+impl Default for FileListing {
+    fn default() -> Self {
+        FileListing {
+            title: default_file_title(),
+            order: FileOrder::default(),
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn apply(&self, mut run: Run) -> Run {
+        if self.bold {
+            run = run.bold();
+        }
+        if self.italic {
+            run = run.italic();
+        }
+        if self.underline {
+            run = run.underline("single");
+        }
+        if let Some(color) = &self.color {
+            run = run.color(color.replace('#', ""));
+        }
+        if let Some(highlight) = &self.highlight {
+            run = run.highlight(highlight);
+        }
+        run
+    }
+}
+
+impl Show {
+    pub fn applies_to(&self, run: &ProgramRun) -> bool {
+        match self {
+            Show::Always => true,
+            Show::OnFailure => run.failed(),
+            Show::Never => false,
+        }
+    }
+}
+
+impl Default for StatusLine {
+    fn default() -> Self {
+        StatusLine {
+            show: Show::default(),
+            paragraph: Paragraph {
+                text: "Program {status}".to_string(),
+                color: Some(default_error_color()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Default for StderrBlock {
+    fn default() -> Self {
+        StderrBlock {
+            show: default_show_always(),
+            title: default_stderr_title(),
+            style: default_stderr_style(),
+        }
+    }
+}
+
+impl Default for OutputCheck {
+    fn default() -> Self {
+        OutputCheck {
+            normalization: Normalization::default(),
+            pass: default_check_pass(),
+            fail: default_check_fail(),
+            diff: default_true(),
+            diff_expected: default_diff_expected(),
+            diff_actual: default_diff_actual(),
+        }
+    }
+}
+
+impl Default for Contents {
+    fn default() -> Self {
+        Contents {
+            show: default_false(),
+            title: default_contents_title(),
+            depth: default_contents_depth(),
+        }
+    }
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Units {
+            heading: default_unit_heading(),
+            page_break: default_false(),
+        }
+    }
+}
+
+impl Default for CheckSummary {
+    fn default() -> Self {
+        CheckSummary {
+            show: default_true(),
+            title: default_summary_title(),
+            line: default_summary_line(),
+            passed: default_summary_passed(),
+            failed: default_summary_failed(),
+            total: default_summary_total(),
+        }
+    }
+}
+
+impl Default for LineNumbers {
+    fn default() -> Self {
+        LineNumbers {
+            enabled: default_false(),
+            start: default_line_start(),
+            step: default_line_step(),
+            separator: default_line_separator(),
+            color: default_line_number_color(),
+        }
+    }
+}
+
+impl LineNumbers {
+    /// Width of the number column for a listing of `lines` lines, in twips.
+    fn gutter_width(&self, lines: usize, size: usize) -> usize {
+        let last = self.start + lines.saturating_sub(1);
+        let columns = last.to_string().len() + self.separator.chars().count() + 1;
+        columns * layout::char_width(size)
+    }
+
+    /// The number printed next to the line at `position`.
+    fn gutter(&self, position: usize, size: usize) -> docx_rs::Paragraph {
+        let number = self.start + position;
+        let mut text = String::new();
+        if self.step <= 1 || number.is_multiple_of(self.step) {
+            text.push_str(&number.to_string());
+        }
+        text.push_str(&self.separator);
+
+        let run = Run::new()
+            .fonts(
+                RunFonts::new()
+                    .ascii("CaskaydiaCove NF")
+                    .hi_ansi("CaskaydiaCove NF")
+                    .east_asia("CaskaydiaCove NF"),
+            )
+            .size(size)
+            .color(self.color.replace('#', ""))
+            .add_text(text);
+        docx_rs::Paragraph::new()
+            .align(AlignmentType::Right)
+            .add_run(run)
+    }
+}
+
+impl Default for Container {
+    fn default() -> Self {
+        Container {
+            enabled: default_false(),
+            background: default_container_background(),
+            border_color: default_container_border_color(),
+            border_size: default_container_border_size(),
+            padding: default_container_padding(),
+            label: default_container_label(),
+        }
+    }
+}
+
+impl Default for ImageLayout {
+    fn default() -> Self {
+        ImageLayout {
+            align: default_image_align(),
+            max_width: default_image_max_width(),
+            caption: default_image_caption(),
+            alt: default_image_alt(),
+            missing: default_image_missing(),
+        }
+    }
+}
+
+impl Default for RunListing {
+    fn default() -> Self {
+        RunListing {
+            title: default_run_title(),
+            input: default_run_input(),
+        }
+    }
+}
+
+impl FileOrder {
+    pub fn sort(&self, files: &mut [SourceFile]) {
+        match self {
+            FileOrder::AsListed => {}
+            FileOrder::Alphabetical => files.sort_by(|a, b| a.filename.cmp(&b.filename)),
+            FileOrder::HeadersFirst => files.sort_by_key(|file| {
+                let path = Path::new(&file.filename);
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+                if matches!(extension, "h" | "hh" | "hpp" | "hxx") {
+                    0
+                } else if stem == "main" {
+                    2
+                } else {
+                    1
+                }
+            }),
+        }
+    }
+}
+
+impl Default for Headings {
+    fn default() -> Self {
+        Headings {
+            enabled: default_true(),
+            word_numbering: default_false(),
+            bookmark: default_heading_bookmark(),
+        }
+    }
+}
+
+impl Default for TextDefaults {
+    fn default() -> Self {
+        TextDefaults {
+            font: default_font(),
+            size: default_size(),
+            line_spacing: default_line_spacing(),
+            margin_top: default_zero(),
+            margin_bottom: default_zero(),
+        }
+    }
+}
+
+impl Default for StyleBorder {
+    fn default() -> Self {
+        StyleBorder {
+            color: default_color(),
+            size: default_container_border_size(),
+            space: default_border_space(),
+        }
+    }
+}
+
+impl Default for Paragraph {
+    fn default() -> Self {
+        Paragraph {
+            size: None,
+            text: String::new(),
+            align: None,
+            bold: None,
+            italic: None,
+            underline: None,
+            font: None,
+            color: None,
+            line_spacing: None,
+            margin_top: None,
+            margin_bottom: None,
+            indent: None,
+            style: default_style(),
+            character_style: None,
+        }
+    }
+}
+
+impl Default for DocumentConfig {
+    /// The layout from the README: a numbered header, the question, and
+    /// titled code and output sections.
+    fn default() -> Self {
+        let title = |text: &str| Paragraph {
+            size: Some(14),
+            text: text.to_string(),
+            bold: Some(true),
+            underline: Some(true),
+            ..Default::default()
+        };
+
+        DocumentConfig {
+            header: Some(Paragraph {
+                size: Some(14),
+                text: "Task {n}".to_string(),
+                align: Some("center".to_string()),
+                bold: Some(true),
+                ..Default::default()
+            }),
+            question: Paragraph {
+                size: Some(17),
+                text: "Q) {question}".to_string(),
+                bold: Some(true),
+                ..Default::default()
+            },
+            solution: SectionWithTitle {
+                content: Paragraph {
+                    text: "{solution}".to_string(),
+                    ..Default::default()
+                },
+                title: title("Code:"),
+                files: FileListing::default(),
+                line_numbers: LineNumbers::default(),
+                container: Container::default(),
+                lines: LineLayout::default(),
+                runs: RunListing::default(),
+                user_input: default_user_input(),
+                status: StatusLine::default(),
+                stderr: StderrBlock::default(),
+                check: OutputCheck::default(),
+                screenshot: Screenshot::default(),
+            },
+            output: SectionWithTitle {
+                content: Paragraph {
+                    text: "{output}".to_string(),
+                    ..Default::default()
+                },
+                title: title("Output:"),
+                files: FileListing::default(),
+                line_numbers: LineNumbers::default(),
+                container: Container::default(),
+                lines: LineLayout::default(),
+                runs: RunListing::default(),
+                user_input: default_user_input(),
+                status: StatusLine::default(),
+                stderr: StderrBlock::default(),
+                check: OutputCheck::default(),
+                screenshot: Screenshot::default(),
+            },
+            footer: None,
+            images: ImageLayout::default(),
+            check_summary: CheckSummary::default(),
+            headings: Headings::default(),
+            toc: Contents::default(),
+            units: Units::default(),
+            numbering: NumberingFormat::default(),
+            fail_on_error: false,
+            variables: BTreeMap::new(),
+            reference_docx: None,
+            defaults: TextDefaults::default(),
+            styles: BTreeMap::new(),
+        }
+    }
+}
+
+/// Pack a document into a `.docx` file and write it to `writer`.
+pub fn write_docx<W: Write>(docx: docx_rs::Docx, writer: W) -> Result<(), Error> {
+    pack(docx.build(), writer)
+}
+
+fn pack<W: Write>(xml: XMLDocx, mut writer: W) -> Result<(), Error> {
+    let mut buffer = Cursor::new(Vec::new());
+    xml.pack(&mut buffer).map_err(DocxError::from)?;
+    writer.write_all(buffer.get_ref())?;
+    Ok(())
+}
+
+/// `styles.xml` with only the last definition of each style. docx-rs always
+/// writes an empty `Normal` style first, which would hide `[styles.Normal]`.
+fn drop_shadowed_styles(styles: &[u8]) -> Vec<u8> {
+    let styles = String::from_utf8_lossy(styles);
+    let definitions = crate::package::element_spans(&styles, "w:style");
+    let id = |style: &Range<usize>| crate::package::attribute(&styles[style.clone()], "w:styleId");
+    let mut kept = String::with_capacity(styles.len());
+    let mut position = 0;
+    for (i, style) in definitions.iter().enumerate() {
+        let redefined = id(style).is_some_and(|own| {
+            definitions[i + 1..]
+                .iter()
+                .any(|later| id(later) == Some(own))
+        });
+        if redefined {
+            kept.push_str(&styles[position..style.start]);
+            position = style.end;
+        }
+    }
+    kept.push_str(&styles[position..]);
+    kept.into_bytes()
+}
+
+/// `numbering.xml` with the `<w:suff>` of each level before its
+/// `<w:lvlText>`, where Word expects it. docx-rs writes it last.
+fn order_level_suffixes(numbering: &[u8]) -> Vec<u8> {
+    let numbering = String::from_utf8_lossy(numbering);
+    let mut ordered = String::with_capacity(numbering.len());
+    let mut rest = numbering.as_ref();
+    while let Some(end) = rest.find("</w:lvl>") {
+        let level = &rest[..end];
+        match (level.rfind("<w:suff "), level.find("<w:lvlText ")) {
+            (Some(suffix), Some(text)) if suffix > text => {
+                let suffix_end = suffix + level[suffix..].find("/>").map_or(0, |end| end + 2);
+                ordered.push_str(&level[..text]);
+                ordered.push_str(&level[suffix..suffix_end]);
+                ordered.push_str(&level[text..suffix]);
+                ordered.push_str(&level[suffix_end..]);
+            }
+            _ => ordered.push_str(level),
+        }
+        ordered.push_str("</w:lvl>");
+        rest = &rest[end + "</w:lvl>".len()..];
+    }
+    ordered.push_str(rest);
+    ordered.into_bytes()
+}
+
+/// Give every picture in `document.xml` its own id and, in order, the alt
+/// text in `descriptions`. docx-rs writes the same placeholder properties for
+/// every picture and has no way to set a description.
+fn describe_pictures(document: &[u8], descriptions: &[String]) -> Vec<u8> {
+    const PLACEHOLDER: &str = r#"<wp:docPr id="1" name="Figure" />"#;
+    let document = String::from_utf8_lossy(document);
+    let mut described = String::with_capacity(document.len());
+    let mut rest = document.as_ref();
+    let mut count = 0;
+    while let Some(start) = rest.find(PLACEHOLDER) {
+        described.push_str(&rest[..start]);
+        rest = &rest[start + PLACEHOLDER.len()..];
+
+        let description = descriptions
+            .get(count)
+            .map(|description| {
+                let escaped = description
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+                    .replace('"', "&quot;")
+                    .replace('\n', "&#10;");
+                format!(r#" descr="{}""#, escaped)
+            })
+            .unwrap_or_default();
+        count += 1;
+        described.push_str(&format!(
+            r#"<wp:docPr id="{count}" name="Picture {count}"{description} />"#
+        ));
+    }
+    described.push_str(rest);
+    described.into_bytes()
+}
+
+/// Give every paragraph in `document.xml` its own `w14:paraId`. docx-rs
+/// numbers paragraphs as they are created, so practicals taken from the
+/// cache reuse the ids of the build that rendered them.
+fn number_paragraphs(document: &[u8]) -> Vec<u8> {
+    const ATTRIBUTE: &str = r#"w14:paraId=""#;
+    let document = String::from_utf8_lossy(document);
+    let mut numbered = String::with_capacity(document.len());
+    let mut rest = document.as_ref();
+    let mut count = 0;
+    while let Some(start) = rest.find(ATTRIBUTE) {
+        let value = start + ATTRIBUTE.len();
+        let Some(end) = rest[value..].find('"') else {
+            break;
+        };
+        count += 1;
+        numbered.push_str(&rest[..value]);
+        numbered.push_str(&format!("{:08x}", count));
+        rest = &rest[value + end..];
+    }
+    numbered.push_str(rest);
+    numbered.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The paragraphs of the document `builder` assembles.
+    fn paragraphs(builder: DocumentBuilder) -> Vec<String> {
+        let xml = builder.finish().build();
+        String::from_utf8(xml.document)
+            .unwrap()
+            .split("</w:p>")
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn position(paragraphs: &[String], text: &str) -> usize {
+        paragraphs
+            .iter()
+            .position(|p| p.contains(&format!(">{}<", text)))
+            .unwrap_or_else(|| panic!("no paragraph reads {:?}", text))
+    }
+
+    #[test]
+    fn units_follow_index_order_not_arrival_order() {
+        let mut config = DocumentConfig::default();
+        config.headings.enabled = true;
+        let mut builder = DocumentBuilder::new(&config);
+        builder.add_entry(&Entry::new(1, "Reverse an array", ".py", ""));
+        builder.add_entry(&Entry::new(0, "Sum an array", ".py", "").with_unit(Unit::new("Arrays")));
+        builder.render();
+
+        let units: Vec<_> = builder
+            .practicals
+            .iter()
+            .map(|(entry, ..)| entry.unit.clone().unwrap())
+            .collect();
+        assert_eq!(units[1].title, "Arrays");
+        assert_eq!((units[1].number, units[1].position), (Some(1), Some(1)));
+
+        let paragraphs = paragraphs(builder);
+        let unit = position(&paragraphs, "Unit 1: Arrays");
+        let task = position(&paragraphs, "Task 2");
+        assert!(unit < task);
+        assert!(paragraphs[task].contains(r#"<w:pStyle w:val="Heading2" />"#));
+    }
+
+    /// A PNG `width` pixels wide. docx-rs stores identical pictures once.
+    fn png(width: u32) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&vec![0; 4 * width as usize]))
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn pictures_get_ids_and_descriptions() {
+        let mut doc = Docx::new();
+        for width in [1, 2] {
+            doc = doc.add_paragraph(
+                docx_rs::Paragraph::new().add_run(Run::new().add_image(Pic::new(&png(width)))),
+            );
+        }
+        let document = doc.build().document;
+        let placeholder = r#"<wp:docPr id="1" name="Figure" />"#;
+        let written = String::from_utf8_lossy(&document);
+        assert_eq!(written.matches(placeholder).count(), 2);
+
+        let descriptions = ["a <b> & \"c\"".to_string(), "two\nlines".to_string()];
+        let described = String::from_utf8(describe_pictures(&document, &descriptions)).unwrap();
+        assert!(!described.contains(placeholder));
+        assert!(described.contains(
+            r#"<wp:docPr id="1" name="Picture 1" descr="a &lt;b&gt; &amp; &quot;c&quot;" />"#
+        ));
+        assert!(described.contains(r#"<wp:docPr id="2" name="Picture 2" descr="two&#10;lines" />"#));
+    }
+
+    #[test]
+    fn paragraphs_are_numbered_in_order() {
+        let mut doc = Docx::new();
+        for text in ["one", "two", "three"] {
+            doc = doc.add_paragraph(docx_rs::Paragraph::new().add_run(Run::new().add_text(text)));
+        }
+        let document = doc.build().document;
+        let numbered = String::from_utf8(number_paragraphs(&document)).unwrap();
+        let ids: Vec<&str> = numbered
+            .split(r#"w14:paraId=""#)
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .collect();
+        assert_eq!(ids, ["00000001", "00000002", "00000003"]);
+        assert_eq!(numbered.len(), document.len());
+    }
+
+    #[test]
+    fn level_suffixes_come_before_level_text() {
+        let mut config = DocumentConfig::default();
+        config.headings.enabled = true;
+        config.headings.word_numbering = true;
+        let numberings = config.base_document(0).build().numberings;
+        // The heading level is the one with a suffix
+        let suffix_after_text = |numbering: &str| {
+            let level = crate::package::elements(numbering, "w:lvl")
+                .into_iter()
+                .find(|level| level.contains("<w:suff "))
+                .unwrap();
+            level.find("<w:suff ").unwrap() > level.find("<w:lvlText ").unwrap()
+        };
+        let written = String::from_utf8_lossy(&numberings);
+        assert!(suffix_after_text(&written));
+
+        let ordered = String::from_utf8(order_level_suffixes(&numberings)).unwrap();
+        assert!(!suffix_after_text(&ordered));
+        assert_eq!(ordered.len(), written.len());
+    }
+
+    #[test]
+    fn shadowed_styles_are_dropped() {
+        let styles = Docx::new()
+            .add_style(Style::new("Normal", StyleType::Paragraph).size(30))
+            .build()
+            .styles;
+        let written = String::from_utf8_lossy(&styles);
+        assert_eq!(written.matches(r#"w:styleId="Normal""#).count(), 2);
+
+        let kept = String::from_utf8(drop_shadowed_styles(&styles)).unwrap();
+        let normal = crate::package::elements(&kept, "w:style")
+            .into_iter()
+            .filter(|style| style.contains(r#"w:styleId="Normal""#))
+            .collect::<Vec<_>>();
+        assert_eq!(normal.len(), 1);
+        assert!(normal[0].contains(r#"<w:sz w:val="30" />"#));
+    }
+
+    #[test]
+    fn failed_screenshots_are_reported_and_printed_as_text() {
+        let mut config = DocumentConfig::default();
+        config.output.screenshot.enabled = true;
+        config.output.screenshot.font = Some(PathBuf::from("missing-font.ttf"));
+        let mut builder = DocumentBuilder::new(&config);
+        builder.add_entry(
+            &Entry::new(0, "Print a greeting", ".py", "")
+                .with_output(crate::Content::text("hello")),
+        );
+        builder.render();
+
+        assert_eq!(builder.failed_screenshots().len(), 1);
+        assert!(builder.failed_screenshots()[0].starts_with("practical 1 (run 1: "));
+        let paragraphs = paragraphs(builder);
+        position(&paragraphs, "hello");
+    }
+
+    /// The XML of the table `section` lays `count` lines out in, in
+    /// `text_width` twips.
+    fn listing_table(
+        section: &SectionWithTitle,
+        count: usize,
+        numbered: bool,
+        text_width: usize,
+    ) -> String {
+        let lines = (0..count)
+            .map(|_| docx_rs::Paragraph::new().add_run(Run::new().add_text("x = 1")))
+            .collect();
+        let entry = Entry::new(0, "Set x", ".py", "x = 1");
+        let mut blocks = section.listing(lines, 20, numbered, "python", &entry, text_width);
+        assert_eq!(blocks.len(), 1);
+        match blocks.remove(0) {
+            Block::Table(table) => {
+                String::from_utf8(Docx::new().add_table(*table).build().document).unwrap()
+            }
+            _ => panic!("the listing is not a table"),
+        }
+    }
+
+    fn grid(table: &str) -> Vec<usize> {
+        table
+            .split(r#"<w:gridCol w:w=""#)
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next()?.parse().ok())
+            .collect()
+    }
+
+    fn container_config(padding: usize) -> Result<DocumentConfig, Error> {
+        DocumentConfig::from_toml(&format!(
+            r#"
+            question.text = "{{question}}"
+            solution.text = "{{solution}}"
+            solution.title.text = "Code:"
+            output.text = "{{output}}"
+            output.title.text = "Output:"
+
+            [solution.container]
+            enabled = true
+            padding = {}
+            label.text = ""
+            "#,
+            padding
+        ))
+    }
+
+    #[test]
+    fn container_spans_the_text_width() {
+        let config = container_config(120).unwrap();
+        let table = listing_table(&config.solution, 3, false, 9000);
+        assert_eq!(grid(&table), [9000]);
+        assert!(table.contains(r#"w:fill="eff1f5""#));
+        assert_eq!(table.matches("<w:tr>").count(), 1);
+        // The padding goes before the first line and after the last
+        assert!(table.contains(r#"w:before="120""#));
+        assert!(table.contains(r#"w:after="120""#));
+    }
+
+    #[test]
+    fn numbered_container_splits_off_the_gutter() {
+        let mut config = container_config(120).unwrap();
+        config.solution.line_numbers.enabled = true;
+        let table = listing_table(&config.solution, 3, true, 9000);
+        let gutter = config.solution.line_numbers.gutter_width(3, 20);
+        assert_eq!(grid(&table), [gutter, 9000 - gutter]);
+        assert_eq!(table.matches("<w:tr>").count(), 3);
+    }
+
+    #[test]
+    fn gutter_and_padding_fit_a_narrow_page() {
+        let mut config = container_config(120).unwrap();
+        config.solution.line_numbers.enabled = true;
+        config.solution.line_numbers.start = usize::MAX / 2;
+        let table = listing_table(&config.solution, 3, true, 400);
+        assert_eq!(grid(&table), [200, 200]);
+
+        // Wider padding than the cell is left with still lays out
+        config.solution.container.padding = 150;
+        listing_table(&config.solution, 3, true, 400);
+
+        assert!(matches!(
+            container_config(TEXT_WIDTH / 2),
+            Err(Error::Container(_))
+        ));
+    }
+
+    #[test]
+    fn summary_lists_checked_practicals_after_the_last() {
+        let config = DocumentConfig::default();
+        let mut builder = DocumentBuilder::new(&config);
+        let practical = |index, output: &str, expected: &str| {
+            Entry::new(index, "Print a greeting", ".py", "")
+                .with_output(crate::Content::text(output))
+                .with_expected(crate::ExpectedOutput::text(expected))
+        };
+        builder.add_entry(&practical(2, "hello", "goodbye"));
+        builder.add_entry(&practical(0, "hello\n", "hello"));
+        builder.add_entry(&Entry::new(1, "Read a number", ".py", ""));
+
+        let paragraphs = paragraphs(builder);
+        let title = position(&paragraphs, "Output checks");
+        let last = paragraphs
+            .iter()
+            .rposition(|p| p.contains("Print a greeting"))
+            .unwrap();
+        assert!(last < title);
+        let lines: Vec<_> = ["Practical 1: passed", "Practical 3: failed"]
+            .iter()
+            .map(|line| position(&paragraphs, line))
+            .collect();
+        assert!(title < lines[0] && lines[0] < lines[1]);
+        assert!(!paragraphs.iter().any(|p| p.contains("Practical 2: ")));
+        let total = position(&paragraphs, "1 of 2 practicals match the expected output");
+        assert!(lines[1] < total);
+    }
+
+    #[test]
+    fn unit_zero_prints_as_zero() {
+        let entry =
+            Entry::new(0, "Install Python", ".py", "").with_unit(Unit::new("Setup").with_number(0));
+        let paragraph = Paragraph {
+            text: "{unit|upper-roman}. {unit_title}, {unit}.{n_in_unit}".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(paragraph.replace_text(&entry), "0. Setup, 0.1");
+    }
+
+    #[test]
+    fn unit_labels_are_dropped_outside_units() {
+        let entry = Entry::new(2, "Reverse an array", ".py", "");
+        let replaced = |text: &str| {
+            Paragraph {
+                text: text.to_string(),
+                ..Default::default()
+            }
+            .replace_text(&entry)
+        };
+        assert_eq!(replaced("Unit {unit}: Practical {n}"), "Practical 3");
+        assert_eq!(replaced("Practical {n} (Unit {unit})"), "Practical 3");
+        assert_eq!(
+            replaced("Practical {n}, Unit {unit|upper-roman}"),
+            "Practical 3"
+        );
+        assert_eq!(replaced("Practical {unit}.{n_in_unit}"), "Practical 3");
+        assert_eq!(replaced("{unit_title} - Practical {n}"), "Practical 3");
+        assert_eq!(
+            replaced("Unit {unit}: {unit_title}\n{question}"),
+            "\nReverse an array"
+        );
+    }
+
+    #[test]
+    fn entries_render_as_they_arrive() {
+        let dir =
+            std::env::temp_dir().join(format!("create-docx-arrival-test-{}", std::process::id()));
+        let config = DocumentConfig::default();
+        let mut builder = DocumentBuilder::new(&config).with_cache(Cache::open(&dir).unwrap());
+        let practical = |index| Entry::new(index, "Sum an array", ".py", "");
+        builder.add_entry(&practical(0).with_unit(Unit::new("Arrays")));
+        builder.add_entry(&practical(1));
+        assert_eq!(builder.keys().len(), 2);
+
+        // Practical 4 moves down its unit once practical 3 arrives, the
+        // ones before practical 3 stay as they are
+        builder.add_entry(&practical(3));
+        let before = builder.keys();
+        builder.add_entry(&practical(2));
+        let after = builder.keys();
+        assert_eq!(before[&0], after[&0]);
+        assert_eq!(before[&1], after[&1]);
+        assert_ne!(before[&3], after[&3]);
+        let position = |index: usize| {
+            let (entry, ..) = &builder.practicals[index];
+            entry.unit.as_ref().unwrap().position
+        };
+        assert_eq!(position(3), Some(3));
+
+        let report = builder.cache().unwrap().report();
+        assert_eq!(report.rebuilt, [0, 1, 2, 3]);
+        assert_eq!(builder.cache().unwrap().prune().unwrap(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn practical_headings_are_numbered_and_bookmarked() {
+        let mut config = DocumentConfig::default();
+        config.headings.word_numbering = true;
+        config.styles.insert(
+            "Aim".to_string(),
+            StyleDefinition {
+                outline_level: Some(2),
+                ..Default::default()
+            },
+        );
+        config.question.style = "Aim".to_string();
+        let mut builder = DocumentBuilder::new(&config);
+        builder.add_entry(&Entry::new(2, "Reverse an array", ".py", ""));
+        let xml = builder.finish().build();
+        let document = String::from_utf8(xml.document).unwrap();
+
+        let header = document
+            .split("</w:p>")
+            .find(|p| p.contains(r#"<w:pStyle w:val="Heading1" />"#))
+            .unwrap();
+        assert!(header.contains(&format!(
+            r#"<w:numPr><w:numId w:val="{}" /><w:ilvl w:val="0" /></w:numPr>"#,
+            HEADING_NUMBERING
+        )));
+        // Word's list prints "Task 3", so the text leaves the number out
+        assert!(!header.contains(">Task"));
+        assert!(header.contains(r#"<w:bookmarkStart w:id="2" w:name="practical_3" />"#));
+        assert!(header.contains(r#"<w:bookmarkEnd w:id="2" />"#));
+        assert!(document.contains(r#"<w:pStyle w:val="Aim" />"#));
+
+        let styles = String::from_utf8(xml.styles).unwrap();
+        let outline = |id: &str| {
+            let style = crate::package::elements(&styles, "w:style")
+                .into_iter()
+                .find(|style| style.contains(&format!(r#"w:styleId="{}""#, id)))
+                .unwrap();
+            style
+                .split(r#"<w:outlineLvl w:val=""#)
+                .nth(1)
+                .and_then(|rest| rest.split('"').next()?.parse::<usize>().ok())
+        };
+        assert_eq!(outline("Heading1"), Some(0));
+        assert_eq!(outline("Heading2"), Some(1));
+        assert_eq!(outline("Aim"), Some(1));
+    }
+
+    #[test]
+    fn word_numbering_rejects_unknown_filters() {
+        let toml = |text: &str| {
+            format!(
+                r#"
+                header.text = "{}"
+                question.text = "{{question}}"
+                solution.text = "{{solution}}"
+                solution.title.text = "Code:"
+                output.text = "{{output}}"
+                output.title.text = "Output:"
+                headings.word_numbering = true
+                "#,
+                text
+            )
+        };
+        assert!(DocumentConfig::from_toml(&toml("Practical {n|upper-roman}")).is_ok());
+        assert!(matches!(
+            DocumentConfig::from_toml(&toml("Practical {n|greek}")),
+            Err(Error::Headings(_))
+        ));
+    }
+
+    #[test]
+    fn contents_is_a_field_word_updates() {
+        let mut config = DocumentConfig::default();
+        config.toc.show = true;
+        let entries = [
+            Entry::new(0, "Sum an array", ".py", ""),
+            Entry::new(1, "Reverse an array", ".py", ""),
+        ];
+        let mut docx = Vec::new();
+        config.write_docx(&entries, &mut docx).unwrap();
+        let package = crate::package::Package::read(&docx).unwrap();
+        let document = package.text("word/document.xml").unwrap();
+        let settings = package.text("word/settings.xml").unwrap();
+        let begin = document
+            .find(r#"<w:fldChar w:fldCharType="begin" w:dirty="true" />"#)
+            .unwrap();
+        let instruction = document
+            .find(r#"<w:instrText>TOC \o &quot;1-2&quot; \h \u \z</w:instrText>"#)
+            .unwrap();
+        let separate = document
+            .find(r#"<w:fldChar w:fldCharType="separate""#)
+            .unwrap();
+        let end = document.find(r#"<w:fldChar w:fldCharType="end""#).unwrap();
+        assert!(begin < instruction && instruction < separate && separate < end);
+        // Until Word updates the field, the listing links to each practical.
+        let mut position = separate;
+        for (style, anchor, text) in [
+            ("TOC1", "practical_1", "Task 1"),
+            ("TOC2", "practical_1", "Q) Sum an array"),
+            ("TOC1", "practical_2", "Task 2"),
+            ("TOC2", "practical_2", "Q) Reverse an array"),
+        ] {
+            let hyperlink = format!(
+                r#"<w:hyperlink w:anchor="{anchor}" w:history="1"><w:r><w:rPr /><w:t xml:space="preserve">{text}</w:t>"#
+            );
+            let found = position + document[position..].find(&hyperlink).unwrap();
+            let paragraph = document[..found].rfind("<w:p ").unwrap();
+            let style = format!(r#"<w:pStyle w:val="{style}" />"#);
+            assert!(document[paragraph..found].contains(&style));
+            position = found + hyperlink.len();
+        }
+        assert!(position < end);
+        assert!(document.contains(r#"<w:bookmarkStart w:id="0" w:name="practical_1" />"#));
+        assert_eq!(settings.matches("<w:updateFields").count(), 1);
+        assert!(settings.contains(r#"<w:updateFields w:val="true" />"#));
+
+        config.toc.show = false;
+        let mut docx = Vec::new();
+        config.write_docx(&entries, &mut docx).unwrap();
+        let package = crate::package::Package::read(&docx).unwrap();
+        assert!(!package.text("word/document.xml").unwrap().contains("TOC"));
+        assert!(!package
+            .text("word/settings.xml")
+            .unwrap()
+            .contains("updateFields"));
+    }
+
+    #[test]
+    fn solution_files_are_captioned_in_order() {
+        let mut config = DocumentConfig::default();
+        config.solution.files.order = FileOrder::HeadersFirst;
+        let mut builder = DocumentBuilder::new(&config);
+        builder.add_entry(
+            &Entry::new(0, "Implement a stack", ".cpp", "")
+                .with_file(crate::SourceFile::new("main.cpp", "int main() {}"))
+                .with_file(crate::SourceFile::new("stack.cpp", "void push(int x);"))
+                .with_file(crate::SourceFile::new("stack.h", "struct Stack;")),
+        );
+
+        let paragraphs = paragraphs(builder);
+        let order = [
+            "File: stack.h",
+            "struct Stack;",
+            "File: stack.cpp",
+            "void push(int x);",
+            "File: main.cpp",
+            "int main() {}",
+        ]
+        .map(|text| position(&paragraphs, text));
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(position(&paragraphs, "Code:") < order[0]);
+        assert!(paragraphs[order[0]].contains("<w:b />"));
+    }
+
+    #[test]
+    fn runs_are_titled_with_their_input() {
+        let mut config = DocumentConfig::default();
+        config.output.runs.title.text = "Run {run}: {label}".to_string();
+        let mut builder = DocumentBuilder::new(&config);
+        builder.add_entry(
+            &Entry::new(0, "Divide two numbers", ".py", "")
+                .with_run(
+                    crate::ProgramRun::new("normal case", crate::Content::text("2"))
+                        .with_stdin("4 2"),
+                )
+                .with_run(crate::ProgramRun::new(
+                    "no input",
+                    crate::Content::text("usage"),
+                )),
+        );
+
+        let paragraphs = paragraphs(builder);
+        let order = [
+            "Output:",
+            "Run 1: normal case",
+            "Input: 4 2",
+            "2",
+            "Run 2: no input",
+            "usage",
+        ]
+        .map(|text| position(&paragraphs, text));
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(paragraphs[order[2]].contains("<w:i />"));
+        // A run fed nothing has no input line
+        let inputs = paragraphs.iter().filter(|p| p.contains(">Input:"));
+        assert_eq!(inputs.count(), 1);
+    }
+
+    #[test]
+    fn typed_input_is_styled_in_the_transcript() {
+        let mut config = DocumentConfig::default();
+        config.output.user_input.color = Some("#1e66f5".to_string());
+        let mut builder = DocumentBuilder::new(&config);
+        let session = crate::ProgramRun::new("", crate::Content::default())
+            .with_chunk(crate::Stream::Output, "Enter a number: ")
+            .with_chunk(crate::Stream::Input, "42\n")
+            .with_chunk(crate::Stream::Output, "Square = 1764\n");
+        builder.add_entry(&Entry::new(0, "Square a number", ".py", "").with_run(session));
+
+        let paragraphs = paragraphs(builder);
+        // The typed input follows the prompt on its line, in its own run
+        let prompt = position(&paragraphs, "Enter a number: ");
+        let runs: Vec<_> = paragraphs[prompt].split("</w:r>").collect();
+        let typed = runs.iter().position(|run| run.contains(">42<")).unwrap();
+        assert!(runs[typed - 1].contains(">Enter a number: <"));
+        assert!(runs[typed].contains(r#"<w:color w:val="1e66f5" />"#));
+        assert!(runs[typed].contains("<w:b />"));
+        assert!(!runs[typed - 1].contains("<w:b />"));
+        assert_eq!(position(&paragraphs, "Square = 1764"), prompt + 1);
+    }
+
+    #[test]
+    fn failed_programs_show_their_status_and_errors() {
+        let mut config = DocumentConfig::default();
+        config.output.status.paragraph.text = "Program {status} after {duration}".to_string();
+        let mut builder = DocumentBuilder::new(&config);
+        let entries = [
+            Entry::new(0, "Divide by zero", ".py", "")
+                .with_output(crate::Content::text("dividing"))
+                .with_stderr("ZeroDivisionError")
+                .with_exit_status(1)
+                .with_duration_ms(1200),
+            Entry::new(1, "Divide by two", ".py", "")
+                .with_output(crate::Content::text("2"))
+                .with_exit_status(0)
+                .with_duration_ms(35),
+        ];
+        for entry in &entries {
+            builder.add_entry(entry);
+        }
+        assert_eq!(builder.failures().len(), 1);
+        assert!(builder.check().is_ok());
+
+        let paragraphs = paragraphs(builder);
+        let order = [
+            "dividing",
+            "ZeroDivisionError",
+            "Program exited with code 1 after 1.20 s",
+        ]
+        .map(|text| position(&paragraphs, text));
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+        for line in &order[1..] {
+            assert!(paragraphs[*line].contains(r#"<w:color w:val="d20f39" />"#));
+        }
+        // Only failed programs get a status line by default
+        let statuses = paragraphs.iter().filter(|p| p.contains(">Program "));
+        assert_eq!(statuses.count(), 1);
+
+        config.fail_on_error = true;
+        let mut builder = DocumentBuilder::new(&config);
+        for entry in &entries {
+            builder.add_entry(entry);
+        }
+        match builder.check() {
+            Err(Error::ProgramsFailed(failures)) => assert_eq!(failures.len(), 1),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn line_numbers_sit_in_their_own_column() {
+        let mut config = DocumentConfig::default();
+        config.solution.line_numbers.enabled = true;
+        config.solution.line_numbers.start = 10;
+        config.solution.line_numbers.step = 2;
+        let mut builder = DocumentBuilder::new(&config);
+        builder.add_entry(&Entry::new(
+            0,
+            "Count to five",
+            ".py",
+            "a = 1\nb = 2\nc = 3\nd = 4\ne = 5",
+        ));
+        let xml = String::from_utf8(builder.finish().build().document).unwrap();
+
+        let text = |cell: &str| -> String {
+            cell.split(r#"<w:t xml:space="preserve">"#)
+                .skip(1)
+                .filter_map(|rest| rest.split("</w:t>").next())
+                .collect()
+        };
+        let rows: Vec<(String, String)> = xml
+            .split("<w:tr>")
+            .skip(1)
+            .map(|row| {
+                let row = row.split("</w:tr>").next().unwrap();
+                let cells: Vec<_> = row.split("<w:tc>").skip(1).collect();
+                assert_eq!(cells.len(), 2);
+                assert!(cells[0].contains(r#"<w:jc w:val="right" />"#));
+                assert!(cells[0].contains(r#"<w:color w:val="8c8fa1" />"#));
+                (text(cells[0]), text(cells[1]))
+            })
+            .collect();
+        let expected = [
+            ("10 \u{2502}", "a = 1"),
+            (" \u{2502}", "b = 2"),
+            ("12 \u{2502}", "c = 3"),
+            (" \u{2502}", "d = 4"),
+            ("14 \u{2502}", "e = 5"),
+        ]
+        .map(|(number, code)| (number.to_string(), code.to_string()));
+        assert_eq!(rows, expected);
+    }
 }
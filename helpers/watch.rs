@@ -0,0 +1,442 @@
+use crate::cli::{BuildArgs, WatchArgs};
+use helpers::numbering::NumberingFormat;
+use helpers::DocumentConfig;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Set to the watched folder for the `--regenerate` command. pfcreator runs
+/// create-docx once it has written the entries, which must then leave the
+/// build of that folder to the watching process instead of building and
+/// deleting the entries itself.
+pub const WATCHING: &str = "CREATE_DOCX_WATCHING";
+
+/// The files a build reads and writes, which tell the changes that call
+/// for a rebuild from the others.
+struct Watched {
+    folder: PathBuf,
+    config: PathBuf,
+    input: PathBuf,
+    reference: Option<PathBuf>,
+    output: PathBuf,
+    cache: PathBuf,
+    /// questions.txt and the source files it lists.
+    sources: BTreeSet<PathBuf>,
+}
+
+impl Watched {
+    /// Whether a change to `path` calls for a rebuild: `Some(true)` when
+    /// it is a source file and the programs must run again.
+    fn relevant(&self, path: &Path) -> Option<bool> {
+        if self.sources.contains(path) {
+            return Some(true);
+        }
+        let relevant = path == self.config
+            || path == self.input
+            || self.reference.as_deref() == Some(path)
+            || (path.parent() == Some(self.folder.as_path())
+                && path != self.output
+                && path.extension().is_none_or(|extension| extension != "docx")
+                && !path.starts_with(&self.cache)
+                && !is_temporary(path));
+        relevant.then_some(false)
+    }
+
+    /// The folders holding the files, watched without their subfolders.
+    fn folders(&self) -> BTreeSet<&Path> {
+        [&self.config, &self.input]
+            .into_iter()
+            .chain(&self.reference)
+            .chain(&self.sources)
+            .filter_map(|path| path.parent())
+            .chain([self.folder.as_path()])
+            .collect()
+    }
+}
+
+/// What a batch of changes calls for.
+#[derive(Default)]
+struct Changes {
+    /// Names of the changed files, for the log.
+    names: BTreeSet<String>,
+    /// questions.txt or a source file changed, so the programs must run
+    /// again.
+    sources: bool,
+    /// Anything else in the folder changed, e.g. format.toml or an image.
+    other: bool,
+}
+
+pub fn watch(mut args: WatchArgs) -> Result<(), Box<dyn Error>> {
+    let build = &mut args.build;
+    if build.reads_stdin() {
+        let e = "watch cannot read the entries from stdin, see --input";
+        eprintln!("{}", e);
+        return Err(e.into());
+    }
+    // The entries are read again on every change
+    build.keep_intermediate = true;
+
+    let folder = build.folder().map_err(|e| {
+        eprintln!("Error getting directory path: {}", e);
+        e
+    })?;
+    let config = build.config_path(&folder)?;
+    let mut watched = Watched {
+        reference: reference_docx(&config),
+        output: output_path(build, &folder, &config)?,
+        input: build.input_path(&folder)?,
+        cache: build.cache_dir(&folder)?,
+        sources: source_files(&folder),
+        folder,
+        config,
+    };
+    let folder = watched.folder.clone();
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| {
+        eprintln!("Failed to watch {:?}: {}", folder, e);
+        e
+    })?;
+    let mut folders = BTreeSet::new();
+    for path in watched.folders() {
+        watcher
+            .watch(path, RecursiveMode::NonRecursive)
+            .map_err(|e| {
+                eprintln!("Failed to watch {:?}: {}", path, e);
+                e
+            })?;
+        folders.insert(path.to_path_buf());
+    }
+
+    let mut keys = BTreeMap::new();
+    let _ = crate::build(build, Some(&mut keys));
+    println!(
+        "Watching {} for changes, press Ctrl+C to stop",
+        folder.display()
+    );
+
+    let debounce = Duration::from_millis(args.debounce);
+    loop {
+        // Wait for a change, then for the changes to settle, e.g. while an
+        // editor saves several files
+        let mut paths = BTreeSet::new();
+        let mut next = receiver.recv().map(Some);
+        while let Ok(Some(event)) = next {
+            if let Ok(event) = event {
+                if !matches!(event.kind, EventKind::Access(_)) {
+                    paths.extend(event.paths);
+                }
+            }
+            next = match receiver.recv_timeout(debounce) {
+                Ok(event) => Ok(Some(event)),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => Err(mpsc::RecvError),
+            };
+        }
+        if next.is_err() {
+            return Ok(());
+        }
+
+        // The output name may come from [variables], which may have changed,
+        // and keeps its last name when it cannot be named
+        if let Ok(path) = output_path(build, &folder, &watched.config) {
+            watched.output = path;
+        }
+        watched.sources = source_files(&folder);
+        watched.reference = reference_docx(&watched.config);
+        // questions.txt may list files in folders not watched yet
+        for path in watched.folders() {
+            if !folders.contains(path) {
+                match watcher.watch(path, RecursiveMode::NonRecursive) {
+                    Ok(()) => {
+                        folders.insert(path.to_path_buf());
+                    }
+                    Err(e) => eprintln!("Failed to watch {:?}: {}", path, e),
+                }
+            }
+        }
+        let mut changes = Changes::default();
+        for path in &paths {
+            let Some(source) = watched.relevant(path) else {
+                continue;
+            };
+            if source {
+                changes.sources = true;
+            } else {
+                changes.other = true;
+            }
+            if let Some(name) = path.file_name() {
+                changes.names.insert(name.to_string_lossy().into_owned());
+            }
+        }
+        if changes.names.is_empty() {
+            continue;
+        }
+
+        let names = changes.names.into_iter().collect::<Vec<_>>().join(", ");
+        println!();
+        match (&args.regenerate, changes.sources) {
+            (Some(command), true) => {
+                println!("{} changed, running `{}`", names, command);
+                if let Err(e) = regenerate(command, &folder) {
+                    eprintln!("Failed to regenerate the entries: {}", e);
+                    continue;
+                }
+            }
+            (None, true) if !changes.other => {
+                println!(
+                    "{} changed; pass --regenerate to run the programs again, e.g. --regenerate \"pfcreator cpp .\"",
+                    names
+                );
+                continue;
+            }
+            _ => println!("{} changed, rebuilding", names),
+        }
+
+        let previous = std::mem::take(&mut keys);
+        if crate::build(build, Some(&mut keys)).is_err() {
+            // Compare the next successful build with the last one
+            keys = previous;
+            println!("Build failed, waiting for changes");
+        } else {
+            // The build loaded the config, so it loads again
+            let numbering = DocumentConfig::load(&watched.config)
+                .map(|config| config.numbering)
+                .unwrap_or_default();
+            println!("{}", describe_changes(&previous, &keys, &numbering));
+        }
+
+        // Skip the changes made by the build and the regenerate command,
+        // which may still be arriving, until they have had as long to
+        // settle as the user's changes
+        let deadline = Instant::now() + debounce;
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            if let Err(RecvTimeoutError::Disconnected) = receiver.recv_timeout(timeout) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Path of the record the build writes, named with the `[variables]` of the
+/// config at `config` and `--var`.
+fn output_path(build: &BuildArgs, folder: &Path, config: &Path) -> Result<PathBuf, String> {
+    let variables = match DocumentConfig::load(config) {
+        Ok(config) => config.variables,
+        Err(e) => {
+            let e = format!("Cannot name the output: {}", e);
+            eprintln!("{}", e);
+            return Err(e);
+        }
+    };
+    build.output_path(folder, &variables).map_err(|e| {
+        let e = format!("Invalid output path: {}", e);
+        eprintln!("{}", e);
+        e
+    })
+}
+
+/// Paths of questions.txt and of the source files it lists after `::`.
+fn source_files(folder: &Path) -> BTreeSet<PathBuf> {
+    let questions = folder.join("questions.txt");
+    let text = std::fs::read_to_string(&questions).unwrap_or_default();
+    let mut files = listed_files(folder, &text);
+    files.insert(questions);
+    files
+}
+
+/// Paths of the source files `questions` lists after `::`, in `folder`.
+fn listed_files(folder: &Path, questions: &str) -> BTreeSet<PathBuf> {
+    questions
+        .split("\n---\n")
+        .filter_map(|question| question.split_once("::"))
+        // Without `./`, so that they match the paths of the events
+        .map(|(_, file)| {
+            folder
+                .join(file.trim())
+                .components()
+                .filter(|component| *component != Component::CurDir)
+                .collect()
+        })
+        .collect()
+}
+
+/// Path of the `reference_docx` of the config, whose changes are not
+/// skipped along with those of other .docx files.
+fn reference_docx(config: &Path) -> Option<PathBuf> {
+    DocumentConfig::load(config).ok()?.reference_docx
+}
+
+/// Swap, backup and lock files written by editors and Word.
+fn is_temporary(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    name.starts_with('.')
+        || name.starts_with("~$")
+        || name.ends_with('~')
+        || [".swp", ".swx", ".tmp"]
+            .iter()
+            .any(|extension| name.ends_with(extension))
+        || name.chars().all(|c| c.is_ascii_digit())
+}
+
+fn regenerate(command: &str, folder: &Path) -> Result<(), String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .current_dir(folder)
+        .env(WATCHING, folder)
+        .status()
+        .map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("`{}` exited with {}", command, status));
+    }
+    Ok(())
+}
+
+/// Which practicals differ between two builds, compared by their cache keys.
+fn describe_changes(
+    previous: &BTreeMap<usize, String>,
+    current: &BTreeMap<usize, String>,
+    numbering: &NumberingFormat,
+) -> String {
+    let list = |indexes: Vec<usize>| {
+        indexes
+            .iter()
+            .map(|index| numbering.label(*index))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let changed: Vec<usize> = current
+        .iter()
+        .filter(|(index, key)| previous.get(index).is_some_and(|old| old != *key))
+        .map(|(index, _)| *index)
+        .collect();
+    let added: Vec<usize> = current
+        .keys()
+        .filter(|index| !previous.contains_key(index))
+        .copied()
+        .collect();
+    let removed: Vec<usize> = previous
+        .keys()
+        .filter(|index| !current.contains_key(index))
+        .copied()
+        .collect();
+
+    let mut parts = Vec::new();
+    for (what, numbers) in [("changed", changed), ("added", added), ("removed", removed)] {
+        if !numbers.is_empty() {
+            parts.push(format!("{} practical {}", what, list(numbers)));
+        }
+    }
+    if parts.is_empty() {
+        return "No practicals changed".to_string();
+    }
+    let mut summary = parts.join(", ");
+    summary[..1].make_ascii_uppercase();
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helpers::numbering::NumberStyle;
+
+    fn watched() -> Watched {
+        let folder = PathBuf::from("/lab");
+        let mut sources = listed_files(
+            &folder,
+            "Reverse an array :: src/reverse.c\n---\nSum an array :: ./sum.c",
+        );
+        sources.insert(folder.join("questions.txt"));
+        Watched {
+            config: folder.join("format.toml"),
+            input: folder.join("output.json"),
+            reference: Some(PathBuf::from("/shared/department.docx")),
+            output: folder.join("21CS042.docx"),
+            cache: folder.join(".create-docx-cache"),
+            sources,
+            folder,
+        }
+    }
+
+    #[test]
+    fn source_files_in_subfolders_call_for_running_the_programs() {
+        let watched = watched();
+        for path in ["/lab/src/reverse.c", "/lab/sum.c", "/lab/questions.txt"] {
+            assert_eq!(watched.relevant(Path::new(path)), Some(true), "{}", path);
+        }
+        let folders = watched.folders();
+        assert!(folders.contains(Path::new("/lab/src")));
+        assert!(folders.contains(Path::new("/shared")));
+    }
+
+    #[test]
+    fn only_files_the_build_reads_call_for_a_rebuild() {
+        let watched = watched();
+        let rebuild = [
+            "/lab/format.toml",
+            "/lab/output.json",
+            "/lab/diagram.png",
+            "/shared/department.docx",
+        ];
+        for path in rebuild {
+            assert_eq!(watched.relevant(Path::new(path)), Some(false), "{}", path);
+        }
+        let ignored = [
+            "/lab/21CS042.docx",
+            "/lab/old.docx",
+            "/lab/.format.toml.swp",
+            "/lab/~$21CS042.docx",
+            "/lab/src/notes.txt",
+            "/lab/.create-docx-cache/0123",
+            "/shared/logo.png",
+        ];
+        for path in ignored {
+            assert_eq!(watched.relevant(Path::new(path)), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn changes_are_described_by_practical_number() {
+        let keys = |pairs: &[(usize, &str)]| {
+            pairs
+                .iter()
+                .map(|(index, key)| (*index, key.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let previous = keys(&[(0, "a"), (1, "b"), (2, "c")]);
+        let current = keys(&[(0, "a"), (1, "B"), (3, "d")]);
+        let numbering = NumberingFormat::default();
+        assert_eq!(
+            describe_changes(&previous, &current, &numbering),
+            "Changed practical 2, added practical 4, removed practical 3"
+        );
+        assert_eq!(
+            describe_changes(&previous, &previous, &numbering),
+            "No practicals changed"
+        );
+
+        let roman = NumberingFormat {
+            style: NumberStyle::LowerRoman,
+            ..Default::default()
+        };
+        assert_eq!(
+            describe_changes(&previous, &current, &roman),
+            "Changed practical ii, added practical iv, removed practical iii"
+        );
+    }
+}
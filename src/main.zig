@@ -282,7 +282,7 @@ pub fn main() !void {
         // Read the generated RTF output
         const output_rtf_content = std.fs.cwd().readFileAlloc(allocator, output_rtf_path, std.math.maxInt(usize)) catch |err| blk: {
             std.debug.print("Failed to read output RTF file: {} for {s}\n", .{ err, filename });
-            break :blk try allocator.dupe(u8, "{\\rtf1 Failed to capture output}");
+            break :blk try allocator.dupe(u8, "Failed to capture output");
         };
 
         // Clean up the executable for C++ files
@@ -327,22 +327,26 @@ pub fn main() !void {
 
         try writer.writeByte('\n');
         try writer.writeAll("  {");
-        try writer.writeAll("\n    \"question\": ");
+        try writer.writeAll("\n    \"version\": 1");
+        try writer.writeAll(",\n    \"question\": ");
         try std.json.stringify(item.question, .{}, writer);
         try writer.writeAll(",\n    \"index\": ");
         try std.json.stringify(item.entry.index, .{}, writer);
         try writer.writeAll(",\n    \"extension\": ");
         try std.json.stringify(item.entry.extension, .{}, writer);
-        try writer.writeAll(",\n    \"code\": ");
+        try writer.writeAll(",\n    \"code\": {\n      \"source\": ");
         try std.json.stringify(item.entry.code, .{}, writer);
-        try writer.writeAll(",\n    \"output_rtf\": ");
-        try std.json.stringify(item.entry.output_rtf, .{}, writer);
 
         if (item.entry.code_rtf) |code_rtf_content| {
-            try writer.writeAll(",\n    \"code_rtf\": ");
+            try writer.writeAll(",\n      \"highlighted\": { \"type\": \"rtf\", \"data\": ");
             try std.json.stringify(code_rtf_content, .{}, writer);
+            try writer.writeAll(" }");
         }
 
+        try writer.writeAll("\n    },\n    \"output\": { \"type\": \"ansi\", \"data\": ");
+        try std.json.stringify(item.entry.output_rtf, .{}, writer);
+        try writer.writeAll(" }");
+
         try writer.writeAll("\n  }");
     }
 
@@ -363,7 +367,7 @@ pub fn main() !void {
     else
         "/usr/local/bin/create-docx";
 
-    var rust_process = std.process.Child.init(&[_][]const u8{ rust_executable_path, dir_path }, allocator);
+    var rust_process = std.process.Child.init(&[_][]const u8{ rust_executable_path, full_dir_path }, allocator);
     rust_process.stderr_behavior = .Pipe;
     rust_process.stdout_behavior = .Pipe;
 